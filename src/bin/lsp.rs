@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as _, SemanticTokensFullRequest,
+};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse, Diagnostic,
+    DiagnosticSeverity, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse, GotoDefinitionParams,
+    GotoDefinitionResponse, Hover, HoverContents, HoverParams, HoverProviderCapability, Location, MarkupContent,
+    MarkupKind, OneOf, Position, PublishDiagnosticsParams, Range, SemanticToken, SemanticTokenModifier,
+    SemanticTokenType, SemanticTokens, SemanticTokensFullOptions, SemanticTokensLegend, SemanticTokensOptions,
+    SemanticTokensParams, SemanticTokensResult, SemanticTokensServerCapabilities, ServerCapabilities,
+    SymbolKind as LspSymbolKind, TextDocumentPositionParams, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+
+use modern_asm::analysis::{Analysis, CompletionKind, SymbolKind, TokenClass};
+use modern_asm::lexer::Span;
+
+/// Semantic token types, `TokenClass`es are sent as indices into it.
+const TOKEN_TYPES: &[SemanticTokenType] = &[
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::MACRO,
+    SemanticTokenType::TYPE,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::PARAMETER,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::PROPERTY,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::STRING,
+    SemanticTokenType::COMMENT,
+];
+
+/// Language server over stdio. Documents are synced whole and analyzed on every change.
+fn main() {
+    if let Err(e) = serve() {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn serve() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(SemanticTokensOptions {
+            legend: SemanticTokensLegend {
+                token_types: TOKEN_TYPES.to_vec(),
+                token_modifiers: vec![SemanticTokenModifier::READONLY],
+            },
+            full: Some(SemanticTokensFullOptions::Bool(true)),
+            ..Default::default()
+        })),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut documents = Documents::default();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                connection.sender.send(Message::Response(documents.respond(request)))?;
+            }
+            Message::Notification(notification) => {
+                if let Some(params) = documents.update(notification)? {
+                    let method = PublishDiagnostics::METHOD.to_string();
+                    connection.sender.send(Message::Notification(Notification::new(method, params)))?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+
+    // the writer thread finishes once the connection is gone
+    drop(connection);
+    io_threads.join()?;
+
+    Ok(())
+}
+
+/// Open documents with their text and analysis.
+#[derive(Default)]
+struct Documents {
+    open: HashMap<Url, (String, Analysis)>,
+}
+
+impl Documents {
+    /// Keeps track of opened, changed and closed documents, returning their new diagnostics.
+    fn update(&mut self, notification: Notification) -> Result<Option<PublishDiagnosticsParams>> {
+        let (uri, text) = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
+                (params.text_document.uri, Some(params.text_document.text))
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
+                match params.content_changes.into_iter().last() {
+                    Some(change) => (params.text_document.uri, Some(change.text)),
+                    None => return Ok(None),
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+                (params.text_document.uri, None)
+            }
+            _ => return Ok(None),
+        };
+
+        let text = match text {
+            Some(text) => text,
+            None => {
+                self.open.remove(&uri);
+                return Ok(Some(PublishDiagnosticsParams::new(uri, vec![], None)));
+            }
+        };
+
+        let analysis = Analysis::new(&text);
+        let diagnostics = analysis
+            .diagnostics
+            .iter()
+            .map(|d| Diagnostic {
+                range: Range::new(position(&text, d.span), line_end(&text, d.span.line)),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("modern_asm".to_string()),
+                message: d.message.clone(),
+                ..Default::default()
+            })
+            .collect();
+        self.open.insert(uri.clone(), (text, analysis));
+
+        Ok(Some(PublishDiagnosticsParams::new(uri, diagnostics, None)))
+    }
+
+    fn respond(&self, request: Request) -> Response {
+        let id = request.id.clone();
+        let result = match request.method.as_str() {
+            HoverRequest::METHOD => serde_json::from_value(request.params)
+                .map(|params| self.hover(params))
+                .and_then(serde_json::to_value),
+            GotoDefinition::METHOD => serde_json::from_value(request.params)
+                .map(|params| self.definition(params))
+                .and_then(serde_json::to_value),
+            Completion::METHOD => serde_json::from_value(request.params)
+                .map(|params| self.completion(params))
+                .and_then(serde_json::to_value),
+            DocumentSymbolRequest::METHOD => serde_json::from_value(request.params)
+                .map(|params| self.symbols(params))
+                .and_then(serde_json::to_value),
+            SemanticTokensFullRequest::METHOD => serde_json::from_value(request.params)
+                .map(|params| self.semantic_tokens(params))
+                .and_then(serde_json::to_value),
+            method => {
+                let message = format!("Unknown method {}", method);
+                return Response::new_err(id, ErrorCode::MethodNotFound as i32, message);
+            }
+        };
+
+        match result {
+            Ok(result) => Response::new_ok(id, result),
+            Err(e) => Response::new_err(id, ErrorCode::InvalidParams as i32, e.to_string()),
+        }
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let (text, analysis, span) = self.at(&params.text_document_position_params)?;
+        let (start, symbol) = analysis.reference_at(span)?;
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("```modern_asm\n{}\n```", symbol.detail),
+            }),
+            range: Some(name_range(text, start, &symbol.name)),
+        })
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let (text, analysis, span) = self.at(&params.text_document_position_params)?;
+        let symbol = analysis.symbol_at(span)?;
+        let uri = params.text_document_position_params.text_document.uri;
+
+        Some(GotoDefinitionResponse::Scalar(Location::new(uri, name_range(text, symbol.span, &symbol.name))))
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let (_, analysis, span) = self.at(&params.text_document_position)?;
+        let items = analysis
+            .completions(span.line)
+            .into_iter()
+            .map(|completion| CompletionItem {
+                label: completion.label,
+                kind: Some(match completion.kind {
+                    CompletionKind::Keyword => CompletionItemKind::KEYWORD,
+                    CompletionKind::Instruction => CompletionItemKind::OPERATOR,
+                    CompletionKind::Type => CompletionItemKind::TYPE_PARAMETER,
+                    CompletionKind::Builtin | CompletionKind::Symbol(SymbolKind::Function) => CompletionItemKind::FUNCTION,
+                    CompletionKind::Symbol(SymbolKind::Parameter | SymbolKind::Variable) => CompletionItemKind::VARIABLE,
+                    CompletionKind::Symbol(SymbolKind::Constant) => CompletionItemKind::CONSTANT,
+                    CompletionKind::Symbol(SymbolKind::Struct) => CompletionItemKind::STRUCT,
+                    CompletionKind::Symbol(SymbolKind::Enum) => CompletionItemKind::ENUM,
+                }),
+                detail: Some(completion.detail),
+                ..Default::default()
+            })
+            .collect();
+
+        Some(CompletionResponse::Array(items))
+    }
+
+    #[allow(deprecated)] // DocumentSymbol::deprecated has to be given
+    fn symbols(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let (text, analysis) = self.open.get(&params.text_document.uri)?;
+        let symbols = analysis
+            .outline()
+            .into_iter()
+            .map(|(symbol, end)| DocumentSymbol {
+                name: symbol.name.clone(),
+                detail: symbol.detail.lines().next().map(str::to_string),
+                kind: match symbol.kind {
+                    SymbolKind::Function => LspSymbolKind::FUNCTION,
+                    SymbolKind::Struct => LspSymbolKind::STRUCT,
+                    SymbolKind::Enum => LspSymbolKind::ENUM,
+                    SymbolKind::Constant => LspSymbolKind::CONSTANT,
+                    SymbolKind::Parameter | SymbolKind::Variable => LspSymbolKind::VARIABLE,
+                },
+                tags: None,
+                deprecated: None,
+                range: Range::new(Position::new(symbol.span.line - 1, 0), line_end(text, end)),
+                selection_range: name_range(text, symbol.span, &symbol.name),
+                children: None,
+            })
+            .collect();
+
+        Some(DocumentSymbolResponse::Nested(symbols))
+    }
+
+    /// Every classified token, split at line breaks and relative to the one before.
+    fn semantic_tokens(&self, params: SemanticTokensParams) -> Option<SemanticTokensResult> {
+        let (text, analysis) = self.open.get(&params.text_document.uri)?;
+
+        let mut data = vec![];
+        let mut previous = Position::new(0, 0);
+        for token in analysis.tokens() {
+            // constants are read-only variables
+            let (token_type, modifiers) = match token.class {
+                TokenClass::Keyword | TokenClass::Bool => (SemanticTokenType::KEYWORD, 0),
+                TokenClass::Instruction => (SemanticTokenType::MACRO, 0),
+                TokenClass::Type => (SemanticTokenType::TYPE, 0),
+                TokenClass::Function => (SemanticTokenType::FUNCTION, 0),
+                TokenClass::Parameter => (SemanticTokenType::PARAMETER, 0),
+                TokenClass::Variable => (SemanticTokenType::VARIABLE, 0),
+                TokenClass::Constant => (SemanticTokenType::VARIABLE, 1),
+                TokenClass::Field => (SemanticTokenType::PROPERTY, 0),
+                TokenClass::Number => (SemanticTokenType::NUMBER, 0),
+                TokenClass::String => (SemanticTokenType::STRING, 0),
+                TokenClass::Comment => (SemanticTokenType::COMMENT, 0),
+                TokenClass::Asm => continue,
+            };
+            let token_type = TOKEN_TYPES.iter().position(|t| *t == token_type).unwrap_or(0) as u32;
+
+            for line in token.start.line..=token.end.line {
+                let start = if line == token.start.line {
+                    position(text, token.start)
+                } else {
+                    Position::new(line - 1, 0)
+                };
+                let end = if line == token.end.line {
+                    position(text, token.end)
+                } else {
+                    line_end(text, line)
+                };
+                if end.character <= start.character {
+                    continue;
+                }
+
+                let delta_line = start.line - previous.line;
+                let delta_start = if delta_line == 0 { start.character - previous.character } else { start.character };
+                data.append(&mut vec![SemanticToken {
+                    delta_line,
+                    delta_start,
+                    length: end.character - start.character,
+                    token_type,
+                    token_modifiers_bitset: modifiers,
+                }]);
+                previous = start;
+            }
+        }
+
+        Some(SemanticTokensResult::Tokens(SemanticTokens { result_id: None, data }))
+    }
+
+    /// Document and position a request is about.
+    fn at(&self, params: &TextDocumentPositionParams) -> Option<(&str, &Analysis, Span)> {
+        let (text, analysis) = self.open.get(&params.text_document.uri)?;
+        Some((text, analysis, span(text, params.position)))
+    }
+}
+
+// ------------------------------------
+// Positions, LSP counts UTF-16 code units from 0 and spans count chars from 1
+
+fn line(text: &str, line: u32) -> &str {
+    text.split('\n').nth(line as usize).unwrap_or("")
+}
+
+fn span(text: &str, position: Position) -> Span {
+    let mut units = 0;
+    let mut column = 1;
+    for c in line(text, position.line).chars() {
+        if units >= position.character {
+            break;
+        }
+        units += c.len_utf16() as u32;
+        column += 1;
+    }
+
+    Span { line: position.line + 1, column }
+}
+
+fn position(text: &str, span: Span) -> Position {
+    let units = line(text, span.line - 1)
+        .chars()
+        .take(span.column as usize - 1)
+        .map(|c| c.len_utf16() as u32)
+        .sum();
+
+    Position::new(span.line - 1, units)
+}
+
+fn line_end(text: &str, line_number: u32) -> Position {
+    let units = line(text, line_number - 1).trim_end_matches('\r').encode_utf16().count();
+    Position::new(line_number - 1, units as u32)
+}
+
+fn name_range(text: &str, start: Span, name: &str) -> Range {
+    let end = Span { line: start.line, column: start.column + name.chars().count() as u32 };
+    Range::new(position(text, start), position(text, end))
+}
@@ -1,3 +1,256 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+
+use modern_asm::driver::{BuildOptions, Emit, OptLevel};
+
+const USAGE: &str = "usage: modern_asm build <file> [-o <output>|-] [-c] [--emit=exe|obj|asm|llvm-ir|llvm-bc] [-O0|-O1|-O2|-O3|-Os] [--header <file.h>] [--freestanding] [--[no-]bounds-checks] [-g]
+                        [--target <triple>] [--cpu <name>|native] [--target-features <+a,-b>] [--linker <cmd>]
+       modern_asm run <file> [--interp | --vm [--max-instructions <n>] [--max-memory <bytes>] [--max-call-depth <n>]] [--[no-]bounds-checks]
+       modern_asm bytecode <file> [-o <output>]
+       modern_asm disasm <file>
+       modern_asm repl
+       modern_asm fmt [--check] <file>...
+       modern_asm grammar [-o <output>]";
+
 fn main() {
-    println!("main");
+    if let Err(e) = run(std::env::args().skip(1).collect()) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Vec<String>) -> Result<()> {
+    match args.first().map(|a| a.as_str()) {
+        Some("build") => build(&parse_build_args(&args[1..])?),
+        Some("run") => {
+            // backend flags are handled here, every other flag goes to the build
+            let mut interp = false;
+            let mut vm = false;
+            let mut max_instructions = None;
+            let mut max_memory = None;
+            let mut max_call_depth = None;
+            let mut build_args = vec![];
+            let mut rest = args[1..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--interp" => interp = true,
+                    "--vm" => vm = true,
+                    "--max-instructions" => {
+                        let value = next_value(&mut rest, arg)?;
+                        let count = value.parse().map_err(|_| anyhow!("Invalid instruction count {}", value))?;
+                        max_instructions = Some(count);
+                    }
+                    "--max-memory" => {
+                        let value = next_value(&mut rest, arg)?;
+                        let bytes = value.parse().map_err(|_| anyhow!("Invalid memory size {}", value))?;
+                        max_memory = Some(bytes);
+                    }
+                    "--max-call-depth" => {
+                        let value = next_value(&mut rest, arg)?;
+                        let depth = value.parse().map_err(|_| anyhow!("Invalid call depth {}", value))?;
+                        max_call_depth = Some(depth);
+                    }
+                    _ => build_args.push(arg.clone()),
+                }
+            }
+            if interp && vm {
+                return Err(anyhow!("--interp and --vm cannot be combined"));
+            }
+            if (max_instructions.is_some() || max_memory.is_some() || max_call_depth.is_some()) && !vm {
+                return Err(anyhow!("--max-instructions, --max-memory and --max-call-depth need --vm"));
+            }
+            let options = parse_build_args(&build_args)?;
+
+            let code = if interp {
+                interpret(&options)?
+            } else if vm {
+                run_vm(&options, max_instructions, max_memory, max_call_depth)?
+            } else {
+                run_binary(&options)?
+            };
+            std::process::exit(code)
+        }
+        Some("bytecode") => write_bytecode(&parse_build_args(&args[1..])?),
+        Some("disasm") => disassemble(&parse_build_args(&args[1..])?),
+        Some("repl") => repl(),
+        Some("fmt") => format(&args[1..]),
+        Some("grammar") => grammar(&args[1..]),
+        _ => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+    }
+}
+
+/// Formats the files in place, `--check` fails listing the ones that need formatting instead.
+fn format(args: &[String]) -> Result<()> {
+    let mut check = false;
+    let mut files = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            flag if flag.starts_with('-') => return Err(anyhow!("Unknown option {}\n{}", flag, USAGE)),
+            file => files.append(&mut vec![PathBuf::from(file)]),
+        }
+    }
+    if files.is_empty() {
+        return Err(anyhow!("No input file\n{}", USAGE));
+    }
+
+    let unformatted = modern_asm::driver::format_files(&files, check)?;
+    if check && !unformatted.is_empty() {
+        let names: Vec<String> = unformatted.iter().map(|f| f.display().to_string()).collect();
+        return Err(anyhow!("Not formatted: {}", names.join(", ")));
+    }
+
+    Ok(())
+}
+
+/// Writes the TextMate grammar to stdout, or to the file given with `-o`.
+fn grammar(args: &[String]) -> Result<()> {
+    let mut output = None;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "-o" => output = Some(PathBuf::from(next_value(&mut rest, arg)?)),
+            other => return Err(anyhow!("Unknown option {}\n{}", other, USAGE)),
+        }
+    }
+
+    let grammar = modern_asm::grammar::textmate_grammar();
+    match output {
+        Some(file) => {
+            std::fs::write(&file, grammar).map_err(|e| anyhow!("Cannot write {}: {}", file.display(), e))
+        }
+        None => {
+            print!("{}", grammar);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "llvm")]
+fn build(options: &BuildOptions) -> Result<()> {
+    modern_asm::driver::build(options)
+}
+
+#[cfg(not(feature = "llvm"))]
+fn build(_: &BuildOptions) -> Result<()> {
+    Err(anyhow!("modern_asm was built without the llvm feature, use run --interp"))
+}
+
+#[cfg(feature = "llvm")]
+fn run_binary(options: &BuildOptions) -> Result<i32> {
+    modern_asm::driver::run(options)
+}
+
+#[cfg(not(feature = "llvm"))]
+fn run_binary(_: &BuildOptions) -> Result<i32> {
+    Err(anyhow!("modern_asm was built without the llvm feature, use run --interp"))
+}
+
+#[cfg(feature = "llvm")]
+fn repl() -> Result<()> {
+    modern_asm::repl::run(std::io::stdin().lock(), std::io::stdout())
+}
+
+#[cfg(not(feature = "llvm"))]
+fn repl() -> Result<()> {
+    Err(anyhow!("modern_asm was built without the llvm feature, the REPL needs its JIT"))
+}
+
+#[cfg(feature = "interp")]
+fn interpret(options: &BuildOptions) -> Result<i32> {
+    // exit statuses are truncated like the ones of compiled programs
+    Ok(modern_asm::driver::interpret(&options.input)? as i32)
+}
+
+#[cfg(not(feature = "interp"))]
+fn interpret(_: &BuildOptions) -> Result<i32> {
+    Err(anyhow!("modern_asm was built without the interp feature"))
+}
+
+#[cfg(feature = "vm")]
+fn run_vm(
+    options: &BuildOptions,
+    max_instructions: Option<u64>,
+    max_memory: Option<usize>,
+    max_call_depth: Option<usize>,
+) -> Result<i32> {
+    let defaults = modern_asm::vm::machine::Limits::default();
+    let limits = modern_asm::vm::machine::Limits {
+        instructions: max_instructions,
+        memory: max_memory.unwrap_or(defaults.memory),
+        call_depth: max_call_depth.unwrap_or(defaults.call_depth),
+        ..defaults
+    };
+    Ok(modern_asm::driver::run_vm(&options.input, limits)? as i32)
+}
+
+#[cfg(not(feature = "vm"))]
+fn run_vm(_: &BuildOptions, _: Option<u64>, _: Option<usize>, _: Option<usize>) -> Result<i32> {
+    Err(anyhow!("modern_asm was built without the vm feature"))
+}
+
+#[cfg(feature = "vm")]
+fn write_bytecode(options: &BuildOptions) -> Result<()> {
+    modern_asm::driver::write_bytecode(&options.input, options.output.clone())
+}
+
+#[cfg(not(feature = "vm"))]
+fn write_bytecode(_: &BuildOptions) -> Result<()> {
+    Err(anyhow!("modern_asm was built without the vm feature"))
+}
+
+#[cfg(feature = "vm")]
+fn disassemble(options: &BuildOptions) -> Result<()> {
+    print!("{}", modern_asm::driver::load_program(&options.input)?);
+    Ok(())
+}
+
+#[cfg(not(feature = "vm"))]
+fn disassemble(_: &BuildOptions) -> Result<()> {
+    Err(anyhow!("modern_asm was built without the vm feature"))
+}
+
+fn parse_build_args(args: &[String]) -> Result<BuildOptions> {
+    let mut input = None;
+    let mut options = BuildOptions::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => options.output = Some(PathBuf::from(next_value(&mut args, arg)?)),
+            "-c" => options.emit = Emit::Object,
+            "--emit" => options.emit = parse_emit(next_value(&mut args, arg)?)?,
+            flag if flag.starts_with("--emit=") => options.emit = parse_emit(&flag["--emit=".len()..])?,
+            flag if flag.starts_with("-O") => {
+                options.opt_level = OptLevel::from_name(&flag[2..])
+                    .ok_or_else(|| anyhow!("Unknown optimization level {}, expected -O0, -O1, -O2, -O3 or -Os", flag))?
+            }
+            "--freestanding" => options.freestanding = true,
+            "--bounds-checks" => options.bounds_checks = Some(true),
+            "--no-bounds-checks" => options.bounds_checks = Some(false),
+            "-g" => options.debug_info = true,
+            "--header" => options.header = Some(PathBuf::from(next_value(&mut args, arg)?)),
+            "--target" => options.target = Some(next_value(&mut args, arg)?.clone()),
+            "--cpu" => options.cpu = Some(next_value(&mut args, arg)?.clone()),
+            "--target-features" => options.target_features = Some(next_value(&mut args, arg)?.clone()),
+            "--linker" => options.linker = Some(next_value(&mut args, arg)?.clone()),
+            flag if flag.starts_with('-') => return Err(anyhow!("Unknown option {}\n{}", flag, USAGE)),
+            file => input = Some(PathBuf::from(file)),
+        }
+    }
+
+    options.input = input.ok_or_else(|| anyhow!("No input file\n{}", USAGE))?;
+    Ok(options)
+}
+
+fn parse_emit(name: &str) -> Result<Emit> {
+    Emit::from_name(name).ok_or_else(|| anyhow!("Unknown --emit kind {}, expected exe, obj, asm, llvm-ir or llvm-bc", name))
+}
+
+fn next_value<'a>(args: &mut impl Iterator<Item = &'a String>, flag: &str) -> Result<&'a String> {
+    args.next().ok_or_else(|| anyhow!("Missing value for {}", flag))
 }
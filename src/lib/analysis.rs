@@ -0,0 +1,729 @@
+use crate::checker::{literal_type, Checker, Scope};
+use crate::lexer::{Lexer, Span, Token, CONTEXTUAL_KEYWORDS, KEYWORDS};
+use crate::parser::{opens_block, Parser};
+use crate::types::call_type::BUILTINS;
+use crate::types::data_type::{DataType, TYPE_NAMES};
+use crate::types::enum_type::EnumType;
+use crate::types::func_type::FuncType;
+use crate::types::lang_type::LangType;
+use crate::types::op_type::INSTRUCTIONS;
+use crate::types::primitive_type::{PrimitiveType, Primitives};
+use crate::types::struct_type::StructType;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Parameter,
+    Variable,
+    Constant,
+    Struct,
+    Enum,
+}
+
+/// A name defined in the file.
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub span: Span,            // where the name is written in its definition
+    pub detail: String,        // the definition as source, with the inferred type once the file parses
+    pub scope: Option<String>, // function a parameter or local belongs to
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompletionKind {
+    Keyword,
+    Instruction,
+    Type,
+    Builtin,
+    Symbol(SymbolKind),
+}
+
+#[derive(Clone, Debug)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub detail: String,
+}
+
+/// How a token is highlighted. Structs and enums are types, `true` and `false` are `Bool`
+/// and char literals are strings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenClass {
+    Keyword,
+    Instruction,
+    Type,
+    Function,
+    Parameter,
+    Variable,
+    Constant,
+    Field,
+    Number,
+    Bool,
+    String,
+    Comment,
+    Asm,
+}
+
+/// A token from `start` up to `end`, which is on a later line for strings spanning lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClassifiedToken {
+    pub start: Span,
+    pub end: Span,
+    pub class: TokenClass,
+}
+
+/// What an editor wants to know about a source file, from the lexer and parser alone.
+/// Definitions come from the tokens so they survive a line that doesn't parse yet,
+/// types need the whole file to parse.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    pub symbols: Vec<Symbol>,
+    references: Vec<(Span, usize)>,   // every identifier naming a symbol, definitions included
+    blocks: Vec<(usize, u32)>,        // global symbols spanning lines, with the line of their `end`
+    tokens: Vec<(Token, Span, Span)>, // with where they start and end
+}
+
+impl Analysis {
+    pub fn new(source: &str) -> Self {
+        let mut analysis = Analysis::default();
+
+        let mut lexer = Lexer::new(source.to_string());
+        analysis.tokens = match lexer.collect_ranges() {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                analysis.error(lexer.span(), e.to_string());
+                return analysis;
+            }
+        };
+        let tokens: Vec<(Token, Span)> = analysis.tokens.iter().map(|(t, start, _)| (t.clone(), *start)).collect();
+        for (_, span) in tokens.iter().filter(|(t, _)| *t == Token::Illegal) {
+            analysis.error(*span, "Unexpected character".to_string());
+        }
+
+        analysis.define(&tokens);
+
+        let mut parser = Parser::with_spans(tokens.clone());
+        let parsed = parser.parse_file();
+        let last = tokens.last().map_or(Span { line: 1, column: 1 }, |(_, span)| *span);
+        let span = parser.error_span().unwrap_or(last);
+        match parsed {
+            Ok(ast) => analysis.describe(&ast),
+            Err(e) => analysis.parse_error(span, without_position(e.to_string())),
+        }
+
+        analysis
+    }
+
+    /// Symbol named by the identifier at `span`, which may point anywhere inside or right after the name.
+    pub fn symbol_at(&self, span: Span) -> Option<&Symbol> {
+        self.reference_at(span).map(|(_, symbol)| symbol)
+    }
+
+    /// Like `symbol_at`, with the start of the identifier.
+    pub fn reference_at(&self, span: Span) -> Option<(Span, &Symbol)> {
+        self.references
+            .iter()
+            .find(|(start, index)| {
+                let len = self.symbols[*index].name.chars().count() as u32;
+                start.line == span.line && start.column <= span.column && span.column <= start.column + len
+            })
+            .map(|(start, index)| (*start, &self.symbols[*index]))
+    }
+
+    /// Every place `symbol` is written, its definition first.
+    pub fn references(&self, symbol: &Symbol) -> Vec<Span> {
+        let index = self.symbols.iter().position(|s| std::ptr::eq(s, symbol));
+        self.references
+            .iter()
+            .filter(|(_, i)| Some(*i) == index)
+            .map(|(span, _)| *span)
+            .collect()
+    }
+
+    /// Functions, constants, structs and enums of the file, with the line their definition ends on.
+    pub fn outline(&self) -> Vec<(&Symbol, u32)> {
+        self.symbols
+            .iter()
+            .enumerate()
+            .filter(|(_, symbol)| symbol.scope.is_none())
+            .map(|(i, symbol)| {
+                let end = self.blocks.iter().find(|(index, _)| *index == i).map_or(symbol.span.line, |(_, end)| *end);
+                (symbol, end)
+            })
+            .collect()
+    }
+
+    /// Words that can be written at `line`: keywords, instructions, types, builtins, the
+    /// global names and the parameters and locals of the function around the line.
+    pub fn completions(&self, line: u32) -> Vec<Completion> {
+        let scope = self.function_at(line).map(|symbol| symbol.name.clone());
+
+        let keywords: Vec<&str> = KEYWORDS.iter().map(|(word, _)| *word).collect();
+        let builtins: Vec<&str> = BUILTINS
+            .iter()
+            .copied()
+            .filter(|name| !self.symbols.iter().any(|s| s.scope.is_none() && s.name == *name))
+            .collect();
+
+        let mut completions = words(&keywords, CompletionKind::Keyword, "keyword");
+        completions.append(&mut words(&["true", "false"], CompletionKind::Keyword, "bool"));
+        completions.append(&mut words(INSTRUCTIONS, CompletionKind::Instruction, "instruction"));
+        completions.append(&mut words(TYPE_NAMES, CompletionKind::Type, "type"));
+        completions.append(&mut words(&builtins, CompletionKind::Builtin, "builtin"));
+        for symbol in &self.symbols {
+            if symbol.scope.is_none() || symbol.scope == scope {
+                completions.append(&mut vec![Completion {
+                    label: symbol.name.clone(),
+                    kind: CompletionKind::Symbol(symbol.kind),
+                    detail: symbol.detail.clone(),
+                }]);
+            }
+        }
+
+        completions
+    }
+
+    /// Every token but punctuation and line breaks, in order. Identifiers are classified by the
+    /// symbol they name, the others by where they are: a mnemonic starting a line is an instruction,
+    /// a name after a dot a field and builtins, type names and contextual keywords are what they say.
+    pub fn tokens(&self) -> Vec<ClassifiedToken> {
+        let mut classified = vec![];
+        let mut line_start = true;
+        for (i, (t, start, end)) in self.tokens.iter().enumerate() {
+            let first = std::mem::replace(&mut line_start, *t == Token::NewLine);
+            let class = match t {
+                Token::Ident(name) => self.classify(name, *start, first, i),
+                Token::Int(_) | Token::Float(_) => TokenClass::Number,
+                Token::Bool(_) => TokenClass::Bool,
+                Token::String(_) | Token::Char(_) => TokenClass::String,
+                Token::Comment(_) => TokenClass::Comment,
+                Token::AsmLine(_) => TokenClass::Asm,
+                t if KEYWORDS.iter().any(|(_, keyword)| keyword == t) => TokenClass::Keyword,
+                _ => continue,
+            };
+            classified.append(&mut vec![ClassifiedToken { start: *start, end: *end, class }]);
+        }
+
+        classified
+    }
+
+    fn classify(&self, name: &str, start: Span, first: bool, i: usize) -> TokenClass {
+        if first && INSTRUCTIONS.contains(&name) {
+            return TokenClass::Instruction;
+        }
+        if let Some((_, index)) = self.references.iter().find(|(span, _)| *span == start) {
+            return match self.symbols[*index].kind {
+                SymbolKind::Function => TokenClass::Function,
+                SymbolKind::Parameter => TokenClass::Parameter,
+                SymbolKind::Variable => TokenClass::Variable,
+                SymbolKind::Constant => TokenClass::Constant,
+                SymbolKind::Struct | SymbolKind::Enum => TokenClass::Type,
+            };
+        }
+
+        let previous = i.checked_sub(1).map(|i| &self.tokens[i].0);
+        let next = self.tokens.get(i + 1).map(|(t, _, _)| t);
+        if name == "addr" {
+            TokenClass::Instruction
+        } else if previous == Some(&Token::Dot) {
+            TokenClass::Field
+        } else if TYPE_NAMES.contains(&name) {
+            TokenClass::Type
+        } else if BUILTINS.contains(&name) && next == Some(&Token::Lparen) {
+            TokenClass::Function
+        } else if CONTEXTUAL_KEYWORDS.contains(&name) {
+            TokenClass::Keyword
+        } else {
+            TokenClass::Variable
+        }
+    }
+
+    /// Function whose definition spans `line`.
+    fn function_at(&self, line: u32) -> Option<&Symbol> {
+        self.blocks
+            .iter()
+            .map(|(index, end)| (&self.symbols[*index], *end))
+            .find(|(symbol, end)| symbol.kind == SymbolKind::Function && symbol.span.line <= line && line <= *end)
+            .map(|(symbol, _)| symbol)
+    }
+
+    fn error(&mut self, span: Span, message: String) {
+        self.diagnostics.append(&mut vec![Diagnostic { span, message }]);
+    }
+
+    /// Parse errors on a line that already has a diagnostic only repeat it.
+    fn parse_error(&mut self, span: Span, message: String) {
+        if !self.diagnostics.iter().any(|d| d.span.line == span.line) {
+            self.error(span, message);
+        }
+    }
+
+    // ------------------------------------
+    // Definitions and references
+
+    /// Walks the lines of the file, defining names as they appear and resolving every identifier.
+    fn define(&mut self, tokens: &[(Token, Span)]) {
+        let mut lines: Vec<Vec<(Token, Span)>> = vec![vec![]];
+        for (t, span) in tokens {
+            match t {
+                Token::NewLine if lines.last().is_some_and(|line| !line.is_empty()) => lines.append(&mut vec![vec![]]),
+                Token::NewLine | Token::Comment(_) => {}
+                t => {
+                    if let Some(line) = lines.last_mut() {
+                        line.append(&mut vec![(t.clone(), *span)]);
+                    }
+                }
+            }
+        }
+
+        let mut blocks: Vec<Token> = vec![]; // keyword of every open block
+        let mut global: Option<usize> = None; // fn, struct or enum the line is in
+        for line in lines.iter().filter(|line| !line.is_empty()) {
+            let code: Vec<Token> = line.iter().map(|(t, _)| t.clone()).collect();
+
+            if code[0] == Token::End {
+                blocks.pop();
+                if blocks.is_empty() {
+                    if let Some(index) = global.take() {
+                        self.blocks.append(&mut vec![(index, line[0].1.line)]);
+                    }
+                }
+                continue;
+            }
+
+            let function = global.filter(|i| self.symbols[*i].kind == SymbolKind::Function);
+            let scope = function.map(|i| self.symbols[i].name.clone());
+            match (blocks.is_empty(), &scope) {
+                (true, _) => {
+                    let defined = self.define_global(line);
+                    if opens_block(&code) {
+                        global = defined;
+                    }
+                }
+                (false, Some(function)) => self.define_local(line, function),
+                (false, None) => {}
+            }
+
+            let in_type = !blocks.is_empty() && function.is_none();
+            self.resolve(line, scope.as_deref(), in_type);
+
+            if opens_block(&code) {
+                blocks.append(&mut vec![code.iter().find(|t| **t != Token::Export).cloned().unwrap_or(Token::End)]);
+            }
+        }
+
+        // a block still being written runs to the end of the file
+        if let (Some(index), Some((_, span))) = (global, tokens.last()) {
+            self.blocks.append(&mut vec![(index, span.line)]);
+        }
+    }
+
+    /// `fn`, `export fn`, `extern fn`, `const`, `struct` and `enum` at the top level.
+    fn define_global(&mut self, line: &[(Token, Span)]) -> Option<usize> {
+        let code: Vec<&Token> = line.iter().map(|(t, _)| t).collect();
+        let (kind, at, detail) = match code[..] {
+            [Token::Export, Token::Function, ..] => (SymbolKind::Function, 2, "export fn"),
+            [Token::Extern, Token::Function, ..] => (SymbolKind::Function, 2, "extern fn"),
+            [Token::Function, ..] => (SymbolKind::Function, 1, "fn"),
+            [Token::Const, ..] => (SymbolKind::Constant, 1, "const"),
+            [Token::Struct, ..] => (SymbolKind::Struct, 1, "struct"),
+            [Token::Enum, ..] => (SymbolKind::Enum, 1, "enum"),
+            _ => return None,
+        };
+        let (name, span) = match line.get(at) {
+            Some((Token::Ident(name), span)) => (name.clone(), *span),
+            _ => return None,
+        };
+
+        if let Some(previous) = self.symbols.iter().find(|s| s.scope.is_none() && s.name == name) {
+            let message = format!("{} is already defined at line {}", name, previous.span.line);
+            self.error(span, message);
+        }
+        let index = self.add(name.clone(), kind, span, format!("{} {}", detail, name), None);
+
+        // `n x:i64 -> i32:`, names not in a type are parameters
+        if kind == SymbolKind::Function && code[0] != &Token::Extern {
+            for (i, (t, span)) in line.iter().enumerate().skip(at + 1) {
+                match (t, &line[i - 1].0) {
+                    (Token::Arrow, _) => break,
+                    (Token::Ident(param), Token::Ident(_) | Token::GreaterThan | Token::Rbracket) => {
+                        self.add(param.clone(), SymbolKind::Parameter, *span, param.clone(), Some(&name));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Some(index)
+    }
+
+    /// `let x`, `const N` and the destination of an instruction define a local the first time.
+    fn define_local(&mut self, line: &[(Token, Span)], function: &str) {
+        let (kind, name, span) = match line {
+            [(Token::Let, _), (Token::Ident(name), span), ..] => (SymbolKind::Variable, name, span),
+            [(Token::Const, _), (Token::Ident(name), span), ..] => (SymbolKind::Constant, name, span),
+            [(Token::Ident(op), _), (Token::Ident(name), span), ..]
+                if INSTRUCTIONS.contains(&op.as_str()) && op != "store" && op != "addr" =>
+            {
+                (SymbolKind::Variable, name, span)
+            }
+            _ => return,
+        };
+        if self.local(function, name).is_some() {
+            return;
+        }
+
+        let detail = match kind {
+            SymbolKind::Constant => format!("const {}", name),
+            _ => format!("let {}", name),
+        };
+        self.add(name.clone(), kind, *span, detail, Some(function));
+    }
+
+    /// Records every identifier of `line` that names a symbol. Field names after a dot don't, and
+    /// inside a struct or enum only the types of fields do.
+    fn resolve(&mut self, line: &[(Token, Span)], function: Option<&str>, in_type: bool) {
+        for (i, (t, span)) in line.iter().enumerate() {
+            let name = match t {
+                Token::Ident(name) => name,
+                _ => continue,
+            };
+            let previous = i.checked_sub(1).map(|i| &line[i].0);
+            if previous == Some(&Token::Dot) {
+                continue;
+            }
+            if in_type && !matches!(previous, Some(Token::Colon | Token::LessThan | Token::Lbracket)) {
+                continue;
+            }
+
+            let index = function
+                .and_then(|function| self.local(function, name))
+                .or_else(|| self.symbols.iter().position(|s| s.scope.is_none() && s.name == *name));
+            if let Some(index) = index {
+                self.references.append(&mut vec![(*span, index)]);
+            }
+        }
+    }
+
+    fn local(&self, function: &str, name: &str) -> Option<usize> {
+        self.symbols
+            .iter()
+            .position(|s| s.scope.as_deref() == Some(function) && s.name == name)
+    }
+
+    fn add(&mut self, name: String, kind: SymbolKind, span: Span, detail: String, scope: Option<&str>) -> usize {
+        self.symbols.append(&mut vec![Symbol { name, kind, span, detail, scope: scope.map(str::to_string) }]);
+        self.symbols.len() - 1
+    }
+
+    // ------------------------------------
+    // Types
+
+    /// Fills in the details of the symbols from the parsed file and reports its type errors.
+    fn describe(&mut self, ast: &[LangType]) {
+        let checker = Checker::check(ast);
+        for error in &checker.errors {
+            // every statement has a location, the file start is only a fallback
+            let span = error.span.unwrap_or(Span { line: 1, column: 1 });
+            self.error(span, error.message.clone());
+        }
+
+        for symbol in self.symbols.iter_mut() {
+            let locals = match &symbol.scope {
+                Some(function) => match checker.locals.get(function) {
+                    Some(locals) => Some(locals),
+                    None => continue,
+                },
+                None => None,
+            };
+            let name = symbol.name.as_str();
+
+            let detail = match symbol.kind {
+                SymbolKind::Function => checker.function(name).map(signature),
+                SymbolKind::Struct => checker.struct_type(name).map(struct_source),
+                SymbolKind::Enum => checker.enum_type(name).map(enum_source),
+                SymbolKind::Constant => {
+                    let value = match locals {
+                        Some(locals) => locals.constants.get(name),
+                        None => checker.constant(name),
+                    };
+                    value.map(|value| match literal_type(value, None) {
+                        Some(data_type) => format!("const {}:{} {}", name, data_type, literal_source(value)),
+                        None => format!("const {} {}", name, literal_source(value)),
+                    })
+                }
+                SymbolKind::Parameter => locals
+                    .and_then(|locals| locals.variables.get(name))
+                    .map(|data_type| format!("{}:{}", name, data_type)),
+                SymbolKind::Variable => locals
+                    .and_then(|locals| locals.variables.get(name))
+                    .map(|data_type| format!("let {}:{}", name, data_type)),
+            };
+            if let Some(detail) = detail {
+                symbol.detail = detail;
+            }
+        }
+    }
+}
+
+fn words(words: &[&str], kind: CompletionKind, detail: &str) -> Vec<Completion> {
+    words
+        .iter()
+        .map(|word| Completion { label: word.to_string(), kind, detail: detail.to_string() })
+        .collect()
+}
+
+/// The header of `func` as it is written, `fn fib n:i32 -> i32`.
+fn signature(func: &FuncType) -> String {
+    let keyword = match (func.is_extern, func.is_export) {
+        (true, _) => "extern fn",
+        (_, true) => "export fn",
+        _ => "fn",
+    };
+    let mut text = format!("{} {}", keyword, func.name);
+    for param in &func.param {
+        match &param.data_type {
+            Some(data_type) if param.name.is_empty() => text.push_str(&format!(" {}", data_type)),
+            Some(data_type) => text.push_str(&format!(" {}:{}", param.name, data_type)),
+            None => text.push_str(&format!(" {}", param.name)),
+        }
+    }
+    if func.variadic {
+        text.push_str(" ...");
+    }
+    if func.ret != DataType::Void {
+        text.push_str(&format!(" -> {}", func.ret));
+    }
+
+    text
+}
+
+fn struct_source(struct_t: &StructType) -> String {
+    let mut text = format!("struct {}", struct_t.name);
+    if struct_t.packed {
+        text.push_str(" packed");
+    }
+    if let Some(align) = struct_t.align {
+        text.push_str(&format!(" align({})", align));
+    }
+    text.push(':');
+    for field in &struct_t.fields {
+        text.push_str(&format!("\n    {}: {}", field.name, field.data_type.clone().unwrap_or_default()));
+    }
+
+    text + "\nend"
+}
+
+fn enum_source(enum_t: &EnumType) -> String {
+    let mut text = format!("enum {} {}:", enum_t.name, enum_t.data_type);
+    for variant in &enum_t.variants {
+        text.push_str(&format!("\n    {} = {}", variant.name, variant.value));
+    }
+
+    text + "\nend"
+}
+
+fn literal_source(p: &PrimitiveType) -> String {
+    let (sign, digits) = match p.value.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", p.value.as_str()),
+    };
+    match (&p.primitive, p.radix) {
+        (Primitives::String, _) => format!("{:?}", p.value),
+        (Primitives::Int, 16) => format!("{}0x{}", sign, digits),
+        (Primitives::Int, 8) => format!("{}0o{}", sign, digits),
+        (Primitives::Int, 2) => format!("{}0b{}", sign, digits),
+        _ => p.value.clone(),
+    }
+}
+
+/// Parser errors count lines without blanks and comments, the diagnostic's span says where they are.
+fn without_position(message: String) -> String {
+    match message.rsplit_once(" at position ") {
+        Some((text, pos)) if pos.chars().all(|c| c.is_ascii_digit() || c == ',' || c == ' ') => text.to_string(),
+        _ => message,
+    }
+}
+
+// ------------------------------------
+// Tests
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use super::{Analysis, CompletionKind, SymbolKind, TokenClass};
+    use crate::lexer::Span;
+
+    const SOURCE: &str = "const LIMIT 10
+struct Point:
+    x: i64
+    y: i64
+end
+
+fn fib n:i32 -> i32:
+    if n < 2:
+        return n
+    end
+    sub m n 1
+    let a fib(m)
+    add b a 1
+    return b
+end
+
+fn main:
+    let p:Point
+    load px p.x
+    itof f LIMIT
+    fib(LIMIT)
+end
+";
+
+    fn at(line: u32, column: u32) -> Span {
+        Span { line, column }
+    }
+
+    #[test]
+    fn analysis_symbols() -> Result<()> {
+        let analysis = Analysis::new(SOURCE);
+        assert!(analysis.diagnostics.is_empty(), "{:?}", analysis.diagnostics);
+
+        let detail = |line, column| analysis.symbol_at(at(line, column)).map(|s| s.detail.clone());
+        assert_eq!(detail(7, 4), Some("fn fib n:i32 -> i32".to_string()));
+        assert_eq!(detail(8, 8), Some("n:i32".to_string()));
+        assert_eq!(detail(11, 9), Some("let m:i32".to_string()));
+        assert_eq!(detail(12, 9), Some("let a:i32".to_string()));
+        assert_eq!(detail(13, 9), Some("let b:i32".to_string()));
+        assert_eq!(detail(18, 9), Some("let p:Point".to_string()));
+        assert_eq!(detail(19, 10), Some("let px:i64".to_string()));
+        assert_eq!(detail(20, 10), Some("let f:f64".to_string()));
+        assert_eq!(detail(21, 9), Some("const LIMIT:i32 10".to_string()));
+        assert_eq!(detail(19, 15), None); // field names are not symbols
+
+        // hover
+        let (start, symbol) = analysis.reference_at(at(12, 13)).expect("fib");
+        assert_eq!((start, symbol.name.as_str()), (at(12, 11), "fib"));
+        assert!(analysis.reference_at(at(12, 5)).is_none()); // let
+
+        // definitions
+        let fib = analysis.symbol_at(at(21, 5)).expect("fib");
+        assert_eq!((fib.kind, fib.span), (SymbolKind::Function, at(7, 4)));
+        assert_eq!(analysis.references(fib), vec![at(7, 4), at(12, 11), at(21, 5)]);
+        let n = analysis.symbol_at(at(11, 11)).expect("n");
+        assert_eq!((n.kind, n.span, n.scope.as_deref()), (SymbolKind::Parameter, at(7, 8), Some("fib")));
+
+        let outline: Vec<(&str, u32)> = analysis.outline().iter().map(|(s, end)| (s.name.as_str(), *end)).collect();
+        assert_eq!(outline, vec![("LIMIT", 1), ("Point", 5), ("fib", 15), ("main", 22)]);
+
+        return Ok(());
+    }
+
+    #[test]
+    fn analysis_diagnostics() -> Result<()> {
+        let analysis = Analysis::new("fn main:\n    let s \"open\n");
+        assert_eq!(analysis.diagnostics.len(), 1);
+        assert!(analysis.diagnostics[0].message.starts_with("Unclosed string literal"));
+
+        let analysis = Analysis::new("fn main:\n\n    if a 1:\n    end\nend\n");
+        assert_eq!(analysis.diagnostics.len(), 1);
+        assert_eq!(analysis.diagnostics[0].span.line, 3);
+        assert!(!analysis.diagnostics[0].message.contains("position"));
+
+        let analysis = Analysis::new("fn a:\nend\nfn a:\nend\n");
+        assert_eq!(analysis.diagnostics[0].span, at(3, 4));
+        assert_eq!(analysis.diagnostics[0].message, "a is already defined at line 1");
+
+        // definitions survive a line that doesn't parse
+        let analysis = Analysis::new("fn main:\n    let total 1\n    add total\nend\n");
+        assert_eq!(analysis.diagnostics.len(), 1);
+        assert_eq!(analysis.symbol_at(at(3, 9)).map(|s| s.detail.as_str()), Some("let total"));
+        assert_eq!(analysis.diagnostics[0].message, "Missing operand");
+
+        // type errors come from the checker, on the line of the statement
+        let analysis = Analysis::new("fn f:\n    let a 1\n\n    add a a true\nend\nfn main:\n    let a 1\n    let a:i64 2\nend\n");
+        let messages: Vec<(u32, &str)> =
+            analysis.diagnostics.iter().map(|d| (d.span.line, d.message.as_str())).collect();
+        assert_eq!(messages, vec![(4, "Mismatched operand types i32 and bool"), (8, "Variable a is already declared")]);
+
+        // truncated lines are parse errors, not panics
+        for line in ["add", "let", "const", "let x -"] {
+            let analysis = Analysis::new(&format!("fn main:\n    {}\nend\n", line));
+            assert_eq!(analysis.diagnostics.len(), 1, "{}", line);
+            assert_eq!(analysis.diagnostics[0].span.line, 2);
+        }
+
+        return Ok(());
+    }
+
+    #[test]
+    fn analysis_completions() -> Result<()> {
+        let analysis = Analysis::new(SOURCE);
+        let labels = |line| -> Vec<(String, CompletionKind)> {
+            analysis.completions(line).into_iter().map(|c| (c.label, c.kind)).collect()
+        };
+
+        let inside = labels(13);
+        assert!(inside.contains(&("match".to_string(), CompletionKind::Keyword)));
+        assert!(inside.contains(&("move".to_string(), CompletionKind::Instruction)));
+        assert!(inside.contains(&("u8".to_string(), CompletionKind::Type)));
+        assert!(inside.contains(&("print".to_string(), CompletionKind::Builtin)));
+        assert!(inside.contains(&("fib".to_string(), CompletionKind::Symbol(SymbolKind::Function))));
+        assert!(inside.contains(&("n".to_string(), CompletionKind::Symbol(SymbolKind::Parameter))));
+        assert!(inside.contains(&("a".to_string(), CompletionKind::Symbol(SymbolKind::Variable))));
+        assert!(!inside.iter().any(|(label, _)| label == "px"));
+
+        let outside = labels(16);
+        assert!(outside.contains(&("LIMIT".to_string(), CompletionKind::Symbol(SymbolKind::Constant))));
+        assert!(!outside.iter().any(|(label, _)| label == "n"));
+
+        return Ok(());
+    }
+
+    #[test]
+    fn analysis_tokens() -> Result<()> {
+        let input = "fn main:\n    let s \"a\nb\" # text\n    cast n LIMIT as u8\n    print(s)\n    move c 'x'\nend\n";
+        let analysis = Analysis::new(&format!("const LIMIT 300\n{}", input));
+
+        let classes: Vec<(TokenClass, u32, u32)> =
+            analysis.tokens().iter().map(|t| (t.class, t.start.line, t.start.column)).collect();
+        assert_eq!(
+            classes,
+            vec![
+                (TokenClass::Keyword, 1, 1),
+                (TokenClass::Constant, 1, 7),
+                (TokenClass::Number, 1, 13),
+                (TokenClass::Keyword, 2, 1),
+                (TokenClass::Function, 2, 4),
+                (TokenClass::Keyword, 3, 5),
+                (TokenClass::Variable, 3, 9),
+                (TokenClass::String, 3, 11),
+                (TokenClass::Comment, 4, 4),
+                (TokenClass::Instruction, 5, 5),
+                (TokenClass::Variable, 5, 10),
+                (TokenClass::Constant, 5, 12),
+                (TokenClass::Keyword, 5, 18),
+                (TokenClass::Type, 5, 21),
+                (TokenClass::Function, 6, 5),
+                (TokenClass::Variable, 6, 11),
+                (TokenClass::Instruction, 7, 5),
+                (TokenClass::Variable, 7, 10),
+                (TokenClass::String, 7, 12),
+                (TokenClass::Keyword, 8, 1),
+            ]
+        );
+
+        // the string ends on the next line
+        let string = analysis.tokens()[7];
+        assert_eq!((string.end.line, string.end.column), (4, 3));
+
+        return Ok(());
+    }
+}
@@ -0,0 +1,710 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, Result};
+
+use crate::lexer::Span;
+use crate::types::call_type::CallType;
+use crate::types::data_type::DataType;
+use crate::types::enum_type::EnumType;
+use crate::types::func_type::FuncType;
+use crate::types::lang_type::LangType;
+use crate::types::match_type::MatchType;
+use crate::types::op_type::{OpType, Operation};
+use crate::types::primitive_type::{PrimitiveType, Primitives};
+use crate::types::struct_type::StructType;
+
+/// What the typing rules look up. Codegen, the interpreter, the VM compiler and `Checker` keep
+/// their own maps and share the rules through the provided methods.
+pub trait Scope {
+    fn variable(&self, name: &str) -> Option<DataType>;
+    fn constant(&self, name: &str) -> Option<&PrimitiveType>;
+    fn function(&self, name: &str) -> Option<&FuncType>;
+    fn struct_type(&self, name: &str) -> Option<&StructType>;
+    fn enum_type(&self, name: &str) -> Option<&EnumType>;
+
+    /// Type `expr` has on its own. Number literals have none, they take the width of what they meet.
+    fn type_of(&self, expr: &LangType) -> Option<DataType> {
+        match expr {
+            LangType::Primitive(p) => inherent_type(p),
+            LangType::Var(var) => match self.constant(&var.name) {
+                Some(con) => inherent_type(con),
+                None => self.variable(&var.name),
+            },
+            LangType::Call(call) => match self.function(&call.name) {
+                Some(func) => Some(func.ret.clone()).filter(|t| *t != DataType::Void),
+                None if call.name == "len" => Some(DataType::U64),
+                None if call.name == "substr" => Some(DataType::Str),
+                None if call.name == "syscall" => Some(DataType::I64),
+                None => None,
+            },
+            LangType::Op(op) => match op.op {
+                Operation::Neg | Operation::Offset => self.type_of(&op.lhs),
+                Operation::Addr => self.type_of(&op.lhs).map(DataType::ptr),
+                Operation::Load => self.type_of(&op.lhs)?.pointee().cloned(),
+                Operation::Cast | Operation::Bitcast => match &*op.rhs {
+                    LangType::Type(data_type) => Some(data_type.clone()),
+                    _ => None,
+                },
+                Operation::Equal | Operation::NotEqual | Operation::LessThan | Operation::GreaterThan => {
+                    Some(DataType::Bool)
+                }
+                Operation::Add | Operation::Sub | Operation::Mul | Operation::Div | Operation::Mod => {
+                    self.type_of(&op.lhs).or_else(|| self.type_of(&op.rhs))
+                }
+                Operation::Field => self.field_type(&op.lhs, &op.rhs),
+                Operation::Index => match self.type_of(&op.lhs)? {
+                    DataType::Str => Some(DataType::ptr(DataType::U8)),
+                    DataType::Array(elem, _) => Some(DataType::ptr(*elem)),
+                    DataType::Ptr(pointee) => match *pointee {
+                        DataType::Array(elem, _) => Some(DataType::ptr(*elem)),
+                        pointee => Some(DataType::ptr(pointee)),
+                    },
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Type `expr` has where a `hint` is expected, literals and conversions take their width from it.
+    fn expected_type(&self, expr: &LangType, hint: Option<&DataType>) -> Option<DataType> {
+        match expr {
+            LangType::Primitive(p) => literal_type(p, hint),
+            LangType::Var(var) => match self.constant(&var.name) {
+                Some(con) => literal_type(con, hint),
+                None => self.variable(&var.name),
+            },
+            LangType::Op(op) => match op.op {
+                Operation::Neg => self.expected_type(&op.lhs, hint),
+                Operation::IntToFloat => Some(hint.filter(|t| t.is_float()).cloned().unwrap_or(DataType::F64)),
+                Operation::FloatToInt => Some(hint.filter(|t| t.is_int()).cloned().unwrap_or(DataType::I64)),
+                Operation::Add | Operation::Sub | Operation::Mul | Operation::Div | Operation::Mod => {
+                    // literals take the width of the other operand
+                    let operand = self.type_of(expr).or_else(|| hint.cloned());
+                    self.expected_type(&op.lhs, operand.as_ref())
+                }
+                _ => self.type_of(expr),
+            },
+            _ => self.type_of(expr),
+        }
+    }
+
+    /// `Enum.Variant` is a value of the enum's type, `s.field` the address of the field.
+    fn field_type(&self, base: &LangType, field: &LangType) -> Option<DataType> {
+        if let Some(enum_t) = self.enum_base(base) {
+            return Some(enum_t.data_type.clone());
+        }
+
+        let struct_type = match self.type_of(base)? {
+            DataType::Ptr(pointee) => *pointee,
+            other => other,
+        };
+        let struct_t = self.struct_type(&struct_type.to_string())?;
+        let field = match field {
+            LangType::Var(var) => struct_t.field_index(&var.name)?,
+            _ => return None,
+        };
+        struct_t.fields[field].data_type.clone().map(DataType::ptr)
+    }
+
+    /// The enum `base` names, unless a variable shadows it.
+    fn enum_base(&self, base: &LangType) -> Option<&EnumType> {
+        match base {
+            LangType::Var(var) if self.variable(&var.name).is_none() => self.enum_type(&var.name),
+            _ => None,
+        }
+    }
+
+    /// The patterns of a match on `value_type` are constants of that type, each value is matched
+    /// once and without an else arm there is an arm for every variant of the matched enum.
+    fn check_match(&self, match_t: &MatchType, value_type: &DataType) -> Result<()> {
+        let bits = value_type.bit_width();
+        let mask = if bits >= 64 { u64::MAX } else { (1 << bits) - 1 };
+        let mut covered = vec![];
+        let mut enums = vec![];
+
+        for pattern in match_t.arms.iter().flat_map(|arm| &arm.patterns) {
+            if let Some(case_type) = self.expected_type(pattern, Some(value_type)) {
+                if case_type != *value_type {
+                    return Err(anyhow!("Cannot match {} against a pattern of type {}", value_type, case_type));
+                }
+            }
+
+            let (value, enum_t) = match pattern {
+                LangType::Primitive(p) if p.is_int() => (p.to_i128(), None),
+                LangType::Var(var) => (self.constant(&var.name).filter(|c| c.is_int()).and_then(|c| c.to_i128()), None),
+                LangType::Op(op) if matches!(op.op, Operation::Field) => {
+                    let enum_t = self.enum_base(&op.lhs);
+                    let variant = match (enum_t, &*op.rhs) {
+                        (Some(enum_t), LangType::Var(var)) => enum_t.variant(&var.name),
+                        _ => None,
+                    };
+                    (variant.map(|v| v.value as i128), enum_t)
+                }
+                _ => (None, None),
+            };
+            // compared at the width of the value, like the switch codegen emits
+            let key = value.ok_or_else(|| anyhow!("Match patterns must be constants, got {:?}", pattern))? as u64 & mask;
+            if covered.contains(&key) {
+                return Err(anyhow!("Unreachable match arm, {:?} is already covered", pattern));
+            }
+
+            covered.append(&mut vec![key]);
+            enums.append(&mut vec![enum_t]);
+        }
+
+        if match_t.default.is_none() {
+            let enum_t = match enums.first() {
+                Some(Some(first)) if enums.iter().all(|e| e.is_some_and(|e| e.name == first.name)) => *first,
+                _ => return Err(anyhow!("Match on {} needs an else arm", value_type)),
+            };
+
+            let missing = enum_t
+                .variants
+                .iter()
+                .filter(|v| !covered.contains(&(v.value as u64 & mask)))
+                .map(|v| format!("{}.{}", enum_t.name, v.name))
+                .collect::<Vec<String>>();
+            if !missing.is_empty() {
+                return Err(anyhow!("Non-exhaustive match, missing {}", missing.join(", ")));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Strings and bools have a type of their own, numbers get theirs from `literal_type`.
+fn inherent_type(p: &PrimitiveType) -> Option<DataType> {
+    match p.primitive {
+        Primitives::String => Some(DataType::Str),
+        Primitives::Bool => Some(DataType::Bool),
+        Primitives::Int | Primitives::Float => None,
+    }
+}
+
+/// Type of the literal `p` where `hint` is expected. Without an integer or float hint integers are
+/// `i32`, or `i64` when they don't fit, and floats are `f64`.
+pub fn literal_type(p: &PrimitiveType, hint: Option<&DataType>) -> Option<DataType> {
+    Some(match p.primitive {
+        Primitives::Int => match hint {
+            Some(t) if t.is_int() => t.clone(),
+            _ if DataType::I32.fits_literal(p.to_i128()?, p.radix) => DataType::I32,
+            _ => DataType::I64,
+        },
+        Primitives::Float => match hint {
+            Some(t) if t.is_float() => t.clone(),
+            _ => DataType::F64,
+        },
+        Primitives::String => DataType::Str,
+        Primitives::Bool => DataType::Bool,
+    })
+}
+
+/// A type error, at the statement it is in when the file was parsed with spans.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeError {
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+/// The parameters, variables and constants of a function, each with the type of its declaration.
+#[derive(Clone, Debug, Default)]
+pub struct Locals {
+    pub variables: HashMap<String, DataType>,
+    pub constants: HashMap<String, PrimitiveType>,
+}
+
+/// Checks a parsed file with the rules of `Scope`, reporting the errors the backends would and
+/// recording the locals of every function. Only types that are known are compared.
+#[derive(Default)]
+pub struct Checker<'a> {
+    functions: HashMap<&'a str, &'a FuncType>,
+    structs: HashMap<&'a str, &'a StructType>,
+    enums: HashMap<&'a str, &'a EnumType>,
+    globals: HashMap<String, PrimitiveType>,
+    scope: Locals,            // of the function being checked
+    declared: HashSet<String>, // variables of the function, also those of unknown type
+    return_type: DataType,
+    location: Option<Span>,
+    pub locals: HashMap<String, Locals>,
+    pub errors: Vec<TypeError>,
+}
+
+impl Scope for Checker<'_> {
+    fn variable(&self, name: &str) -> Option<DataType> {
+        self.scope.variables.get(name).cloned()
+    }
+
+    fn constant(&self, name: &str) -> Option<&PrimitiveType> {
+        self.scope.constants.get(name).or_else(|| self.globals.get(name))
+    }
+
+    fn function(&self, name: &str) -> Option<&FuncType> {
+        self.functions.get(name).copied()
+    }
+
+    fn struct_type(&self, name: &str) -> Option<&StructType> {
+        self.structs.get(name).copied()
+    }
+
+    fn enum_type(&self, name: &str) -> Option<&EnumType> {
+        self.enums.get(name).copied()
+    }
+}
+
+impl<'a> Checker<'a> {
+    pub fn check(ast: &'a [LangType]) -> Self {
+        let mut checker = Checker::default();
+        for lang_t in ast {
+            match lang_t {
+                LangType::Func(func) => {
+                    checker.functions.insert(func.name.as_str(), func);
+                }
+                LangType::Struct(struct_t) => {
+                    checker.structs.insert(struct_t.name.as_str(), struct_t);
+                }
+                LangType::Enum(enum_t) => {
+                    checker.enums.insert(enum_t.name.as_str(), enum_t);
+                }
+                LangType::Const(con) => {
+                    checker.globals.insert(con.name.clone(), con.value.clone());
+                }
+                _ => {}
+            }
+        }
+
+        for lang_t in ast {
+            let func = match lang_t {
+                LangType::Func(func) if !func.is_extern => func,
+                _ => continue,
+            };
+            for param in &func.param {
+                checker.declared.insert(param.name.clone());
+                let data_type = param.data_type.clone().unwrap_or_default();
+                checker.scope.variables.insert(param.name.clone(), data_type);
+            }
+            checker.return_type = func.ret.clone();
+            checker.location = None;
+            checker.block(&func.body);
+
+            checker.declared.clear();
+            let locals = std::mem::take(&mut checker.scope);
+            checker.locals.insert(func.name.clone(), locals);
+        }
+
+        checker
+    }
+
+    fn block(&mut self, body: &[LangType]) {
+        for stmt in body {
+            self.statement(stmt);
+        }
+    }
+
+    fn statement(&mut self, stmt: &LangType) {
+        match stmt {
+            LangType::Location(span) => self.location = Some(*span),
+            LangType::Const(con) => {
+                self.scope.constants.insert(con.name.clone(), con.value.clone());
+            }
+            LangType::Op(op) if matches!(op.op, Operation::Assign) => self.assign(op),
+            LangType::Op(op) if matches!(op.op, Operation::Store) => {
+                let pointee = self.expr(&op.lhs, None).and_then(|t| t.pointee().cloned());
+                let pointee = pointee.filter(|t| *t != DataType::Void);
+                let value = self.expr(&op.rhs, pointee.as_ref());
+                if let (Some(pointee), Some(value)) = (pointee, value) {
+                    if value != pointee {
+                        self.error(format!("Cannot store {} through ptr<{}>", value, pointee));
+                    }
+                }
+            }
+            LangType::Call(call) => {
+                self.call(call);
+            }
+            LangType::If(if_t) => {
+                match self.expr(&if_t.condition, None) {
+                    Some(DataType::Bool) | None => {}
+                    Some(other) => self.error(format!("If condition must be bool, got {}", other)),
+                }
+                self.block(&if_t.body);
+                self.block(&if_t.else_body);
+            }
+            LangType::Match(match_t) => {
+                let errors = self.errors.len();
+                let value_type = self.expr(&match_t.value, None);
+                for pattern in match_t.arms.iter().flat_map(|arm| &arm.patterns) {
+                    self.expr(pattern, value_type.as_ref());
+                }
+                match value_type {
+                    Some(data_type) if !data_type.is_int() => self.error(format!("Cannot match on {}", data_type)),
+                    // patterns that are already wrong would be reported twice
+                    Some(data_type) if self.errors.len() == errors => {
+                        if let Err(e) = self.check_match(match_t, &data_type) {
+                            self.error(e.to_string());
+                        }
+                    }
+                    _ => {}
+                }
+                for arm in &match_t.arms {
+                    self.block(&arm.body);
+                }
+                if let Some(body) = &match_t.default {
+                    self.block(body);
+                }
+            }
+            LangType::Return(ret) => {
+                let return_type = self.return_type.clone();
+                match (&*ret.value, &return_type) {
+                    (LangType::Undefined, DataType::Void) => {}
+                    (LangType::Undefined, _) => self.error(format!("Missing return value of type {}", return_type)),
+                    (_, DataType::Void) => self.error("Cannot return a value from a void function".to_string()),
+                    (value, _) => match self.expr(value, Some(&return_type)) {
+                        Some(value_type) if value_type != return_type => self.error(format!(
+                            "Cannot return {} from a function returning {}",
+                            value_type, return_type
+                        )),
+                        _ => {}
+                    },
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// `let`, `move` and the instructions writing their result to a variable.
+    fn assign(&mut self, op: &OpType) {
+        let var = match &*op.lhs {
+            LangType::Var(var) => var,
+            _ => return,
+        };
+        if self.constant(&var.name).is_some() {
+            self.error(format!("Cannot assign to constant {}", var.name));
+            return;
+        }
+
+        let declared = match &var.data_type {
+            Some(data_type) => {
+                if self.variable(&var.name).is_some() {
+                    self.error(format!("Variable {} is already declared", var.name));
+                }
+                Some(data_type.clone())
+            }
+            None => self.variable(&var.name),
+        };
+
+        let value = match &*op.rhs {
+            LangType::Undefined => None,
+            rhs => self.expr(rhs, declared.as_ref()),
+        };
+        match (&declared, &value) {
+            (Some(declared), Some(value)) if value != declared => {
+                self.error(format!("Cannot assign {} to {} of type {}", value, var.name, declared));
+            }
+            _ => {}
+        }
+
+        self.declared.insert(var.name.clone());
+        if let Some(data_type) = declared.or(value) {
+            self.scope.variables.entry(var.name.clone()).or_insert(data_type);
+        }
+    }
+
+    /// Checks `expr` and its operands, returning its type where `hint` is expected.
+    fn expr(&mut self, expr: &LangType, hint: Option<&DataType>) -> Option<DataType> {
+        match expr {
+            LangType::Primitive(p) => {
+                let data_type = literal_type(p, hint)?;
+                if let (Primitives::Int, Some(value)) = (&p.primitive, p.to_i128()) {
+                    if !data_type.fits_literal(value, p.radix) {
+                        self.error(format!("Integer literal {} does not fit in {}", value, data_type));
+                    }
+                }
+                Some(data_type)
+            }
+            LangType::Var(var) => {
+                if self.constant(&var.name).is_none() && !self.declared.contains(&var.name) {
+                    self.error(format!("Unknown variable {}", var.name));
+                }
+                self.expected_type(expr, hint)
+            }
+            LangType::Call(call) => self.call(call),
+            LangType::Op(op) => self.operation(expr, op, hint),
+            _ => self.expected_type(expr, hint),
+        }
+    }
+
+    fn operation(&mut self, expr: &LangType, op: &OpType, hint: Option<&DataType>) -> Option<DataType> {
+        match op.op {
+            Operation::Add
+            | Operation::Sub
+            | Operation::Mul
+            | Operation::Div
+            | Operation::Mod
+            | Operation::Equal
+            | Operation::NotEqual
+            | Operation::LessThan
+            | Operation::GreaterThan => {
+                let is_compare = matches!(
+                    op.op,
+                    Operation::Equal | Operation::NotEqual | Operation::LessThan | Operation::GreaterThan
+                );
+                let operand = self
+                    .type_of(&op.lhs)
+                    .or_else(|| self.type_of(&op.rhs))
+                    .or_else(|| if is_compare { None } else { hint.cloned() });
+                let lhs = self.expr(&op.lhs, operand.as_ref());
+                let rhs = self.expr(&op.rhs, lhs.as_ref());
+                if let (Some(lhs), Some(rhs)) = (&lhs, &rhs) {
+                    if lhs != rhs {
+                        self.error(format!("Mismatched operand types {} and {}", lhs, rhs));
+                    }
+                }
+            }
+            Operation::Neg => {
+                let value_hint = self.type_of(&op.lhs).or_else(|| hint.cloned());
+                self.expr(&op.lhs, value_hint.as_ref());
+            }
+            Operation::IntToFloat => match self.expr(&op.lhs, None) {
+                Some(from) if !from.is_int() => self.error(format!("itof expects an integer, got {}", from)),
+                _ => {}
+            },
+            Operation::FloatToInt => match self.expr(&op.lhs, None) {
+                Some(from) if !from.is_float() => self.error(format!("ftoi expects a float, got {}", from)),
+                _ => {}
+            },
+            Operation::Cast => {
+                let from = self.expr(&op.lhs, None);
+                if let (Some(from), LangType::Type(to)) = (from, &*op.rhs) {
+                    if !from.can_cast_to(to) {
+                        self.error(format!("Cannot cast {} to {}", from, to));
+                    }
+                }
+            }
+            Operation::Bitcast => {
+                let from = self.expr(&op.lhs, None);
+                if let (Some(from), LangType::Type(to)) = (from, &*op.rhs) {
+                    if !from.can_bitcast_to(to) {
+                        self.error(format!("Cannot bitcast {} to {}", from, to));
+                    }
+                }
+            }
+            Operation::Field => {
+                let field = match &*op.rhs {
+                    LangType::Var(var) => var.name.as_str(),
+                    _ => "",
+                };
+                if let Some(enum_t) = self.enum_base(&op.lhs) {
+                    if enum_t.variant(field).is_none() {
+                        let name = enum_t.name.clone();
+                        self.error(format!("Enum {} has no variant {}", name, field));
+                    }
+                    return self.type_of(expr);
+                }
+
+                let struct_type = match self.expr(&op.lhs, None) {
+                    Some(DataType::Ptr(pointee)) => *pointee,
+                    other => other?,
+                };
+                if let Some(struct_t) = self.struct_type(&struct_type.to_string()) {
+                    if struct_t.field_index(field).is_none() {
+                        let name = struct_t.name.clone();
+                        self.error(format!("Struct {} has no field {}", name, field));
+                    }
+                }
+            }
+            Operation::Index => {
+                self.expr(&op.lhs, None);
+                match self.expr(&op.rhs, None) {
+                    Some(index) if !index.is_int() => self.error(format!("Index must be an integer, got {}", index)),
+                    _ => {}
+                }
+            }
+            Operation::Offset => {
+                self.expr(&op.lhs, None);
+                match self.expr(&op.rhs, None) {
+                    Some(index) if !index.is_int() => {
+                        self.error(format!("Pointer offset must be an integer, got {}", index))
+                    }
+                    _ => {}
+                }
+            }
+            Operation::Addr | Operation::Load => {
+                self.expr(&op.lhs, None);
+            }
+            _ => {}
+        }
+
+        self.expected_type(expr, hint)
+    }
+
+    /// Checks the arguments of `call`, returning its result type.
+    fn call(&mut self, call: &CallType) -> Option<DataType> {
+        let func = match self.function(&call.name) {
+            Some(func) => func,
+            None => {
+                let expected = match call.name.as_str() {
+                    "len" => Some(1),
+                    "substr" => Some(3),
+                    "print" | "syscall" => None,
+                    _ => {
+                        self.error(format!("Unknown function {}", call.name));
+                        return None;
+                    }
+                };
+                match expected {
+                    Some(expected) if call.param.len() != expected => {
+                        self.error(format!("{} expects {} arguments, got {}", call.name, expected, call.param.len()));
+                    }
+                    None if call.name == "syscall" && (call.param.is_empty() || call.param.len() > 7) => {
+                        let count = call.param.len();
+                        self.error(format!("syscall expects a number and up to 6 arguments, got {}", count));
+                    }
+                    _ => {}
+                }
+                for arg in &call.param {
+                    self.expr(arg, None);
+                }
+                return match call.name.as_str() {
+                    "len" => Some(DataType::U64),
+                    "substr" => Some(DataType::Str),
+                    "syscall" => Some(DataType::I64),
+                    _ => None,
+                };
+            }
+        };
+        let params: Vec<Option<DataType>> = func.param.iter().map(|p| p.data_type.clone()).collect();
+        let (variadic, ret) = (func.variadic, func.ret.clone());
+
+        if call.param.len() < params.len() || (!variadic && call.param.len() > params.len()) {
+            self.error(format!(
+                "Function {} expects {} arguments, got {}",
+                call.name,
+                params.len(),
+                call.param.len()
+            ));
+        }
+        for (i, arg) in call.param.iter().enumerate() {
+            let expected = params.get(i).map(|t| t.clone().unwrap_or_default());
+            match (self.expr(arg, expected.as_ref()), expected) {
+                (Some(value), Some(expected)) if value != expected => self.error(format!(
+                    "Argument {} of {} must be {}, got {}",
+                    i + 1,
+                    call.name,
+                    expected,
+                    value
+                )),
+                _ => {}
+            }
+        }
+
+        Some(ret).filter(|t| *t != DataType::Void)
+    }
+
+    fn error(&mut self, message: String) {
+        self.errors.append(&mut vec![TypeError { span: self.location, message }]);
+    }
+}
+
+// ------------------------------------
+// Tests
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use super::{Checker, Scope};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::types::data_type::DataType;
+    use crate::types::lang_type::LangType;
+
+    fn errors(input: &str) -> Result<Vec<String>> {
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        return Ok(Checker::check(&ast).errors.into_iter().map(|e| e.message).collect());
+    }
+
+    #[test]
+    fn checker_types() -> Result<()> {
+        let input = r#"const LIMIT 10
+enum Color u8:
+    Red
+end
+fn main:
+    let a 1
+    let big 3000000000
+    add b a LIMIT
+    itof f a
+    let c Color.Red
+    let s "x"
+    let l len(s)
+    let p addr a
+    load v p
+end"#;
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+        let checker = Checker::check(&ast);
+        assert!(checker.errors.is_empty(), "{:?}", checker.errors);
+
+        let locals = &checker.locals["main"].variables;
+        let types = ["a:i32", "big:i64", "b:i32", "f:f64", "c:u8", "s:str", "l:u64", "p:ptr<i32>", "v:i32"];
+        for expected in types {
+            let (name, data_type) = expected.split_once(':').unwrap_or_default();
+            assert_eq!(locals.get(name).map(|t| t.to_string()).as_deref(), Some(data_type), "{}", name);
+        }
+
+        // literals only get a width from what they meet
+        let one = LangType::Primitive(crate::types::primitive_type::PrimitiveType::int("1"));
+        assert_eq!(checker.type_of(&one), None);
+        assert_eq!(checker.expected_type(&one, Some(&DataType::U8)), Some(DataType::U8));
+
+        return Ok(());
+    }
+
+    #[test]
+    fn checker_errors() -> Result<()> {
+        let cases = [
+            ("let a 1\n    add a a true", "Mismatched operand types i32 and bool"),
+            ("let a:u8 300", "Integer literal 300 does not fit in u8"),
+            ("let a:u8 true", "Cannot assign bool to a of type u8"),
+            ("let a 1\n    let a:i64 2", "Variable a is already declared"),
+            ("print(missing)", "Unknown variable missing"),
+            ("nothing(1)", "Unknown function nothing"),
+            ("let a 1\n    if a < 1.5:\n    end", "Mismatched operand types i32 and f64"),
+            ("let f 1.5\n    match f:\n    else:\n    end", "Cannot match on f64"),
+            ("let f 1.5\n    itof g f", "itof expects an integer, got f64"),
+            ("let f 1.5\n    bitcast g f as i32", "Cannot bitcast f64 to i32"),
+            ("len(1, 2)", "len expects 1 arguments, got 2"),
+            ("twice(1, 2)", "Function twice expects 1 arguments, got 2"),
+            ("twice(true)", "Argument 1 of twice must be i32, got bool"),
+            ("return \"a\"", "Cannot return str from a function returning i32"),
+            ("let b true\n    cast x b as f64", "Cannot cast bool to f64"),
+            ("let a 1\n    match a:\n    1:\n        print(a)\n    end", "Match on i32 needs an else arm"),
+            (
+                "let c Color.Red\n    match c:\n    Color.Red:\n        print(c)\n    end",
+                "Non-exhaustive match, missing Color.Green",
+            ),
+            (
+                "let a 1\n    match a:\n    1, 2, 1:\n        print(a)\n    else:\n    end",
+                "Unreachable match arm, Primitive(PrimitiveType { value: \"1\", primitive: Int, radix: 10 }) is already covered",
+            ),
+            (
+                "let a 1\n    let b 2\n    match a:\n    b:\n        print(a)\n    else:\n    end",
+                "Match patterns must be constants, got Var(VarType { name: \"b\", data_type: None })",
+            ),
+        ];
+        for (body, expected) in cases {
+            let input = format!(
+                "enum Color u8:\n    Red\n    Green\nend\nfn twice n:i32 -> i32:\n    mul n n 2\n    return n\nend\nfn main:\n    {}\nend",
+                body
+            );
+            assert_eq!(errors(&input)?, vec![expected.to_string()], "{}", body);
+        }
+        assert_eq!(errors("fn f -> void:\n    return 1\nend")?, vec!["Cannot return a value from a void function"]);
+
+        return Ok(());
+    }
+}
@@ -1,14 +1,26 @@
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use anyhow::{anyhow, Result, Ok};
 
+use inkwell::attributes::AttributeLoc;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
-use inkwell::execution_engine::{ExecutionEngine, JitFunction};
-use inkwell::values::{BasicMetadataValueEnum, IntValue, FunctionValue, PointerValue, BasicValueEnum, BasicValue};
-use inkwell::types::BasicMetadataTypeEnum;
-use inkwell::module::Module;
-use inkwell::basic_block::BasicBlock;
+use inkwell::debug_info::{
+    AsDIScope, DICompileUnit, DIFlags, DIFlagsConstants, DISubprogram, DIType, DWARFEmissionKind, DWARFSourceLanguage,
+    DebugInfoBuilder,
+};
+use inkwell::values::{BasicMetadataValueEnum, IntValue, FloatValue, FunctionValue, PointerValue, BasicValueEnum, BasicValue, InstructionValue};
+use inkwell::types::{BasicMetadataTypeEnum, BasicType, BasicTypeEnum, FunctionType};
+use inkwell::module::{FlagBehavior, Linkage, Module};
+use inkwell::targets::{TargetMachine, TargetTriple};
+use inkwell::{AddressSpace, FloatPredicate, InlineAsmDialect, IntPredicate};
 
+use crate::checker::{literal_type, Scope};
+use crate::lexer::Span;
+use crate::runtime::Runtime;
+use crate::types::asm_type::AsmType;
+use crate::types::call_type::CallType;
+use crate::types::data_type::DataType;
 use crate::types::func_type::FuncType;
 use crate::types::if_type::IfType;
 use crate::types::lang_type::LangType;
@@ -16,131 +28,2088 @@ use crate::types::op_type::OpType;
 use crate::types::op_type::Operation;
 use crate::types::primitive_type::PrimitiveType;
 use crate::types::primitive_type::Primitives;
-use crate::types::var_type::VarType;
+use crate::types::return_type::ReturnType;
+use crate::types::struct_type::StructType;
+use crate::types::enum_type::EnumType;
+use crate::types::match_type::MatchType;
 
-struct CodeGen<'ctx> {
+/// LLVM's `ccc` calling convention id.
+const C_CALL_CONV: u32 = 0;
+
+// DWARF base type encodings
+const DW_ATE_BOOLEAN: u32 = 0x02;
+const DW_ATE_FLOAT: u32 = 0x04;
+const DW_ATE_SIGNED: u32 = 0x05;
+const DW_ATE_SIGNED_CHAR: u32 = 0x06;
+const DW_ATE_UNSIGNED: u32 = 0x07;
+
+/// Byte layout of a struct. Structs are emitted as packed LLVM structs with explicit
+/// padding so `packed` and `align(N)` don't depend on the target's defaults.
+#[derive(Clone, Debug)]
+struct StructLayout {
+    /// LLVM element index of every declared field
+    indices: Vec<u32>,
+    /// byte offset of every declared field
+    offsets: Vec<u64>,
+    size: u64,
+    align: u64,
+}
+
+/// DWARF of a `-g` build.
+struct DebugInfo<'ctx> {
+    builder: DebugInfoBuilder<'ctx>,
+    unit: DICompileUnit<'ctx>,
+    optimized: bool,
+    /// subprogram of the function being compiled
+    scope: Option<DISubprogram<'ctx>>,
+    /// line of the statement being compiled
+    line: u32,
+    types: HashMap<String, DIType<'ctx>>,
+}
+
+pub struct CodeGen<'ctx> {
     context: &'ctx Context,
     module: Module<'ctx>,
     builder: Builder<'ctx>,
-    execution_engine: ExecutionEngine<'ctx>,
 
-    variables: HashMap<String, PointerValue<'ctx>>,
+    variables: HashMap<String, (PointerValue<'ctx>, DataType)>,
+    constants: HashMap<String, PrimitiveType>, // globals plus the ones of the current function
+    globals: HashMap<String, PrimitiveType>,
+    functions: HashMap<String, FuncType>,
+    structs: HashMap<String, StructType>,
+    struct_types: HashMap<String, inkwell::types::StructType<'ctx>>,
+    layouts: HashMap<String, StructLayout>,
+    enums: HashMap<String, EnumType>,
+    return_type: DataType,
+    triple: String,
+    freestanding: bool,
+    bounds_checks: bool,
 
     module_ast: Vec<LangType>,
+
+    // REPL entries keep their variables in globals shared by the modules of one JIT
+    repl_entry: Option<String>,
+    repl_variables: Vec<(String, DataType)>,
+
+    // source file and whether it gets optimized, for `-g`
+    debug_source: Option<(PathBuf, bool)>,
+    debug: Option<DebugInfo<'ctx>>,
+}
+
+impl Scope for CodeGen<'_> {
+    fn variable(&self, name: &str) -> Option<DataType> {
+        self.variables.get(name).map(|(_, t)| t.clone())
+    }
+
+    fn constant(&self, name: &str) -> Option<&PrimitiveType> {
+        self.constants.get(name)
+    }
+
+    fn function(&self, name: &str) -> Option<&FuncType> {
+        self.functions.get(name)
+    }
+
+    fn struct_type(&self, name: &str) -> Option<&StructType> {
+        self.structs.get(name)
+    }
+
+    fn enum_type(&self, name: &str) -> Option<&EnumType> {
+        self.enums.get(name)
+    }
 }
 
 impl<'ctx> CodeGen<'ctx> {
+    pub fn new(context: &'ctx Context) -> Self {
+        Self {
+            context,
+            module: context.create_module("main"),
+            builder: context.create_builder(),
+            variables: HashMap::new(),
+            constants: HashMap::new(),
+            globals: HashMap::new(),
+            functions: HashMap::new(),
+            structs: HashMap::new(),
+            struct_types: HashMap::new(),
+            layouts: HashMap::new(),
+            enums: HashMap::new(),
+            return_type: DataType::Void,
+            triple: TargetMachine::get_default_triple().as_str().to_string_lossy().to_string(),
+            freestanding: false,
+            bounds_checks: false,
+            module_ast: vec![],
+            repl_entry: None,
+            repl_variables: vec![],
+            debug_source: None,
+            debug: None,
+        }
+    }
+
+    /// Target triple the module is generated for, defaults to the host.
+    pub fn set_triple(&mut self, triple: &str) {
+        self.triple = triple.to_string();
+    }
+
+    /// Freestanding modules get a `_start` entry and may not use libc-backed builtins.
+    pub fn set_freestanding(&mut self, freestanding: bool) {
+        self.freestanding = freestanding;
+    }
+
+    /// Trap on out of bounds array indices, meant for debug builds.
+    pub fn set_bounds_checks(&mut self, bounds_checks: bool) {
+        self.bounds_checks = bounds_checks;
+    }
+
+    /// Emit DWARF for `source`, parsed with spans. `optimized` tells debuggers that values may be gone.
+    pub fn set_debug_info(&mut self, source: &Path, optimized: bool) {
+        self.debug_source = Some((source.to_path_buf(), optimized));
+    }
+
     pub fn compile_module(&mut self, name: String, ast: Vec<LangType>) -> Result<Module<'ctx>> {
         self.module = self.context.create_module(&name.to_string());
+        self.module.set_triple(&TargetTriple::create(&self.triple));
+        self.debug = self.debug_source.clone().map(|(source, optimized)| self.create_debug_info(&source, optimized));
         self.module_ast = ast;
 
-        for func_type in self.module_ast.clone() {
-            if let LangType::Func(func) = func_type {
-                let new_fn = self.compile_fn(func)?;
-                self.module.add_function(new_fn.get_name().to_str()?, new_fn.get_type(), Option::None);
+        // types come first, prototypes and bodies refer to them
+        for lang_t in &self.module_ast {
+            let name = match lang_t {
+                LangType::Struct(struct_t) => &struct_t.name,
+                LangType::Enum(enum_t) => &enum_t.name,
+                _ => continue,
+            };
+            if self.structs.contains_key(name) || self.enums.contains_key(name) || DataType::from_name(name).is_some() {
+                return Err(anyhow!("Type {} is already declared", name));
+            }
+
+            match lang_t {
+                LangType::Struct(struct_t) => {
+                    let llvm_type = self.context.opaque_struct_type(&struct_t.name);
+                    self.struct_types.insert(struct_t.name.clone(), llvm_type);
+                    self.structs.insert(struct_t.name.clone(), struct_t.clone());
+                }
+                LangType::Enum(enum_t) => {
+                    if let Some(variant) = enum_t.variants.iter().find(|v| !enum_t.data_type.fits(v.value)) {
+                        return Err(anyhow!(
+                            "Value {} of {}.{} does not fit in {}",
+                            variant.value,
+                            enum_t.name,
+                            variant.name,
+                            enum_t.data_type
+                        ));
+                    }
+                    self.enums.insert(enum_t.name.clone(), enum_t.clone());
+                }
+                _ => {}
             }
-            else if let LangType::Const(con) = func_type {
-                // To do: Const handling
+        }
+        for name in self.structs.keys().cloned().collect::<Vec<String>>() {
+            self.compile_struct(&name, &mut vec![])?;
+        }
+
+        // declare every prototype and constant first so uses don't depend on declaration order
+        for lang_t in self.module_ast.clone() {
+            match lang_t {
+                LangType::Func(func) => {
+                    self.compile_prototype(&func)?;
+                    self.functions.insert(func.name.clone(), func);
+                }
+                LangType::Const(con) => {
+                    self.globals.insert(con.name, con.value);
+                }
+                LangType::Struct(_) | LangType::Enum(_) | LangType::Comment(_) => {}
+                _ => return Err(anyhow!("Expression outside of function!")),
             }
-            else {
-                return Err(anyhow!("Expression outside of function!"));
+        }
+
+        for lang_t in self.module_ast.clone() {
+            if let LangType::Func(func) = lang_t {
+                self.compile_fn(func)?;
             }
         }
 
+        if self.freestanding {
+            self.compile_start()?;
+        }
+
+        if let Some(debug) = &self.debug {
+            debug.builder.finalize();
+        }
+
         Ok(self.module.to_owned())
     }
 
-    fn compile_fn(&mut self, func_type: FuncType) -> Result<FunctionValue<'ctx>> {
+    fn create_debug_info(&self, source: &Path, optimized: bool) -> DebugInfo<'ctx> {
+        // debuggers find the source through the absolute directory
+        let source = source.canonicalize().unwrap_or_else(|_| source.to_path_buf());
+        let filename = source.file_name().unwrap_or_default().to_string_lossy();
+        let directory = source.parent().unwrap_or(Path::new("")).to_string_lossy();
 
-        //-----------
-        // Prototype
-        //-----------
+        // LLVM drops debug info without a version flag
+        let i32_type = self.context.i32_type();
+        self.module
+            .add_basic_value_flag("Debug Info Version", FlagBehavior::Warning, i32_type.const_int(3, false));
+        self.module.add_basic_value_flag("Dwarf Version", FlagBehavior::Warning, i32_type.const_int(4, false));
 
-        let ret_type = self.context.i32_type();
-        let args_types = std::iter::repeat(ret_type)
-            .take(func_type.param.len())
-            .map(|f| f.into())
-            .collect::<Vec<BasicMetadataTypeEnum>>();
-        let args_types = args_types.as_slice();
-        
-        let fn_type = self.context.i32_type().fn_type(&args_types, false);
-        let fn_val = self.module.add_function(&func_type.name, fn_type, None);
+        let (builder, unit) = self.module.create_debug_info_builder(
+            true,
+            DWARFSourceLanguage::C,
+            &filename,
+            &directory,
+            "modern_asm",
+            optimized,
+            "",
+            0,
+            "",
+            DWARFEmissionKind::Full,
+            0,
+            false,
+            false,
+            "",
+            "",
+        );
 
-        // build variables map
-        self.variables.reserve(func_type.param.len());
+        DebugInfo {
+            builder,
+            unit,
+            optimized,
+            scope: None,
+            line: 0,
+            types: HashMap::new(),
+        }
+    }
 
-        for (i, arg) in fn_val.get_param_iter().enumerate() {
-            let arg_name = func_type.param[i].name.as_str();
-            let alloca = self.create_entry_block_alloca(fn_val.get_first_basic_block().unwrap(),arg_name);
+    /// Compiles one REPL entry: `items` are the definitions entered so far, `body` becomes the
+    /// exported void function `entry`. Its variables are globals named `repl.<name>`; the ones in
+    /// `variables` are defined by earlier entries, new ones are appended on success.
+    pub fn compile_repl_entry(
+        &mut self,
+        entry: &str,
+        mut items: Vec<LangType>,
+        body: Vec<LangType>,
+        variables: &mut Vec<(String, DataType)>,
+    ) -> Result<Module<'ctx>> {
+        let mut entry_fn = FuncType::new(entry.to_string(), vec![], DataType::Void, body);
+        entry_fn.is_export = true;
+        items.append(&mut vec![LangType::Func(entry_fn)]);
+
+        self.repl_entry = Some(entry.to_string());
+        self.repl_variables = variables.clone();
+        let module = self.compile_module(entry.to_string(), items);
+        self.repl_entry = None;
+
+        let variables_after = std::mem::take(&mut self.repl_variables);
+        if module.is_ok() {
+            *variables = variables_after;
+        }
+        module
+    }
+
+    /// Lays out `name` and sets the body of its LLVM type; `visiting` catches structs containing themselves.
+    fn compile_struct(&mut self, name: &str, visiting: &mut Vec<String>) -> Result<StructLayout> {
+        if let Some(layout) = self.layouts.get(name) {
+            return Ok(layout.clone());
+        }
+        if visiting.iter().any(|v| v == name) {
+            return Err(anyhow!("Struct {} contains itself", name));
+        }
+        let struct_t = self
+            .structs
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown type {}", name))?;
+        visiting.push(name.to_string());
+
+        let i8_type = self.context.i8_type();
+        let mut body: Vec<BasicTypeEnum> = vec![];
+        let mut indices = vec![];
+        let mut offsets = vec![];
+        let mut offset = 0;
+        let mut align = 1;
+
+        for field in &struct_t.fields {
+            let data_type = field.data_type.clone().unwrap_or_default();
+            let (size, field_align) = self.type_layout(&data_type, visiting)?;
+            let field_align = if struct_t.packed { 1 } else { field_align };
 
-            self.builder.build_store(alloca, arg).unwrap();
+            let padding = (field_align - offset % field_align) % field_align;
+            if padding > 0 {
+                body.append(&mut vec![i8_type.array_type(padding as u32).as_basic_type_enum()]);
+                offset += padding;
+            }
+
+            indices.append(&mut vec![body.len() as u32]);
+            offsets.append(&mut vec![offset]);
+            body.append(&mut vec![self.basic_type(&data_type)?]);
+            offset += size;
+            align = align.max(field_align);
+        }
+
+        if let Some(n) = struct_t.align {
+            align = align.max(n as u64);
+        }
+        // trailing padding keeps every element of an array of this struct aligned
+        let padding = (align - offset % align) % align;
+        if padding > 0 {
+            body.append(&mut vec![i8_type.array_type(padding as u32).as_basic_type_enum()]);
+            offset += padding;
+        }
+
+        self.struct_types[name].set_body(&body, true);
+        visiting.pop();
+
+        let layout = StructLayout {
+            indices,
+            offsets,
+            size: offset,
+            align,
+        };
+        self.layouts.insert(name.to_string(), layout.clone());
+
+        Ok(layout)
+    }
+
+    /// Size and alignment in bytes, laying out structs on first use.
+    fn type_layout(&mut self, data_type: &DataType, visiting: &mut Vec<String>) -> Result<(u64, u64)> {
+        Ok(match data_type {
+            DataType::Struct(name) => {
+                let layout = self.compile_struct(name, visiting)?;
+                (layout.size, layout.align)
+            }
+            DataType::Array(elem, len) => {
+                let (size, align) = self.type_layout(elem, visiting)?;
+                (size * *len as u64, align)
+            }
+            DataType::Bool => (1, 1),
+            DataType::Void => return Err(anyhow!("void is not a value type")),
+            // every supported target has 64-bit pointers and naturally aligned integers
+            other => {
+                let size = other.bit_width() as u64 / 8;
+                (size, size)
+            }
+        })
+    }
 
-            self.variables.insert(func_type.param[i].name.clone(), alloca);
+    fn compile_prototype(&mut self, func_type: &FuncType) -> Result<FunctionValue<'ctx>> {
+        let aggregate = |t: &DataType| t.is_array() || t.is_struct();
+        let by_value_aggregate = aggregate(&func_type.ret)
+            || func_type.param.iter().any(|p| p.data_type.as_ref().is_some_and(aggregate));
+        if by_value_aggregate && (func_type.is_extern || func_type.is_export) {
+            return Err(anyhow!("Function {} must pass arrays and structs by pointer to match the C ABI", func_type.name));
+        }
+
+        let args_types = func_type
+            .param
+            .iter()
+            .map(|p| Ok(self.basic_type(&p.data_type.clone().unwrap_or_default())?.into()))
+            .collect::<Result<Vec<BasicMetadataTypeEnum>>>()?;
+
+        let fn_type = self.fn_type(&func_type.ret, &args_types, func_type.variadic)?;
+
+        if let Some(fn_val) = self.module.get_function(&func_type.name) {
+            if fn_val.get_type() != fn_type {
+                return Err(anyhow!("Conflicting declarations of function {}", func_type.name));
+            }
+            return Ok(fn_val);
+        }
+
+        // only extern, exported and entry functions are visible outside the module
+        let visible = match &self.repl_entry {
+            // REPL modules share one JIT, redefinitions must not clash there
+            Some(entry) => func_type.is_extern || func_type.name == *entry,
+            None => func_type.is_extern || func_type.is_export || func_type.name == "main",
+        };
+        let linkage = if visible { Linkage::External } else { Linkage::Internal };
+        let fn_val = self.module.add_function(&func_type.name, fn_type, Some(linkage));
+
+        if func_type.is_export {
+            fn_val.set_call_conventions(C_CALL_CONV);
         }
 
         // set arguments names
         for (i, arg) in fn_val.get_param_iter().enumerate() {
-            arg.into_float_value().set_name(func_type.param[i].name.as_str());
+            arg.set_name(func_type.param[i].name.as_str());
         }
 
+        Ok(fn_val)
+    }
+
+    fn compile_fn(&mut self, func_type: FuncType) -> Result<FunctionValue<'ctx>> {
+
+        //-----------
+        // Prototype
+        //-----------
+
+        let fn_val = self.compile_prototype(&func_type)?;
+
         // got external function, returning only compiled prototype
-        if func_type.body.is_empty() {
+        if func_type.is_extern {
             return Ok(fn_val);
         }
 
         let entry = self.context.append_basic_block(fn_val, "entry");
 
         self.builder.position_at_end(entry);
+        self.debug_function(fn_val, &func_type)?;
+
+        self.return_type = func_type.ret.clone();
+
+        // consts of other functions are out of scope
+        self.constants = self.globals.clone();
+
+        // build variables map
+        self.variables.clear();
+        self.variables.reserve(func_type.param.len());
 
+        for (i, arg) in fn_val.get_param_iter().enumerate() {
+            let arg_name = func_type.param[i].name.as_str();
+            let arg_type = func_type.param[i].data_type.clone().unwrap_or_default();
+            let alloca = self.create_entry_block_alloca(fn_val, arg_name, &arg_type)?;
+
+            self.builder.build_store(alloca, arg)?;
+            self.debug_variable(alloca, arg_name, &arg_type, Some(i as u32 + 1))?;
+
+            self.variables.insert(func_type.param[i].name.clone(), (alloca, arg_type));
+        }
+
+        if self.is_repl_entry(&func_type.name) {
+            for (name, data_type) in self.repl_variables.clone() {
+                let global = self.module.add_global(self.basic_type(&data_type)?, None, &format!("repl.{}", name));
+                global.set_linkage(Linkage::External);
+                self.variables.insert(name, (global.as_pointer_value(), data_type));
+            }
+        }
 
         //------------
         // Body
         //------------
 
-        //To do: Compile Body
+        self.compile_block(func_type.body)?;
+
+        if self.no_terminator() {
+            self.build_default_return(&func_type.ret)?;
+        }
+
+        // the next function may have no subprogram, e.g. _start
+        self.builder.unset_current_debug_location();
+        if let Some(debug) = &mut self.debug {
+            debug.scope = None;
+        }
+
+        if !fn_val.verify(true) {
+            return Err(anyhow!("Invalid generated function {}", func_type.name));
+        }
 
         Ok(fn_val)
     }
 
-    fn create_entry_block_alloca(&self,entry: BasicBlock<'ctx>, name: &str) -> PointerValue<'ctx> {
-        let builder = self.context.create_builder();
+    fn compile_block(&mut self, body: Vec<LangType>) -> Result<()> {
+        for lang_t in body {
+            // everything after a terminator is unreachable
+            if !self.no_terminator() {
+                break;
+            }
+            self.compile_stmt(lang_t)?;
+        }
 
-        match entry.get_first_instruction() {
-            Some(first_instr) => builder.position_before(&first_instr),
-            None => builder.position_at_end(entry),
+        Ok(())
+    }
+
+    fn compile_stmt(&mut self, stmt_type: LangType) -> Result<()> {
+        match stmt_type {
+            LangType::Op(op) if matches!(op.op, Operation::Assign) => self.compile_assign(op),
+            LangType::Op(op) if matches!(op.op, Operation::Store) => self.compile_store(op),
+            LangType::Call(call) => self.compile_call(call).map(|_| ()),
+            LangType::If(if_t) => self.compile_if(if_t),
+            LangType::Match(match_t) => self.compile_match(match_t),
+            LangType::Return(ret) => self.compile_return(ret),
+            LangType::Asm(asm) => self.compile_asm(asm),
+            LangType::Const(con) => {
+                self.constants.insert(con.name, con.value);
+                Ok(())
+            }
+            LangType::Comment(_) => Ok(()),
+            LangType::Location(span) => {
+                self.set_debug_location(span);
+                Ok(())
+            }
+            other => Err(anyhow!("Unexpected statement {:?}", other)),
+        }
+    }
+
+    fn compile_assign(&mut self, op: OpType) -> Result<()> {
+        let (name, declared_type) = match *op.lhs {
+            LangType::Var(var) => (var.name, var.data_type),
+            other => return Err(anyhow!("Cannot assign to {:?}", other)),
+        };
+
+        if self.constants.contains_key(&name) {
+            return Err(anyhow!("Cannot assign to constant {}", name));
+        }
+
+        // typed declaration, zeroed without a value, e.g. `let buf [u8; 64]`
+        if let Some(data_type) = declared_type {
+            if self.variables.contains_key(&name) {
+                return Err(anyhow!("Variable {} is already declared", name));
+            }
+
+            let value = match *op.rhs {
+                LangType::Undefined => self.basic_type(&data_type)?.const_zero(),
+                rhs => {
+                    let (value, value_type) = self.compile_expr(rhs, Some(&data_type))?;
+                    if value_type != data_type {
+                        return Err(anyhow!("Cannot assign {} to {} of type {}", value_type, name, data_type));
+                    }
+                    value
+                }
+            };
+            let alloca = self.declare_variable(&name, &data_type)?;
+            self.builder.build_store(alloca, value)?;
+            self.variables.insert(name, (alloca, data_type));
+
+            return Ok(());
+        }
+
+        match self.variables.get(&name).cloned() {
+            Some((ptr, data_type)) => {
+                let (value, value_type) = self.compile_expr(*op.rhs, Some(&data_type))?;
+                if value_type != data_type {
+                    return Err(anyhow!("Cannot assign {} to {} of type {}", value_type, name, data_type));
+                }
+                self.builder.build_store(ptr, value)?;
+            }
+            None => {
+                let (value, data_type) = self.compile_expr(*op.rhs, None)?;
+                let alloca = self.declare_variable(&name, &data_type)?;
+                self.builder.build_store(alloca, value)?;
+                self.variables.insert(name, (alloca, data_type));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn compile_if(&mut self, if_t: IfType) -> Result<()> {
+        let (cond, cond_type) = self.compile_expr(*if_t.condition, None)?;
+        if cond_type != DataType::Bool {
+            return Err(anyhow!("If condition must be bool, got {}", cond_type));
+        }
+
+        let fn_val = self.current_fn()?;
+        let then_bb = self.context.append_basic_block(fn_val, "then");
+        let else_bb = self.context.append_basic_block(fn_val, "else");
+        let merge_bb = self.context.append_basic_block(fn_val, "ifcont");
+
+        self.builder.build_conditional_branch(cond.into_int_value(), then_bb, else_bb)?;
+
+        self.builder.position_at_end(then_bb);
+        self.compile_block(if_t.body)?;
+        if self.no_terminator() {
+            self.builder.build_unconditional_branch(merge_bb)?;
+        }
+
+        self.builder.position_at_end(else_bb);
+        self.compile_block(if_t.else_body)?;
+        if self.no_terminator() {
+            self.builder.build_unconditional_branch(merge_bb)?;
+        }
+
+        self.builder.position_at_end(merge_bb);
+
+        Ok(())
+    }
+
+    fn compile_match(&mut self, match_t: MatchType) -> Result<()> {
+        let (value, value_type) = self.compile_expr(*match_t.value.clone(), None)?;
+        if !value_type.is_int() {
+            return Err(anyhow!("Cannot match on {}", value_type));
+        }
+
+        self.check_match(&match_t, &value_type)?;
+
+        let fn_val = self.current_fn()?;
+        let mut cases = vec![];
+        let mut arm_bbs = vec![];
+
+        for arm in &match_t.arms {
+            let arm_bb = self.context.append_basic_block(fn_val, "arm");
+            arm_bbs.append(&mut vec![arm_bb]);
+            for pattern in &arm.patterns {
+                let (case, _) = self.compile_expr(pattern.clone(), Some(&value_type))?;
+                cases.append(&mut vec![(case.into_int_value(), arm_bb)]);
+            }
+        }
+
+        let default_bb = self.context.append_basic_block(fn_val, "default");
+        let merge_bb = self.context.append_basic_block(fn_val, "matchcont");
+
+        self.builder.build_switch(value.into_int_value(), default_bb, &cases)?;
+
+        for (arm, arm_bb) in match_t.arms.into_iter().zip(arm_bbs) {
+            self.builder.position_at_end(arm_bb);
+            self.compile_block(arm.body)?;
+            if self.no_terminator() {
+                self.builder.build_unconditional_branch(merge_bb)?;
+            }
+        }
+
+        self.builder.position_at_end(default_bb);
+        match match_t.default {
+            Some(body) => {
+                self.compile_block(body)?;
+                if self.no_terminator() {
+                    self.builder.build_unconditional_branch(merge_bb)?;
+                }
+            }
+            // a value outside the enum, e.g. from a cast, fails like in the interpreter and VM
+            None => self.build_trap()?,
+        }
+
+        self.builder.position_at_end(merge_bb);
+
+        Ok(())
+    }
+
+    /// `base` names an enum unless a variable shadows it.
+    fn enum_name(&self, base: &LangType) -> Option<String> {
+        self.enum_base(base).map(|enum_t| enum_t.name.clone())
+    }
+
+    fn compile_return(&mut self, ret: ReturnType) -> Result<()> {
+        let return_type = self.return_type.clone();
+
+        match (*ret.value, &return_type) {
+            (LangType::Undefined, DataType::Void) => {
+                self.builder.build_return(None)?;
+            }
+            (LangType::Undefined, _) => {
+                return Err(anyhow!("Missing return value of type {}", return_type));
+            }
+            (_, DataType::Void) => {
+                return Err(anyhow!("Cannot return a value from a void function"));
+            }
+            (value, _) => {
+                let (value, value_type) = self.compile_expr(value, Some(&return_type))?;
+                if value_type != return_type {
+                    return Err(anyhow!("Cannot return {} from a function returning {}", value_type, return_type));
+                }
+                self.builder.build_return(Some(&value))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn compile_asm(&mut self, asm: AsmType) -> Result<()> {
+        let mut constraints = vec![];
+
+        let mut output_ptrs = vec![];
+        let mut output_types: Vec<BasicTypeEnum> = vec![];
+        for output in asm.outputs {
+            let name = match output.value {
+                LangType::Var(var) => var.name,
+                other => return Err(anyhow!("Asm output must be a variable, got {:?}", other)),
+            };
+            let (ptr, data_type) = self
+                .variables
+                .get(&name)
+                .cloned()
+                .ok_or_else(|| anyhow!("Unknown asm output variable {}", name))?;
+
+            output_ptrs.append(&mut vec![ptr]);
+            output_types.append(&mut vec![self.basic_type(&data_type)?]);
+            constraints.append(&mut vec![output.constraint]);
+        }
+
+        let mut args: Vec<BasicMetadataValueEnum> = vec![];
+        let mut arg_types: Vec<BasicMetadataTypeEnum> = vec![];
+        for input in asm.inputs {
+            let (value, data_type) = self.compile_expr(input.value, None)?;
+
+            args.append(&mut vec![value.into()]);
+            arg_types.append(&mut vec![self.basic_type(&data_type)?.into()]);
+            constraints.append(&mut vec![input.constraint]);
+        }
+
+        for clobber in asm.clobbers {
+            constraints.append(&mut vec![format!("~{{{}}}", clobber)]);
+        }
+
+        // several outputs are returned as one struct
+        let fn_type = match output_types.len() {
+            0 => self.context.void_type().fn_type(&arg_types, false),
+            1 => output_types[0].fn_type(&arg_types, false),
+            _ => self.context.struct_type(&output_types, false).fn_type(&arg_types, false),
+        };
+
+        let dialect = if asm.intel { InlineAsmDialect::Intel } else { InlineAsmDialect::ATT };
+        let asm_ptr = self.context.create_inline_asm(
+            fn_type,
+            asm.lines.join("\n"),
+            constraints.join(","),
+            true,
+            false,
+            Some(dialect),
+            false,
+        );
+
+        let call_site = self.builder.build_indirect_call(fn_type, asm_ptr, &args, "asm")?;
+
+        if let Some(result) = call_site.try_as_basic_value().left() {
+            if output_ptrs.len() == 1 {
+                self.builder.build_store(output_ptrs[0], result)?;
+            } else {
+                for (i, ptr) in output_ptrs.into_iter().enumerate() {
+                    let value = self.builder.build_extract_value(result.into_struct_value(), i as u32, "asm_out")?;
+                    self.builder.build_store(ptr, value)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn compile_call(&mut self, call: CallType) -> Result<Option<(BasicValueEnum<'ctx>, DataType)>> {
+        if call.name == "print" && !self.functions.contains_key("print") {
+            self.compile_print(call.param)?;
+            return Ok(None);
+        }
+        if call.name == "syscall" && !self.functions.contains_key("syscall") {
+            return self.compile_syscall(call.param).map(Some);
+        }
+        if (call.name == "len" || call.name == "substr") && !self.functions.contains_key(&call.name) {
+            return self.compile_str_call(call).map(Some);
+        }
+
+        let func = self
+            .functions
+            .get(&call.name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown function {}", call.name))?;
+        let fn_val = self
+            .module
+            .get_function(&call.name)
+            .ok_or_else(|| anyhow!("Unknown function {}", call.name))?;
+
+        if call.param.len() < func.param.len() || (!func.variadic && call.param.len() > func.param.len()) {
+            return Err(anyhow!(
+                "Function {} expects {} arguments, got {}",
+                call.name,
+                func.param.len(),
+                call.param.len()
+            ));
+        }
+
+        let mut args: Vec<BasicMetadataValueEnum> = vec![];
+        for (i, arg) in call.param.into_iter().enumerate() {
+            let expected = func.param.get(i).map(|p| p.data_type.clone().unwrap_or_default());
+            let (value, value_type) = self.compile_expr(arg, expected.as_ref())?;
+            if let Some(expected) = expected {
+                if value_type != expected {
+                    return Err(anyhow!(
+                        "Argument {} of {} must be {}, got {}",
+                        i + 1,
+                        call.name,
+                        expected,
+                        value_type
+                    ));
+                }
+            }
+            args.append(&mut vec![value.into()]);
+        }
+
+        let call_site = self.builder.build_call(fn_val, &args, "call")?;
+
+        Ok(call_site.try_as_basic_value().left().map(|v| (v, func.ret)))
+    }
+
+    /// `len(s) -> u64` and `substr(s, start, count) -> str`.
+    fn compile_str_call(&mut self, call: CallType) -> Result<(BasicValueEnum<'ctx>, DataType)> {
+        let expected = if call.name == "len" { 1 } else { 3 };
+        if call.param.len() != expected {
+            return Err(anyhow!("{} expects {} arguments, got {}", call.name, expected, call.param.len()));
+        }
+        if call.name == "substr" && self.freestanding {
+            return Err(anyhow!("substr allocates with malloc and is not available in freestanding builds"));
+        }
+
+        let mut params = call.param.into_iter();
+        let (s, s_type) = self.compile_expr(params.next().unwrap_or(LangType::Undefined), None)?;
+        if s_type != DataType::Str {
+            return Err(anyhow!("{} expects a str, got {}", call.name, s_type));
+        }
+
+        let mut args: Vec<BasicMetadataValueEnum> = vec![s.into()];
+        for param in params {
+            let (value, data_type) = self.compile_expr(param, Some(&DataType::U64))?;
+            if !data_type.is_int() {
+                return Err(anyhow!("{} expects integer bounds, got {}", call.name, data_type));
+            }
+            let value = self.builder.build_int_cast_sign_flag(
+                value.into_int_value(),
+                self.context.i64_type(),
+                data_type.is_signed(),
+                "bound",
+            )?;
+            args.append(&mut vec![value.into()]);
+        }
+
+        let runtime = Runtime::new(self.context, &self.module);
+        let (fn_val, data_type) = if call.name == "len" {
+            (runtime.str_len()?, DataType::U64)
+        } else {
+            (runtime.str_sub()?, DataType::Str)
+        };
+        let value = self
+            .builder
+            .build_call(fn_val, &args, &call.name)?
+            .try_as_basic_value()
+            .left()
+            .ok_or_else(|| anyhow!("{} did not produce a value", call.name))?;
+
+        Ok((value, data_type))
+    }
+
+    /// `add` concatenates strings, comparisons compare their contents.
+    fn compile_str_op(&mut self, op: Operation, lhs: BasicValueEnum<'ctx>, rhs: BasicValueEnum<'ctx>) -> Result<(BasicValueEnum<'ctx>, DataType)> {
+        let runtime = Runtime::new(self.context, &self.module);
+        let args: [BasicMetadataValueEnum; 2] = [lhs.into(), rhs.into()];
+
+        if matches!(op, Operation::Add) {
+            if self.freestanding {
+                return Err(anyhow!("String concatenation allocates with malloc and is not available in freestanding builds"));
+            }
+            let value = self
+                .builder
+                .build_call(runtime.str_concat()?, &args, "concat")?
+                .try_as_basic_value()
+                .left()
+                .ok_or_else(|| anyhow!("concat did not produce a value"))?;
+            return Ok((value, DataType::Str));
+        }
+
+        let pred = match op {
+            Operation::Equal => IntPredicate::EQ,
+            Operation::NotEqual => IntPredicate::NE,
+            Operation::LessThan => IntPredicate::SLT,
+            Operation::GreaterThan => IntPredicate::SGT,
+            other => return Err(anyhow!("Operation {:?} is not supported for str", other)),
+        };
+        let order = self
+            .builder
+            .build_call(runtime.str_cmp()?, &args, "cmp")?
+            .try_as_basic_value()
+            .left()
+            .ok_or_else(|| anyhow!("cmp did not produce a value"))?
+            .into_int_value();
+        let value = self
+            .builder
+            .build_int_compare(pred, order, self.context.i32_type().const_zero(), "strcmp")?;
+
+        Ok((value.as_basic_value_enum(), DataType::Bool))
+    }
+
+    /// `syscall(num, a1, ..., a6)` lowered to the kernel's register convention.
+    fn compile_syscall(&mut self, params: Vec<LangType>) -> Result<(BasicValueEnum<'ctx>, DataType)> {
+        if params.is_empty() || params.len() > 7 {
+            return Err(anyhow!("syscall expects a number and up to 6 arguments, got {}", params.len()));
+        }
+
+        let i64_type = self.context.i64_type();
+        let mut args = vec![];
+
+        for param in params {
+            let (value, data_type) = self.compile_expr(param, Some(&DataType::I64))?;
+
+            // every register argument is passed as a full i64
+            let value = match data_type {
+                DataType::Str | DataType::Ptr(_) => {
+                    self.builder.build_ptr_to_int(value.into_pointer_value(), i64_type, "arg")?
+                }
+                DataType::I64 | DataType::U64 => value.into_int_value(),
+                t if t.is_signed() => self.builder.build_int_s_extend(value.into_int_value(), i64_type, "arg")?,
+                t if t.is_unsigned() || t == DataType::Bool => {
+                    self.builder.build_int_z_extend(value.into_int_value(), i64_type, "arg")?
+                }
+                t => return Err(anyhow!("Cannot pass {} to syscall", t)),
+            };
+
+            args.append(&mut vec![value]);
+        }
+
+        let value = self.build_syscall(&args)?;
+
+        Ok((value.as_basic_value_enum(), DataType::I64))
+    }
+
+    fn build_syscall(&self, args: &[IntValue<'ctx>]) -> Result<IntValue<'ctx>> {
+        let arch = self.triple.split('-').next().unwrap_or_default();
+        let (instruction, result, registers, clobbers): (&str, &str, [&str; 7], &[&str]) = match arch {
+            "x86_64" => (
+                "syscall",
+                "={rax}",
+                ["{rax}", "{rdi}", "{rsi}", "{rdx}", "{r10}", "{r8}", "{r9}"],
+                &["~{rcx}", "~{r11}", "~{memory}"],
+            ),
+            "aarch64" => (
+                "svc #0",
+                "={x0}",
+                ["{x8}", "{x0}", "{x1}", "{x2}", "{x3}", "{x4}", "{x5}"],
+                &["~{memory}"],
+            ),
+            "riscv64" => (
+                "ecall",
+                "={x10}",
+                ["{x17}", "{x10}", "{x11}", "{x12}", "{x13}", "{x14}", "{x15}"],
+                &["~{memory}"],
+            ),
+            _ => return Err(anyhow!("syscall is not supported on {}", self.triple)),
+        };
+
+        let mut constraints = vec![result.to_string()];
+        constraints.extend(registers.iter().take(args.len()).map(|r| r.to_string()));
+        constraints.extend(clobbers.iter().map(|c| c.to_string()));
+
+        let i64_type = self.context.i64_type();
+        let arg_types = vec![BasicMetadataTypeEnum::from(i64_type); args.len()];
+        let fn_type = i64_type.fn_type(&arg_types, false);
+        let asm_ptr = self.context.create_inline_asm(
+            fn_type,
+            instruction.to_string(),
+            constraints.join(","),
+            true,
+            false,
+            None,
+            false,
+        );
+
+        let args = args.iter().map(|a| (*a).into()).collect::<Vec<BasicMetadataValueEnum>>();
+        let call_site = self.builder.build_indirect_call(fn_type, asm_ptr, &args, "syscall")?;
+
+        Ok(call_site
+            .try_as_basic_value()
+            .left()
+            .ok_or_else(|| anyhow!("syscall did not produce a value"))?
+            .into_int_value())
+    }
+
+    /// Entry point for freestanding builds: `_start` calls `main` and exits with its result.
+    fn compile_start(&mut self) -> Result<()> {
+        let main = self
+            .functions
+            .get("main")
+            .cloned()
+            .ok_or_else(|| anyhow!("Freestanding build requires a main function"))?;
+        if !main.param.is_empty() {
+            return Err(anyhow!("main cannot take parameters in a freestanding build"));
+        }
+        let main_val = self
+            .module
+            .get_function("main")
+            .ok_or_else(|| anyhow!("Freestanding build requires a main function"))?;
+
+        let arch = self.triple.split('-').next().unwrap_or_default();
+        let exit_group = match arch {
+            "x86_64" => 231,
+            "aarch64" | "riscv64" => 94,
+            _ => return Err(anyhow!("Freestanding builds are not supported on {}", self.triple)),
+        };
+
+        let start_type = self.context.void_type().fn_type(&[], false);
+        let start = self.module.add_function("_start", start_type, Some(Linkage::External));
+        // the kernel enters _start with the stack aligned for a call, not after one
+        start.add_attribute(
+            AttributeLoc::Function,
+            self.context.create_string_attribute("stackrealign", ""),
+        );
+
+        let entry = self.context.append_basic_block(start, "entry");
+        self.builder.position_at_end(entry);
+
+        let i64_type = self.context.i64_type();
+        let result = self.builder.build_call(main_val, &[], "main")?;
+        let code = match (result.try_as_basic_value().left(), &main.ret) {
+            (Some(value), ret) if ret.is_signed() => {
+                self.builder.build_int_s_extend_or_bit_cast(value.into_int_value(), i64_type, "code")?
+            }
+            (Some(value), ret) if ret.is_unsigned() => {
+                self.builder.build_int_z_extend_or_bit_cast(value.into_int_value(), i64_type, "code")?
+            }
+            (None, DataType::Void) => i64_type.const_zero(),
+            (_, ret) => return Err(anyhow!("main must return an integer or void, not {}", ret)),
+        };
+
+        self.build_syscall(&[i64_type.const_int(exit_group, false), code])?;
+        self.builder.build_unreachable()?;
+
+        Ok(())
+    }
+
+    fn compile_print(&mut self, params: Vec<LangType>) -> Result<()> {
+        if self.freestanding {
+            return Err(anyhow!("print needs libc and is not available in freestanding builds"));
+        }
+
+        let mut format = String::new();
+        let mut args: Vec<BasicMetadataValueEnum> = vec![];
+
+        for param in params {
+            let (value, data_type) = self.compile_expr(param, None)?;
+            if !format.is_empty() {
+                format.push(' ');
+            }
+
+            match data_type {
+                DataType::Str | DataType::Ptr(_) => {
+                    format.push_str(if data_type == DataType::Str { "%s" } else { "%p" });
+                    args.append(&mut vec![value.into()]);
+                }
+                DataType::Array(..) | DataType::Struct(_) => {
+                    return Err(anyhow!("Cannot print a value of type {}", data_type));
+                }
+                DataType::F32 | DataType::F64 => {
+                    // varargs promote float to double
+                    let value = self.builder.build_float_ext(value.into_float_value(), self.context.f64_type(), "promote")?;
+                    format.push_str("%g");
+                    args.append(&mut vec![value.into()]);
+                }
+                _ => {
+                    // varargs promote everything narrower than int
+                    let value = value.into_int_value();
+                    let value = if data_type.bit_width() < 32 {
+                        if data_type.is_signed() {
+                            self.builder.build_int_s_extend(value, self.context.i32_type(), "promote")?
+                        } else {
+                            self.builder.build_int_z_extend(value, self.context.i32_type(), "promote")?
+                        }
+                    } else {
+                        value
+                    };
+
+                    format.push_str(match (data_type.bit_width() == 64, data_type.is_unsigned()) {
+                        (true, true) => "%lu",
+                        (true, false) => "%ld",
+                        (false, true) => "%u",
+                        (false, false) => "%d",
+                    });
+                    args.append(&mut vec![value.into()]);
+                }
+            }
         }
+        format.push('\n');
 
-        builder.build_alloca(self.context.f64_type(), name).unwrap()
+        let format_ptr = self.builder.build_global_string_ptr(&format, "fmt")?;
+        args.insert(0, format_ptr.as_pointer_value().into());
+
+        let printf = self.get_printf();
+        self.builder.build_call(printf, &args, "print")?;
+
+        Ok(())
     }
 
-    fn compile_expr(&mut self,expr_type: LangType) -> Result<BasicValueEnum<'ctx>> {
+    fn compile_expr(&mut self, expr_type: LangType, hint: Option<&DataType>) -> Result<(BasicValueEnum<'ctx>, DataType)> {
         Ok(match expr_type {
             LangType::Primitive(p) => {
-                self.primitive(p)?
+                self.primitive(p, hint)?
+            },
+            LangType::Var(var) => {
+                if let Some(con) = self.constants.get(&var.name).cloned() {
+                    return self.primitive(con, hint);
+                }
+
+                let (ptr, data_type) = self
+                    .variables
+                    .get(&var.name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Unknown variable {}", var.name))?;
+                let ty = self.basic_type(&data_type)?;
+                (self.builder.build_load(ty, ptr, &var.name)?, data_type)
+            },
+            LangType::Op(op) => {
+                self.compile_op(op, hint)?
             },
-            _ => BasicValueEnum::IntValue(self.context.i8_type().const_int(0, false))
+            LangType::Call(call) => {
+                let name = call.name.clone();
+                self.compile_call(call)?
+                    .ok_or_else(|| anyhow!("Function {} does not return a value", name))?
+            },
+            other => return Err(anyhow!("Unexpected expression {:?}", other)),
         })
     }
 
-    fn primitive(&mut self,prim_type: PrimitiveType) -> Result<BasicValueEnum<'ctx>> {
+    fn compile_op(&mut self, op: OpType, hint: Option<&DataType>) -> Result<(BasicValueEnum<'ctx>, DataType)> {
+        match op.op {
+            Operation::Addr | Operation::Load | Operation::Offset | Operation::Index | Operation::Field => {
+                return self.compile_ptr_op(op)
+            }
+            Operation::Store => return Err(anyhow!("store does not produce a value")),
+            Operation::Neg => return self.compile_neg(*op.lhs, hint),
+            Operation::IntToFloat | Operation::FloatToInt => return self.compile_conversion(op.op, *op.lhs, hint),
+            Operation::Cast => return self.compile_cast(*op.lhs, *op.rhs),
+            Operation::Bitcast => return self.compile_bitcast(*op.lhs, *op.rhs),
+            _ => {}
+        }
+
+        let is_compare = matches!(
+            op.op,
+            Operation::Equal | Operation::NotEqual | Operation::LessThan | Operation::GreaterThan
+        );
+
+        // literals take the width of the other operand
+        let operand_hint = self
+            .type_of(&op.lhs)
+            .or_else(|| self.type_of(&op.rhs))
+            .or_else(|| if is_compare { None } else { hint.cloned() });
+
+        let (lhs, lhs_type) = self.compile_expr(*op.lhs, operand_hint.as_ref())?;
+        let (rhs, rhs_type) = self.compile_expr(*op.rhs, Some(&lhs_type))?;
+
+        if lhs_type != rhs_type {
+            return Err(anyhow!("Mismatched operand types {} and {}", lhs_type, rhs_type));
+        }
+        if lhs_type == DataType::Str {
+            return self.compile_str_op(op.op, lhs, rhs);
+        }
+        if lhs_type.is_float() {
+            return self.compile_float_op(op.op, lhs.into_float_value(), rhs.into_float_value(), lhs_type);
+        }
+        let bool_compare = lhs_type == DataType::Bool && matches!(op.op, Operation::Equal | Operation::NotEqual);
+        if !lhs_type.is_int() && !bool_compare {
+            return Err(anyhow!("Operation {:?} is not supported for {}", op.op, lhs_type));
+        }
+
+        let signed = lhs_type.is_signed();
+        let lhs = lhs.into_int_value();
+        let rhs = rhs.into_int_value();
+
+        let value: IntValue = match op.op {
+            Operation::Add => self.builder.build_int_add(lhs, rhs, "add")?,
+            Operation::Sub => self.builder.build_int_sub(lhs, rhs, "sub")?,
+            Operation::Mul => self.builder.build_int_mul(lhs, rhs, "mul")?,
+            Operation::Div if signed => self.builder.build_int_signed_div(lhs, rhs, "div")?,
+            Operation::Div => self.builder.build_int_unsigned_div(lhs, rhs, "div")?,
+            Operation::Mod if signed => self.builder.build_int_signed_rem(lhs, rhs, "mod")?,
+            Operation::Mod => self.builder.build_int_unsigned_rem(lhs, rhs, "mod")?,
+            Operation::Equal => self.builder.build_int_compare(IntPredicate::EQ, lhs, rhs, "eq")?,
+            Operation::NotEqual => self.builder.build_int_compare(IntPredicate::NE, lhs, rhs, "ne")?,
+            Operation::LessThan => {
+                let pred = if signed { IntPredicate::SLT } else { IntPredicate::ULT };
+                self.builder.build_int_compare(pred, lhs, rhs, "lt")?
+            }
+            Operation::GreaterThan => {
+                let pred = if signed { IntPredicate::SGT } else { IntPredicate::UGT };
+                self.builder.build_int_compare(pred, lhs, rhs, "gt")?
+            }
+            _ => return Err(anyhow!("Unexpected operation {:?}", op.op)),
+        };
+
+        let data_type = if is_compare { DataType::Bool } else { lhs_type };
+        Ok((value.as_basic_value_enum(), data_type))
+    }
+
+    fn compile_float_op(
+        &mut self,
+        op: Operation,
+        lhs: FloatValue<'ctx>,
+        rhs: FloatValue<'ctx>,
+        data_type: DataType,
+    ) -> Result<(BasicValueEnum<'ctx>, DataType)> {
+        let value = match op {
+            Operation::Add => self.builder.build_float_add(lhs, rhs, "fadd")?.as_basic_value_enum(),
+            Operation::Sub => self.builder.build_float_sub(lhs, rhs, "fsub")?.as_basic_value_enum(),
+            Operation::Mul => self.builder.build_float_mul(lhs, rhs, "fmul")?.as_basic_value_enum(),
+            Operation::Div => self.builder.build_float_div(lhs, rhs, "fdiv")?.as_basic_value_enum(),
+            Operation::Mod => self.builder.build_float_rem(lhs, rhs, "frem")?.as_basic_value_enum(),
+            // ordered compares are false for NaN, so != has to be the unordered one
+            Operation::Equal | Operation::NotEqual | Operation::LessThan | Operation::GreaterThan => {
+                let pred = match op {
+                    Operation::Equal => FloatPredicate::OEQ,
+                    Operation::NotEqual => FloatPredicate::UNE,
+                    Operation::LessThan => FloatPredicate::OLT,
+                    _ => FloatPredicate::OGT,
+                };
+                let value = self.builder.build_float_compare(pred, lhs, rhs, "fcmp")?;
+                return Ok((value.as_basic_value_enum(), DataType::Bool));
+            }
+            _ => return Err(anyhow!("Operation {:?} is not supported for {}", op, data_type)),
+        };
+
+        Ok((value, data_type))
+    }
+
+    fn compile_neg(&mut self, value: LangType, hint: Option<&DataType>) -> Result<(BasicValueEnum<'ctx>, DataType)> {
+        let value_hint = self.type_of(&value).or_else(|| hint.cloned());
+        let (value, data_type) = self.compile_expr(value, value_hint.as_ref())?;
+        if data_type.is_float() {
+            let value = self.builder.build_float_neg(value.into_float_value(), "fneg")?;
+            return Ok((value.as_basic_value_enum(), data_type));
+        }
+        if !data_type.is_signed() {
+            return Err(anyhow!("Cannot negate {}, only signed integers and floats can be negated", data_type));
+        }
+
+        let value = self.builder.build_int_neg(value.into_int_value(), "neg")?;
+        Ok((value.as_basic_value_enum(), data_type))
+    }
+
+    /// `itof` and `ftoi`, converting to the type of the destination or to `f64`/`i64` for new variables.
+    fn compile_conversion(&mut self, op: Operation, value: LangType, hint: Option<&DataType>) -> Result<(BasicValueEnum<'ctx>, DataType)> {
+        let (value, from) = self.compile_expr(value, None)?;
+
+        if let Operation::IntToFloat = op {
+            if !from.is_int() {
+                return Err(anyhow!("itof expects an integer, got {}", from));
+            }
+            let to = hint.filter(|t| t.is_float()).cloned().unwrap_or(DataType::F64);
+            let float_type = self.basic_type(&to)?.into_float_type();
+            let value = if from.is_signed() {
+                self.builder.build_signed_int_to_float(value.into_int_value(), float_type, "itof")?
+            } else {
+                self.builder.build_unsigned_int_to_float(value.into_int_value(), float_type, "itof")?
+            };
+
+            return Ok((value.as_basic_value_enum(), to));
+        }
+
+        if !from.is_float() {
+            return Err(anyhow!("ftoi expects a float, got {}", from));
+        }
+        let to = hint.filter(|t| t.is_int()).cloned().unwrap_or(DataType::I64);
+        let int_type = self.context.custom_width_int_type(to.bit_width());
+        let value = if to.is_signed() {
+            self.builder.build_float_to_signed_int(value.into_float_value(), int_type, "ftoi")?
+        } else {
+            self.builder.build_float_to_unsigned_int(value.into_float_value(), int_type, "ftoi")?
+        };
+
+        Ok((value.as_basic_value_enum(), to))
+    }
+
+    /// `cast dest x as type`. Integers are extended by the signedness of the source and truncated,
+    /// ints and floats convert by value, and pointers convert to other pointers and integers.
+    fn compile_cast(&mut self, value: LangType, target: LangType) -> Result<(BasicValueEnum<'ctx>, DataType)> {
+        let to = match target {
+            LangType::Type(data_type) => data_type,
+            other => return Err(anyhow!("Expected a type to cast to, got {:?}", other)),
+        };
+        // literals keep their default type, `cast x 300 as u8` truncates like any other i32
+        let (value, from) = self.compile_expr(value, None)?;
+        if !from.can_cast_to(&to) {
+            return Err(anyhow!("Cannot cast {} to {}", from, to));
+        }
+        let target_type = self.basic_type(&to)?;
+        let is_ptr = |t: &DataType| t.is_ptr() || *t == DataType::Str;
+
+        let value = match (&from, &to) {
+            _ if from == to => value,
+            (f, t) if (f.is_int() || *f == DataType::Bool) && t.is_int() => self
+                .builder
+                .build_int_cast_sign_flag(value.into_int_value(), target_type.into_int_type(), f.is_signed(), "cast")?
+                .as_basic_value_enum(),
+            (f, t) if f.is_int() && t.is_float() => {
+                let float_type = target_type.into_float_type();
+                if f.is_signed() {
+                    self.builder.build_signed_int_to_float(value.into_int_value(), float_type, "cast")?
+                } else {
+                    self.builder.build_unsigned_int_to_float(value.into_int_value(), float_type, "cast")?
+                }
+                .as_basic_value_enum()
+            }
+            (f, t) if f.is_float() && t.is_int() => {
+                let int_type = target_type.into_int_type();
+                if t.is_signed() {
+                    self.builder.build_float_to_signed_int(value.into_float_value(), int_type, "cast")?
+                } else {
+                    self.builder.build_float_to_unsigned_int(value.into_float_value(), int_type, "cast")?
+                }
+                .as_basic_value_enum()
+            }
+            (DataType::F32, DataType::F64) => self
+                .builder
+                .build_float_ext(value.into_float_value(), target_type.into_float_type(), "cast")?
+                .as_basic_value_enum(),
+            (DataType::F64, DataType::F32) => self
+                .builder
+                .build_float_trunc(value.into_float_value(), target_type.into_float_type(), "cast")?
+                .as_basic_value_enum(),
+            (f, t) if is_ptr(f) && is_ptr(t) => self
+                .builder
+                .build_pointer_cast(value.into_pointer_value(), target_type.into_pointer_type(), "cast")?
+                .as_basic_value_enum(),
+            (f, t) if is_ptr(f) && t.is_int() => self
+                .builder
+                .build_ptr_to_int(value.into_pointer_value(), target_type.into_int_type(), "cast")?
+                .as_basic_value_enum(),
+            (f, t) if f.is_int() && is_ptr(t) => self
+                .builder
+                .build_int_to_ptr(value.into_int_value(), target_type.into_pointer_type(), "cast")?
+                .as_basic_value_enum(),
+            _ => return Err(anyhow!("Cannot cast {} to {}", from, to)),
+        };
+
+        Ok((value, to))
+    }
+
+    fn compile_bitcast(&mut self, value: LangType, target: LangType) -> Result<(BasicValueEnum<'ctx>, DataType)> {
+        let to = match target {
+            LangType::Type(data_type) => data_type,
+            other => return Err(anyhow!("Expected a type to cast to, got {:?}", other)),
+        };
+        let (value, from) = self.compile_expr(value, None)?;
+        if !from.can_bitcast_to(&to) {
+            return Err(anyhow!("Cannot bitcast {} to {}", from, to));
+        }
+
+        let value = self.builder.build_bitcast(value, self.basic_type(&to)?, "bitcast")?;
+
+        Ok((value, to))
+    }
+
+    fn compile_ptr_op(&mut self, op: OpType) -> Result<(BasicValueEnum<'ctx>, DataType)> {
+        match op.op {
+            Operation::Addr => {
+                let name = match *op.lhs {
+                    LangType::Var(var) => var.name,
+                    other => return Err(anyhow!("Cannot take the address of {:?}", other)),
+                };
+                let (ptr, data_type) = self
+                    .variables
+                    .get(&name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Unknown variable {}", name))?;
+
+                Ok((ptr.as_basic_value_enum(), DataType::ptr(data_type)))
+            }
+            Operation::Load => {
+                let packed = self.is_packed_place(&op.lhs);
+                let (ptr, pointee) = self.compile_ptr(*op.lhs)?;
+                let value = self.builder.build_load(self.basic_type(&pointee)?, ptr, "load")?;
+                if packed {
+                    set_unaligned(value.as_instruction_value())?;
+                }
+
+                Ok((value, pointee))
+            }
+            Operation::Offset => {
+                let (ptr, pointee) = self.compile_ptr(*op.lhs)?;
+                let (index, index_type) = self.compile_expr(*op.rhs, Some(&DataType::I64))?;
+                if !index_type.is_int() {
+                    return Err(anyhow!("Pointer offset must be an integer, got {}", index_type));
+                }
+
+                let index = self.builder.build_int_cast_sign_flag(
+                    index.into_int_value(),
+                    self.context.i64_type(),
+                    index_type.is_signed(),
+                    "index",
+                )?;
+                // offsets count elements of the pointee, like C pointer arithmetic
+                let value = unsafe { self.builder.build_gep(self.basic_type(&pointee)?, ptr, &[index], "offset")? };
+
+                Ok((value.as_basic_value_enum(), DataType::ptr(pointee)))
+            }
+            Operation::Index => self.compile_index(*op.lhs, *op.rhs),
+            Operation::Field => self.compile_field(*op.lhs, *op.rhs),
+            _ => Err(anyhow!("Unexpected pointer operation {:?}", op.op)),
+        }
+    }
+
+    /// Address of `base[index]` for arrays, pointers to arrays and plain pointers.
+    fn compile_index(&mut self, base: LangType, index: LangType) -> Result<(BasicValueEnum<'ctx>, DataType)> {
+        // array variables are indexed in place instead of being loaded
+        let array_var = match &base {
+            LangType::Var(var) => self.variables.get(&var.name).filter(|(_, t)| t.is_array()).cloned(),
+            _ => None,
+        };
+        let (ptr, base_type) = match array_var {
+            Some((ptr, data_type)) => (ptr, DataType::ptr(data_type)),
+            None => {
+                let (value, data_type) = self.compile_expr(base, None)?;
+                if !data_type.is_ptr() && data_type != DataType::Str {
+                    return Err(anyhow!("Cannot index into {}", data_type));
+                }
+                (value.into_pointer_value(), data_type)
+            }
+        };
+
+        let (index, index_type) = self.compile_expr(index, Some(&DataType::I64))?;
+        if !index_type.is_int() {
+            return Err(anyhow!("Index must be an integer, got {}", index_type));
+        }
+        let i64_type = self.context.i64_type();
+        let index = self.builder.build_int_cast_sign_flag(
+            index.into_int_value(),
+            i64_type,
+            index_type.is_signed(),
+            "index",
+        )?;
+
+        if base_type == DataType::Str {
+            // strings index their bytes, checked against the length up to the terminator
+            if self.bounds_checks {
+                let len = self
+                    .builder
+                    .build_call(Runtime::new(self.context, &self.module).str_len()?, &[ptr.into()], "len")?
+                    .try_as_basic_value()
+                    .left()
+                    .ok_or_else(|| anyhow!("len did not produce a value"))?
+                    .into_int_value();
+                self.build_bounds_check(index, len)?;
+            }
+
+            let value = unsafe { self.builder.build_gep(self.context.i8_type(), ptr, &[index], "byte")? };
+            return Ok((value.as_basic_value_enum(), DataType::ptr(DataType::U8)));
+        }
+
+        match base_type.pointee().cloned() {
+            Some(DataType::Array(elem, len)) => {
+                if self.bounds_checks {
+                    self.build_bounds_check(index, i64_type.const_int(len as u64, false))?;
+                }
+
+                let array_type = self.basic_type(&DataType::Array(elem.clone(), len))?;
+                let value = unsafe {
+                    self.builder
+                        .build_in_bounds_gep(array_type, ptr, &[i64_type.const_zero(), index], "elem")?
+                };
+                Ok((value.as_basic_value_enum(), DataType::ptr(*elem)))
+            }
+            Some(DataType::Void) | None => Err(anyhow!("Cannot index into {}", base_type)),
+            Some(pointee) => {
+                let value = unsafe { self.builder.build_gep(self.basic_type(&pointee)?, ptr, &[index], "elem")? };
+                Ok((value.as_basic_value_enum(), DataType::ptr(pointee)))
+            }
+        }
+    }
+
+    /// Address of `base.field` for struct variables and pointers to structs.
+    fn compile_field(&mut self, base: LangType, field: LangType) -> Result<(BasicValueEnum<'ctx>, DataType)> {
+        let field = match field {
+            LangType::Var(var) => var.name,
+            other => return Err(anyhow!("Expected field name, got {:?}", other)),
+        };
+
+        // `Color.Red` is the value of an enum variant, not an address
+        if let Some(name) = self.enum_name(&base) {
+            let enum_t = &self.enums[&name];
+            let variant = enum_t
+                .variant(&field)
+                .ok_or_else(|| anyhow!("Enum {} has no variant {}", name, field))?;
+            let int_type = self.context.custom_width_int_type(enum_t.data_type.bit_width());
+            let value = int_type.const_int(variant.value as u64, enum_t.data_type.is_signed());
+
+            return Ok((value.as_basic_value_enum(), enum_t.data_type.clone()));
+        }
+
+        // struct variables are accessed in place instead of being loaded
+        let struct_var = match &base {
+            LangType::Var(var) => self.variables.get(&var.name).filter(|(_, t)| t.is_struct()).cloned(),
+            _ => None,
+        };
+        let (ptr, struct_type) = match struct_var {
+            Some((ptr, data_type)) => (ptr, data_type),
+            None => {
+                let (value, data_type) = self.compile_expr(base, None)?;
+                match data_type.pointee() {
+                    Some(pointee) if pointee.is_struct() => (value.into_pointer_value(), pointee.clone()),
+                    _ => return Err(anyhow!("{} has no fields", data_type)),
+                }
+            }
+        };
+
+        let name = struct_type.to_string();
+        let struct_t = self.structs.get(&name).ok_or_else(|| anyhow!("Unknown type {}", name))?;
+        let index = struct_t
+            .field_index(&field)
+            .ok_or_else(|| anyhow!("Struct {} has no field {}", name, field))?;
+        let field_type = struct_t.fields[index].data_type.clone().unwrap_or_default();
+
+        let value = self.builder.build_struct_gep(
+            self.struct_types[&name],
+            ptr,
+            self.layouts[&name].indices[index],
+            &field,
+        )?;
+
+        Ok((value.as_basic_value_enum(), DataType::ptr(field_type)))
+    }
+
+    /// Fields of packed structs can sit at any offset, so accesses through them can't assume alignment.
+    fn is_packed_place(&self, expr: &LangType) -> bool {
+        match expr {
+            LangType::Op(op) if matches!(op.op, Operation::Field) => {
+                let struct_type = match self.type_of(&op.lhs) {
+                    Some(DataType::Ptr(pointee)) => *pointee,
+                    other => other.unwrap_or_default(),
+                };
+                let packed = self.structs.get(&struct_type.to_string()).is_some_and(|s| s.packed);
+                packed || self.is_packed_place(&op.lhs)
+            }
+            LangType::Op(op) if matches!(op.op, Operation::Index) => self.is_packed_place(&op.lhs),
+            _ => false,
+        }
+    }
+
+    /// Traps unless `index < len`; negative indices wrap and trap as well.
+    fn build_bounds_check(&self, index: IntValue<'ctx>, len: IntValue<'ctx>) -> Result<()> {
+        let in_bounds = self.builder.build_int_compare(IntPredicate::ULT, index, len, "inbounds")?;
+
+        let fn_val = self.current_fn()?;
+        let trap_bb = self.context.append_basic_block(fn_val, "outofbounds");
+        let ok_bb = self.context.append_basic_block(fn_val, "inbounds");
+        self.builder.build_conditional_branch(in_bounds, ok_bb, trap_bb)?;
+
+        self.builder.position_at_end(trap_bb);
+        self.build_trap()?;
+
+        self.builder.position_at_end(ok_bb);
+
+        Ok(())
+    }
+
+    /// Aborts the program through `llvm.trap`, ending the current block.
+    fn build_trap(&self) -> Result<()> {
+        let trap = self.module.get_function("llvm.trap").unwrap_or_else(|| {
+            let trap_type = self.context.void_type().fn_type(&[], false);
+            self.module.add_function("llvm.trap", trap_type, None)
+        });
+        self.builder.build_call(trap, &[], "trap")?;
+        self.builder.build_unreachable()?;
+
+        Ok(())
+    }
+
+    fn compile_store(&mut self, op: OpType) -> Result<()> {
+        let packed = self.is_packed_place(&op.lhs);
+        let (ptr, pointee) = self.compile_ptr(*op.lhs)?;
+        let (value, value_type) = self.compile_expr(*op.rhs, Some(&pointee))?;
+        if value_type != pointee {
+            return Err(anyhow!("Cannot store {} through ptr<{}>", value_type, pointee));
+        }
+
+        let store = self.builder.build_store(ptr, value)?;
+        if packed {
+            set_unaligned(Some(store))?;
+        }
+
+        Ok(())
+    }
+
+    /// Compiles a typed pointer expression, returning it with its pointee type.
+    fn compile_ptr(&mut self, expr: LangType) -> Result<(PointerValue<'ctx>, DataType)> {
+        let (value, data_type) = self.compile_expr(expr, None)?;
+
+        match data_type.pointee() {
+            Some(DataType::Void) | None => Err(anyhow!("Expected a typed pointer, got {}", data_type)),
+            Some(pointee) => Ok((value.into_pointer_value(), pointee.clone())),
+        }
+    }
+
+    fn primitive(&mut self, prim_type: PrimitiveType, hint: Option<&DataType>) -> Result<(BasicValueEnum<'ctx>, DataType)> {
         Ok(match prim_type.primitive {
             Primitives::Int => {
-                BasicValueEnum::IntValue(self.context.i32_type().const_int(prim_type.value.parse::<u64>()?, false))
-            }
-            Primitives::Bool => {
-                if prim_type.value == "true"{
-                    BasicValueEnum::IntValue(self.context.bool_type().const_int(0, false))
+                let value = prim_type
+                    .to_i128()
+                    .ok_or_else(|| anyhow!("Integer literal {} does not fit in 64 bits", prim_type.value))?;
+                let data_type = literal_type(&prim_type, hint).unwrap_or(DataType::I64);
+                if !data_type.fits_literal(value, prim_type.radix) {
+                    return Err(anyhow!("Integer literal {} does not fit in {}", value, data_type));
                 }
-                else {
-                    BasicValueEnum::IntValue(self.context.bool_type().const_int(1, false))
+
+                // the low bits of the two's complement are the value at any width
+                let int_type = self.context.custom_width_int_type(data_type.bit_width());
+                (BasicValueEnum::IntValue(int_type.const_int(value as u64, false)), data_type)
+            }
+            Primitives::Float => {
+                let data_type = literal_type(&prim_type, hint).unwrap_or(DataType::F64);
+                let value: f64 = prim_type
+                    .value
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid float literal {}", prim_type.value))?;
+                let finite = if data_type == DataType::F32 { (value as f32).is_finite() } else { value.is_finite() };
+                if !finite {
+                    return Err(anyhow!("Float literal {} does not fit in {}", prim_type.value, data_type));
                 }
+
+                let float_type = self.basic_type(&data_type)?.into_float_type();
+                (BasicValueEnum::FloatValue(float_type.const_float(value)), data_type)
+            }
+            Primitives::Bool => {
+                let value = if prim_type.value == "true" { 1 } else { 0 };
+                (BasicValueEnum::IntValue(self.context.bool_type().const_int(value, false)), DataType::Bool)
             }
             Primitives::String => {
                 let global_str = self.builder.build_global_string_ptr(&prim_type.value, "str")?;
 
-                global_str.as_basic_value_enum()
+                (global_str.as_pointer_value().as_basic_value_enum(), DataType::Str)
             }
         })
     }
+
+    fn basic_type(&self, data_type: &DataType) -> Result<BasicTypeEnum<'ctx>> {
+        Ok(match data_type {
+            DataType::Bool => self.context.bool_type().as_basic_type_enum(),
+            DataType::Str => self
+                .context
+                .i8_type()
+                .ptr_type(AddressSpace::default())
+                .as_basic_type_enum(),
+            DataType::Ptr(pointee) => match **pointee {
+                // opaque pointers are passed around as i8*
+                DataType::Void => self.context.i8_type().ptr_type(AddressSpace::default()).as_basic_type_enum(),
+                _ => self.basic_type(pointee)?.ptr_type(AddressSpace::default()).as_basic_type_enum(),
+            },
+            DataType::Array(elem, len) => self.basic_type(elem)?.array_type(*len).as_basic_type_enum(),
+            DataType::Struct(name) => self
+                .struct_types
+                .get(name)
+                .ok_or_else(|| anyhow!("Unknown type {}", name))?
+                .as_basic_type_enum(),
+            DataType::F32 => self.context.f32_type().as_basic_type_enum(),
+            DataType::F64 => self.context.f64_type().as_basic_type_enum(),
+            DataType::Void => return Err(anyhow!("void is not a value type")),
+            int => self.context.custom_width_int_type(int.bit_width()).as_basic_type_enum(),
+        })
+    }
+
+    fn fn_type(&self, ret: &DataType, args: &[BasicMetadataTypeEnum<'ctx>], variadic: bool) -> Result<FunctionType<'ctx>> {
+        Ok(match ret {
+            DataType::Void => self.context.void_type().fn_type(args, variadic),
+            ret => self.basic_type(ret)?.fn_type(args, variadic),
+        })
+    }
+
+    fn get_printf(&self) -> FunctionValue<'ctx> {
+        self.module.get_function("printf").unwrap_or_else(|| {
+            let str_type = self.context.i8_type().ptr_type(AddressSpace::default());
+            let printf_type = self.context.i32_type().fn_type(&[str_type.into()], true);
+            self.module.add_function("printf", printf_type, Some(Linkage::External))
+        })
+    }
+
+    fn build_default_return(&self, ret: &DataType) -> Result<()> {
+        match ret {
+            DataType::Void => self.builder.build_return(None)?,
+            ret => {
+                let zero = self.basic_type(ret)?.const_zero();
+                self.builder.build_return(Some(&zero))?
+            }
+        };
+
+        Ok(())
+    }
+
+    fn current_fn(&self) -> Result<FunctionValue<'ctx>> {
+        self.builder
+            .get_insert_block()
+            .and_then(|bb| bb.get_parent())
+            .ok_or_else(|| anyhow!("Statement outside of function"))
+    }
+
+    fn no_terminator(&self) -> bool {
+        self.builder
+            .get_insert_block()
+            .is_some_and(|bb| bb.get_terminator().is_none())
+    }
+
+    /// Storage for a new variable, an alloca or a global in REPL entries.
+    fn declare_variable(&mut self, name: &str, data_type: &DataType) -> Result<PointerValue<'ctx>> {
+        let fn_val = self.current_fn()?;
+        let fn_name = fn_val.get_name().to_string_lossy().to_string();
+        if !self.is_repl_entry(&fn_name) {
+            let alloca = self.create_entry_block_alloca(fn_val, name, data_type)?;
+            self.debug_variable(alloca, name, data_type, None)?;
+            return Ok(alloca);
+        }
+
+        let basic_type = self.basic_type(data_type)?;
+        let global = self.module.add_global(basic_type, None, &format!("repl.{}", name));
+        global.set_linkage(Linkage::External);
+        global.set_initializer(&basic_type.const_zero());
+        self.repl_variables.append(&mut vec![(name.to_string(), data_type.clone())]);
+
+        Ok(global.as_pointer_value())
+    }
+
+    fn is_repl_entry(&self, name: &str) -> bool {
+        self.repl_entry.as_deref() == Some(name)
+    }
+
+    fn create_entry_block_alloca(&self, fn_val: FunctionValue<'ctx>, name: &str, data_type: &DataType) -> Result<PointerValue<'ctx>> {
+        let builder = self.context.create_builder();
+
+        let entry = fn_val
+            .get_first_basic_block()
+            .ok_or_else(|| anyhow!("Function without entry block"))?;
+
+        match entry.get_first_instruction() {
+            Some(first_instr) => builder.position_before(&first_instr),
+            None => builder.position_at_end(entry),
+        }
+
+        let alloca = builder.build_alloca(self.basic_type(data_type)?, name)?;
+
+        // structs are packed LLVM types, their alignment has to be spelled out
+        if let Some(align) = self.aggregate_align(data_type) {
+            alloca
+                .as_instruction_value()
+                .ok_or_else(|| anyhow!("Alloca is not an instruction"))?
+                .set_alignment(align as u32)
+                .map_err(|e| anyhow!(e))?;
+        }
+
+        Ok(alloca)
+    }
+
+    /// Attaches a subprogram to `fn_val` and starts its line table at the `fn` line.
+    fn debug_function(&mut self, fn_val: FunctionValue<'ctx>, func_type: &FuncType) -> Result<()> {
+        if self.debug.is_none() {
+            return Ok(());
+        }
+
+        let ret = match &func_type.ret {
+            DataType::Void => None,
+            ret => Some(self.debug_type(ret, &mut vec![])?),
+        };
+        let params = func_type
+            .param
+            .iter()
+            .map(|p| self.debug_type(&p.data_type.clone().unwrap_or_default(), &mut vec![]))
+            .collect::<Result<Vec<DIType>>>()?;
+
+        let debug = self.debug.as_mut().ok_or_else(|| anyhow!("Debug info is not enabled"))?;
+        let file = debug.unit.get_file();
+        let subroutine_type = debug.builder.create_subroutine_type(file, ret, &params, DIFlags::PROTOTYPED);
+        let subprogram = debug.builder.create_function(
+            debug.unit.as_debug_info_scope(),
+            &func_type.name,
+            None,
+            file,
+            func_type.line,
+            subroutine_type,
+            fn_val.get_linkage() == Linkage::Internal,
+            true,
+            func_type.line,
+            DIFlags::PROTOTYPED,
+            debug.optimized,
+        );
+        fn_val.set_subprogram(subprogram);
+        debug.scope = Some(subprogram);
+
+        self.set_debug_location(Span {
+            line: func_type.line,
+            column: 1,
+        });
+
+        Ok(())
+    }
+
+    fn debug_info(&self) -> Result<&DebugInfo<'ctx>> {
+        self.debug.as_ref().ok_or_else(|| anyhow!("Debug info is not enabled"))
+    }
+
+    /// Following instructions belong to `span`, within the current function.
+    fn set_debug_location(&mut self, span: Span) {
+        let Some(debug) = &mut self.debug else { return };
+        let Some(scope) = debug.scope else { return };
+
+        let location =
+            debug
+                .builder
+                .create_debug_location(self.context, span.line, span.column, scope.as_debug_info_scope(), None);
+        self.builder.set_current_debug_location(location);
+        debug.line = span.line;
+    }
+
+    /// Describes the storage of a variable, or of parameter `arg_no` counted from 1.
+    fn debug_variable(&mut self, storage: PointerValue<'ctx>, name: &str, data_type: &DataType, arg_no: Option<u32>) -> Result<()> {
+        if self.debug.as_ref().and_then(|debug| debug.scope).is_none() {
+            return Ok(());
+        }
+
+        let ty = self.debug_type(data_type, &mut vec![])?;
+        let block = self
+            .builder
+            .get_insert_block()
+            .ok_or_else(|| anyhow!("Variable {} outside of function", name))?;
+
+        let debug = self.debug_info()?;
+        let scope = debug
+            .scope
+            .ok_or_else(|| anyhow!("Variable {} outside of function", name))?
+            .as_debug_info_scope();
+        let file = debug.unit.get_file();
+
+        let variable = match arg_no {
+            Some(arg_no) => debug
+                .builder
+                .create_parameter_variable(scope, name, arg_no, file, debug.line, ty, true, DIFlags::ZERO),
+            None => debug
+                .builder
+                .create_auto_variable(scope, name, file, debug.line, ty, true, DIFlags::ZERO, 0),
+        };
+        let location = debug.builder.create_debug_location(self.context, debug.line, 0, scope, None);
+        debug.builder.insert_declare_at_end(storage, Some(variable), None, location, block);
+
+        Ok(())
+    }
+
+    /// DWARF type of `data_type`. Pointers to a struct that is still being described, i.e.
+    /// in `visiting`, are described as byte pointers.
+    fn debug_type(&mut self, data_type: &DataType, visiting: &mut Vec<String>) -> Result<DIType<'ctx>> {
+        let name = data_type.to_string();
+        let debug = self.debug_info()?;
+        if let Some(ty) = debug.types.get(&name) {
+            return Ok(*ty);
+        }
+
+        let ty = match data_type {
+            DataType::Void => return Err(anyhow!("void is not a value type")),
+            DataType::Str | DataType::Ptr(_) => {
+                let pointee = match data_type {
+                    DataType::Ptr(pointee) => match &**pointee {
+                        DataType::Struct(name) if visiting.contains(name) => self.debug_type(&DataType::U8, visiting)?,
+                        DataType::Void => self.debug_type(&DataType::U8, visiting)?,
+                        pointee => self.debug_type(pointee, visiting)?,
+                    },
+                    // so debuggers print the text
+                    _ => {
+                        let debug = self.debug_info()?;
+                        debug
+                            .builder
+                            .create_basic_type("char", 8, DW_ATE_SIGNED_CHAR, DIFlags::ZERO)
+                            .map_err(|e| anyhow!(e))?
+                            .as_type()
+                    }
+                };
+                let debug = self.debug_info()?;
+                debug
+                    .builder
+                    .create_pointer_type(&name, pointee, 64, 64, AddressSpace::default())
+                    .as_type()
+            }
+            DataType::Array(elem, len) => {
+                let (size, align) = self.type_layout(data_type, &mut vec![])?;
+                let elem = self.debug_type(elem, visiting)?;
+                let debug = self.debug_info()?;
+                debug
+                    .builder
+                    .create_array_type(elem, size * 8, (align * 8) as u32, std::slice::from_ref(&(0..*len as i64)))
+                    .as_type()
+            }
+            DataType::Struct(struct_name) => {
+                let struct_t = self
+                    .structs
+                    .get(struct_name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Unknown type {}", struct_name))?;
+                let layout = self.compile_struct(struct_name, &mut vec![])?;
+
+                visiting.push(struct_name.clone());
+                let mut members = vec![];
+                for (field, offset) in struct_t.fields.iter().zip(&layout.offsets) {
+                    let field_type = field.data_type.clone().unwrap_or_default();
+                    let (size, _) = self.type_layout(&field_type, &mut vec![])?;
+                    let ty = self.debug_type(&field_type, visiting)?;
+
+                    let debug = self.debug_info()?;
+                    let file = debug.unit.get_file();
+                    let member = debug.builder.create_member_type(
+                        file.as_debug_info_scope(),
+                        &field.name,
+                        file,
+                        0,
+                        size * 8,
+                        0,
+                        offset * 8,
+                        DIFlags::ZERO,
+                        ty,
+                    );
+                    members.append(&mut vec![member.as_type()]);
+                }
+                visiting.pop();
+
+                let debug = self.debug_info()?;
+                let file = debug.unit.get_file();
+                debug
+                    .builder
+                    .create_struct_type(
+                        debug.unit.as_debug_info_scope(),
+                        struct_name,
+                        file,
+                        0,
+                        layout.size * 8,
+                        (layout.align * 8) as u32,
+                        DIFlags::ZERO,
+                        None,
+                        &members,
+                        0,
+                        None,
+                        struct_name,
+                    )
+                    .as_type()
+            }
+            basic => {
+                let encoding = match basic {
+                    DataType::Bool => DW_ATE_BOOLEAN,
+                    DataType::F32 | DataType::F64 => DW_ATE_FLOAT,
+                    signed if signed.is_signed() => DW_ATE_SIGNED,
+                    _ => DW_ATE_UNSIGNED,
+                };
+                let (size, _) = self.type_layout(basic, &mut vec![])?;
+                let debug = self.debug_info()?;
+                debug
+                    .builder
+                    .create_basic_type(&name, size * 8, encoding, DIFlags::ZERO)
+                    .map_err(|e| anyhow!(e))?
+                    .as_type()
+            }
+        };
+
+        // types inside a struct may have cut a pointer short, only complete ones are reused
+        if visiting.is_empty() {
+            if let Some(debug) = self.debug.as_mut() {
+                debug.types.insert(name, ty);
+            }
+        }
+
+        Ok(ty)
+    }
+
+    /// Alignment of structs and arrays of structs.
+    fn aggregate_align(&self, data_type: &DataType) -> Option<u64> {
+        match data_type {
+            DataType::Struct(name) => self.layouts.get(name).map(|l| l.align),
+            DataType::Array(elem, _) => self.aggregate_align(elem),
+            _ => None,
+        }
+    }
+}
+
+fn set_unaligned(instruction: Option<InstructionValue>) -> Result<()> {
+    instruction
+        .ok_or_else(|| anyhow!("Expected a memory instruction"))?
+        .set_alignment(1)
+        .map_err(|e| anyhow!(e))
 }
 
+// ------------------------------------
+// Tests
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use inkwell::context::Context;
+    use inkwell::module::Linkage;
+
+    use super::{CodeGen, C_CALL_CONV};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn ir(input: &str) -> Result<String> {
+        let context = Context::create();
+        let ast = Parser::new(Lexer::new(input.into()).collect()?).parse_file()?;
+        let module = CodeGen::new(&context).compile_module("test".to_string(), ast)?;
+
+        return Ok(module.print_to_string().to_string());
+    }
+
+    #[test]
+    fn codegen_extern() -> Result<()> {
+        let input = r#"extern fn puts str -> i32
+extern fn printf str ... -> i32
+extern fn exit i32
+fn main:
+    puts("hi")
+    exit(0)
+end"#;
+
+        let ir = ir(input)?;
+        assert!(ir.contains("declare i32 @puts(i8*)"));
+        assert!(ir.contains("declare i32 @printf(i8*, ...)"));
+        assert!(ir.contains("declare void @exit(i32)"));
+        assert!(ir.contains("call i32 @puts(i8* "));
+        assert!(ir.contains("call void @exit(i32 0)"));
+
+        let context = Context::create();
+        let lex = Lexer::new("extern fn abs i32 -> i32\nextern fn abs i64 -> i64".into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+        assert!(CodeGen::new(&context).compile_module("test".to_string(), ast).is_err());
+
+        return Ok(());
+    }
+
+    #[test]
+    fn codegen_linkage() -> Result<()> {
+        let input = r#"extern fn puts str -> i32
+export fn square x:i64 -> i64:
+    mul r x x
+    return r
+end
+fn helper -> i32:
+    return 1
+end"#;
+
+        let context = Context::create();
+        let ast = Parser::new(Lexer::new(input.into()).collect()?).parse_file()?;
+        let module = CodeGen::new(&context).compile_module("test".to_string(), ast)?;
+        for (name, linkage) in [("puts", Linkage::External), ("square", Linkage::External), ("helper", Linkage::Internal)] {
+            let fn_val = module.get_function(name).expect(name);
+            assert_eq!(fn_val.get_linkage(), linkage, "{}", name);
+            assert_eq!(fn_val.get_call_conventions(), C_CALL_CONV, "{}", name);
+        }
+
+        let ir = module.print_to_string().to_string();
+        assert!(ir.contains("define i64 @square(i64 %x)"));
+        assert!(ir.contains("define internal i32 @helper()"));
+
+        return Ok(());
+    }
+
+    #[test]
+    fn codegen_bitcast() -> Result<()> {
+        let input = "fn main:\n    let one 1.0\n    bitcast bits one as u64\n    let raw:u32 7\n    bitcast f raw as f32\nend";
+        let module = ir(input)?;
+        assert!(module.contains("= bitcast double %"));
+        assert!(module.contains("= bitcast i32 %"));
+        assert!(ir("fn main:\n    let one 1.0\n    bitcast x one as i32\nend").is_err());
+
+        return Ok(());
+    }
+
+    #[test]
+    fn codegen_match_trap() -> Result<()> {
+        let input = r#"enum Color u8:
+    Red
+    Green
+end
+fn main:
+    let c Color.Red
+    match c:
+    Color.Red:
+        print(1)
+    Color.Green:
+        print(2)
+    end
+end"#;
+
+        // an enum value out of range must not be undefined behavior
+        let ir = ir(input)?;
+        let default: Vec<&str> = ir.lines().skip_while(|line| !line.starts_with("default:")).skip(1).take(2).collect();
+        assert_eq!(default, vec!["  call void @llvm.trap()", "  unreachable"]);
+
+        return Ok(());
+    }
+
+    #[test]
+    fn codegen_local_consts() -> Result<()> {
+        let local = "fn main -> i32:\n    const N 7\n    return N\nend\nfn helper -> i32:\n    return N\nend";
+        let err = ir(local).expect_err("N is local to main");
+        assert_eq!(err.to_string(), "Unknown variable N");
+
+        let global = "const N 7\nfn main -> i32:\n    const M 1\n    return N\nend\nfn helper -> i32:\n    return N\nend";
+        assert!(ir(global)?.contains("ret i32 7"));
+
+        return Ok(());
+    }
+}
@@ -0,0 +1,418 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+#[cfg(feature = "llvm")]
+use std::io::Write;
+#[cfg(feature = "llvm")]
+use std::process::Command;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+#[cfg(feature = "llvm")]
+use inkwell::context::Context;
+#[cfg(feature = "llvm")]
+use inkwell::module::Module;
+#[cfg(feature = "llvm")]
+use inkwell::targets::{CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine, TargetTriple};
+
+#[cfg(feature = "llvm")]
+use crate::codegen::CodeGen;
+#[cfg(feature = "llvm")]
+use crate::header::c_header;
+#[cfg(feature = "llvm")]
+use crate::optimizer::{llvm_level, optimize};
+use crate::formatter::format_source;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::types::lang_type::LangType;
+#[cfg(feature = "vm")]
+use crate::vm::bytecode::{Program, MAGIC};
+#[cfg(feature = "vm")]
+use crate::vm::machine::Limits;
+
+/// What `build` writes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Emit {
+    #[default]
+    Executable,
+    Object,
+    Assembly,
+    LlvmIr,
+    LlvmBitcode,
+}
+
+impl Emit {
+    pub fn from_name(name: &str) -> Option<Emit> {
+        Some(match name {
+            "exe" => Emit::Executable,
+            "obj" => Emit::Object,
+            "asm" => Emit::Assembly,
+            "llvm-ir" => Emit::LlvmIr,
+            "llvm-bc" => Emit::LlvmBitcode,
+            _ => return None,
+        })
+    }
+
+    /// Extension of the default output file, executables have none.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Emit::Executable => "",
+            Emit::Object => "o",
+            Emit::Assembly => "s",
+            Emit::LlvmIr => "ll",
+            Emit::LlvmBitcode => "bc",
+        }
+    }
+}
+
+/// `-O0` to `-O3` and `-Os`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OptLevel {
+    #[default]
+    O0,
+    O1,
+    O2,
+    O3,
+    Os,
+}
+
+impl OptLevel {
+    /// Level from what follows `-O`.
+    pub fn from_name(name: &str) -> Option<OptLevel> {
+        Some(match name {
+            "0" => OptLevel::O0,
+            "1" => OptLevel::O1,
+            "2" => OptLevel::O2,
+            "3" => OptLevel::O3,
+            "s" => OptLevel::Os,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BuildOptions {
+    pub input: PathBuf,
+    pub output: Option<PathBuf>, // `-` writes to stdout, except for executables
+    pub emit: Emit,
+    pub opt_level: OptLevel,
+    pub target: Option<String>, // triple, the host when unset
+    pub cpu: Option<String>,
+    pub target_features: Option<String>,
+    pub linker: Option<String>,
+    pub header: Option<PathBuf>,
+    pub freestanding: bool,
+    pub bounds_checks: Option<bool>, // on for -O0 and -g builds when unset
+    pub debug_info: bool,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            input: PathBuf::new(),
+            output: None,
+            emit: Emit::Executable,
+            opt_level: OptLevel::O0,
+            target: None,
+            cpu: None,
+            target_features: None,
+            linker: None,
+            header: None,
+            freestanding: false,
+            bounds_checks: None,
+            debug_info: false,
+        }
+    }
+}
+
+impl BuildOptions {
+    pub fn new(input: PathBuf) -> Self {
+        Self {
+            input,
+            ..Default::default()
+        }
+    }
+
+    /// Whether out of bounds indices trap, by default only in unoptimized and debug builds.
+    pub fn checks_bounds(&self) -> bool {
+        self.bounds_checks.unwrap_or(self.opt_level == OptLevel::O0 || self.debug_info)
+    }
+
+    #[cfg(feature = "llvm")]
+    fn module_name(&self) -> String {
+        self.input
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| "main".to_string())
+    }
+}
+
+pub fn parse_source(source: String) -> Result<Vec<LangType>> {
+    let tokens = Lexer::new(source).collect()?;
+    Parser::new(tokens).parse_file()
+}
+
+fn read_source(input: &Path) -> Result<String> {
+    fs::read_to_string(input).map_err(|e| anyhow!("Cannot read {}: {}", input.display(), e))
+}
+
+/// Formats `files` in place, or only checks them with `check`. Returns the files that were not formatted.
+pub fn format_files(files: &[PathBuf], check: bool) -> Result<Vec<PathBuf>> {
+    let mut unformatted = vec![];
+    for file in files {
+        let source = read_source(file)?;
+        let formatted = format_source(&source).map_err(|e| anyhow!("{}: {}", file.display(), e))?;
+        if formatted == source {
+            continue;
+        }
+
+        if !check {
+            fs::write(file, &formatted).map_err(|e| anyhow!("Cannot write {}: {}", file.display(), e))?;
+        }
+        unformatted.append(&mut vec![file.clone()]);
+    }
+
+    Ok(unformatted)
+}
+
+/// Runs `input` with the interpreter, printing to stdout, and returns the exit code of `main`.
+#[cfg(feature = "interp")]
+pub fn interpret(input: &Path) -> Result<i64> {
+    let ast = parse_source(read_source(input)?)?;
+    crate::interp::run(ast, std::io::stdout())
+}
+
+/// Loads a bytecode file, or compiles `input` to bytecode when it is source.
+#[cfg(feature = "vm")]
+pub fn load_program(input: &Path) -> Result<Program> {
+    let bytes = fs::read(input).map_err(|e| anyhow!("Cannot read {}: {}", input.display(), e))?;
+    if bytes.starts_with(MAGIC) {
+        return Program::from_bytes(&bytes).map_err(|e| anyhow!("{}: {}", input.display(), e));
+    }
+
+    let source = String::from_utf8(bytes).map_err(|_| anyhow!("{} is not UTF-8", input.display()))?;
+    crate::vm::compiler::compile(parse_source(source)?)
+}
+
+/// Compiles `input` to a bytecode file, `<input>.mbc` unless `output` is given.
+#[cfg(feature = "vm")]
+pub fn write_bytecode(input: &Path, output: Option<PathBuf>) -> Result<()> {
+    let program = load_program(input)?;
+    let output = output.unwrap_or_else(|| input.with_extension("mbc"));
+    fs::write(&output, program.to_bytes()).map_err(|e| anyhow!("Cannot write {}: {}", output.display(), e))
+}
+
+/// Runs `input`, source or bytecode, on the VM, printing to stdout, and returns the exit code of `main`.
+#[cfg(feature = "vm")]
+pub fn run_vm(input: &Path, limits: Limits) -> Result<i64> {
+    let program = load_program(input)?;
+    crate::vm::machine::run(&program, std::io::stdout(), limits)
+}
+
+/// Builds `options.input` into a temporary executable, runs it and returns its exit code.
+#[cfg(feature = "llvm")]
+pub fn run(options: &BuildOptions) -> Result<i32> {
+    if options.target.is_some() && !is_host(&target_triple(options)) {
+        return Err(anyhow!("Cannot run a program built for {}", target_triple(options)));
+    }
+
+    let executable = std::env::temp_dir().join(format!("{}-{}", options.module_name(), std::process::id()));
+    build(&BuildOptions {
+        output: Some(executable.clone()),
+        emit: Emit::Executable,
+        ..options.clone()
+    })?;
+
+    let status = Command::new(&executable).status();
+    fs::remove_file(&executable)?;
+
+    status?
+        .code()
+        .ok_or_else(|| anyhow!("{} was terminated by a signal", options.input.display()))
+}
+
+/// Compiles `options.input` to what `options.emit` asks for, linking executables with `cc` or `--linker`.
+#[cfg(feature = "llvm")]
+pub fn build(options: &BuildOptions) -> Result<()> {
+    let source = read_source(&options.input)?;
+    // only debug info needs to know where statements are
+    let ast = if options.debug_info {
+        Parser::with_spans(Lexer::new(source).collect_spanned()?).parse_file()?
+    } else {
+        parse_source(source)?
+    };
+    let name = options.module_name();
+
+    if let Some(header) = &options.header {
+        fs::write(header, c_header(&name, &ast))?;
+    }
+
+    let reloc = if options.freestanding { RelocMode::Static } else { RelocMode::PIC };
+    let machine = target_machine(options, reloc)?;
+    let triple = machine.get_triple();
+
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context);
+    codegen.set_triple(&triple.as_str().to_string_lossy());
+    codegen.set_freestanding(options.freestanding);
+    codegen.set_bounds_checks(options.checks_bounds());
+    if options.debug_info {
+        codegen.set_debug_info(&options.input, options.opt_level != OptLevel::O0);
+    }
+    let module = codegen.compile_module(name.clone(), ast)?;
+
+    module.set_triple(&triple);
+    module.set_data_layout(&machine.get_target_data().get_data_layout());
+    optimize(&module, options.opt_level)?;
+
+    match options.emit {
+        Emit::Executable => {}
+        Emit::LlvmIr => return write_output(options, &name, module.print_to_string().to_bytes()),
+        Emit::LlvmBitcode => return write_output(options, &name, module.write_bitcode_to_memory().as_slice()),
+        Emit::Object | Emit::Assembly => {
+            let file_type = if options.emit == Emit::Object { FileType::Object } else { FileType::Assembly };
+            let buffer = machine
+                .write_to_memory_buffer(&module, file_type)
+                .map_err(|e| anyhow!("Cannot generate code for {}: {}", name, e))?;
+            return write_output(options, &name, buffer.as_slice());
+        }
+    }
+
+    if options.output.as_deref() == Some(Path::new("-")) {
+        return Err(anyhow!("Executables cannot be written to stdout, use --emit=obj"));
+    }
+    let object = std::env::temp_dir().join(format!("{}-{}.o", name, std::process::id()));
+    write_object(&machine, &module, &object)?;
+
+    // the host's cc can't link for other targets
+    let linker = match &options.linker {
+        Some(linker) => linker.as_str(),
+        None if is_host(&triple) => "cc",
+        None => return Err(anyhow!("Cannot link for {} without --linker, or use --emit=obj", triple)),
+    };
+    let executable = options.output.clone().unwrap_or_else(|| PathBuf::from(&name));
+    let linked = link(linker, &object, &executable, options.freestanding);
+    fs::remove_file(&object)?;
+
+    linked
+}
+
+/// Machine for `--target`, `--cpu` and `--target-features`, the host by default.
+#[cfg(feature = "llvm")]
+fn target_machine(options: &BuildOptions, reloc: RelocMode) -> Result<TargetMachine> {
+    if options.target.is_some() {
+        Target::initialize_all(&InitializationConfig::default());
+    } else {
+        Target::initialize_native(&InitializationConfig::default()).map_err(|e| anyhow!(e))?;
+    }
+
+    let triple = target_triple(options);
+    let target = Target::from_triple(&triple).map_err(|e| anyhow!("Unknown target {}: {}", triple, e))?;
+
+    // `--cpu native` also takes the host's features unless they are given
+    let (cpu, features) = match options.cpu.as_deref() {
+        Some("native") => (
+            TargetMachine::get_host_cpu_name().to_string(),
+            options
+                .target_features
+                .clone()
+                .unwrap_or_else(|| TargetMachine::get_host_cpu_features().to_string()),
+        ),
+        cpu => (
+            cpu.unwrap_or("generic").to_string(),
+            options.target_features.clone().unwrap_or_default(),
+        ),
+    };
+
+    target
+        .create_target_machine(
+            &triple,
+            &cpu,
+            &features,
+            llvm_level(options.opt_level),
+            reloc,
+            CodeModel::Default,
+        )
+        .ok_or_else(|| anyhow!("Cannot create target machine for {} with cpu {} and features '{}'", triple, cpu, features))
+}
+
+#[cfg(feature = "llvm")]
+fn target_triple(options: &BuildOptions) -> TargetTriple {
+    match &options.target {
+        Some(triple) => TargetMachine::normalize_triple(&TargetTriple::create(triple)),
+        None => TargetMachine::get_default_triple(),
+    }
+}
+
+#[cfg(feature = "llvm")]
+fn is_host(triple: &TargetTriple) -> bool {
+    triple.as_str() == TargetMachine::get_default_triple().as_str()
+}
+
+/// Writes `bytes` to the `-o` path, stdout for `-`, or `<name>.<extension>`.
+#[cfg(feature = "llvm")]
+fn write_output(options: &BuildOptions, name: &str, bytes: &[u8]) -> Result<()> {
+    let path = options
+        .output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(name).with_extension(options.emit.extension()));
+
+    if path == Path::new("-") {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(bytes)?;
+        return Ok(stdout.flush()?);
+    }
+
+    fs::write(&path, bytes).map_err(|e| anyhow!("Cannot write {}: {}", path.display(), e))
+}
+
+#[cfg(feature = "llvm")]
+fn write_object(machine: &TargetMachine, module: &Module, path: &Path) -> Result<()> {
+    machine
+        .write_to_file(module, FileType::Object, path)
+        .map_err(|e| anyhow!("Cannot write {}: {}", path.display(), e))
+}
+
+#[cfg(feature = "llvm")]
+fn link(linker: &str, object: &Path, executable: &Path, freestanding: bool) -> Result<()> {
+    let mut command = Command::new(linker);
+    if freestanding {
+        // codegen already emitted _start, so skip the C runtime entirely
+        command.args(["-nostdlib", "-static"]);
+    }
+    let status = command.arg(object).arg("-o").arg(executable).status()?;
+
+    if !status.success() {
+        return Err(anyhow!("Linking {} failed", executable.display()));
+    }
+
+    Ok(())
+}
+
+// ------------------------------------
+// Tests
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use super::{BuildOptions, OptLevel};
+
+    #[test]
+    fn driver_bounds_checks() -> Result<()> {
+        let options = |opt_level, debug_info, bounds_checks| BuildOptions {
+            opt_level,
+            debug_info,
+            bounds_checks,
+            ..Default::default()
+        };
+        assert!(options(OptLevel::O0, false, None).checks_bounds());
+        assert!(!options(OptLevel::O2, false, None).checks_bounds());
+        assert!(options(OptLevel::O2, true, None).checks_bounds());
+        assert!(!options(OptLevel::O0, true, Some(false)).checks_bounds());
+        assert!(options(OptLevel::O3, false, Some(true)).checks_bounds());
+
+        return Ok(());
+    }
+}
@@ -0,0 +1,219 @@
+use anyhow::{anyhow, Result};
+
+use crate::lexer::{Lexer, Span, Token};
+use crate::parser::{is_match_arm, opens_block, Parser};
+
+const INDENT: &str = "    ";
+
+/// An output line, its trailing comment is aligned once the whole file is known.
+struct Line {
+    code: String,
+    comment: Option<String>,
+}
+
+/// Formats `source`. Bodies of `fn`, `if`, `match`, `struct`, `enum` and `asm` are indented four
+/// spaces per level, `else`, match arms and `end` line up with the line that opened the block.
+/// Runs of blank lines shrink to one and trailing comments of consecutive lines share a column.
+/// The text of every line, strings spanning lines and asm instructions are kept as written.
+pub fn format_source(source: &str) -> Result<String> {
+    let tokens = Lexer::new(source.to_string()).collect_spanned()?;
+    if let Some((_, span)) = tokens.iter().find(|(t, _)| *t == Token::Illegal) {
+        return Err(anyhow!("Unexpected character at line {}, column {}", span.line, span.column));
+    }
+    // never rewrite a file that doesn't parse
+    Parser::new(tokens.iter().map(|(t, _)| t.clone()).collect()).parse_file()?;
+
+    let physical: Vec<&str> = source.split('\n').collect();
+
+    // tokens between newlines, with the physical line the newline ends
+    let mut logical: Vec<(Vec<(Token, Span)>, u32)> = vec![];
+    let mut current = vec![];
+    for (t, span) in tokens {
+        if t == Token::NewLine {
+            logical.append(&mut vec![(std::mem::take(&mut current), span.line)]);
+        } else {
+            current.append(&mut vec![(t, span)]);
+        }
+    }
+    logical.append(&mut vec![(current, physical.len() as u32)]);
+
+    let mut lines: Vec<Line> = vec![];
+    let mut blocks: Vec<bool> = vec![]; // open blocks, true for `match`
+    let mut next = 1;
+
+    for (tokens, last) in logical.into_iter().filter(|(tokens, _)| !tokens.is_empty()) {
+        let first = tokens[0].1.line;
+        if first > next && lines.last().is_some_and(|line| !line.code.is_empty()) {
+            lines.append(&mut vec![Line { code: String::new(), comment: None }]);
+        }
+        next = last + 1;
+
+        let code: Vec<Token> = tokens
+            .iter()
+            .map(|(t, _)| t.clone())
+            .filter(|t| !matches!(t, Token::Comment(_)))
+            .collect();
+        let depth = match code.first() {
+            Some(Token::End) => {
+                blocks.pop();
+                blocks.len()
+            }
+            Some(Token::Else) => blocks.len().saturating_sub(1),
+            _ if blocks.last() == Some(&true) && is_match_arm(&code) => blocks.len() - 1,
+            _ => blocks.len(),
+        };
+        if opens_block(&code) {
+            blocks.append(&mut vec![code.first() == Some(&Token::Match)]);
+        }
+
+        let indent = INDENT.repeat(depth);
+        let line = physical[first as usize - 1];
+        let text = line.trim_start_matches([' ', '\t']);
+
+        if last > first {
+            // a string continues on the next lines, they are part of its value
+            lines.append(&mut vec![Line { code: format!("{}{}", indent, text), comment: None }]);
+            for line in &physical[first as usize..last as usize] {
+                lines.append(&mut vec![Line { code: line.to_string(), comment: None }]);
+            }
+            continue;
+        }
+
+        match tokens.last() {
+            Some((Token::Comment(comment), span)) if tokens.len() > 1 => {
+                let start = line.char_indices().nth(span.column as usize - 1).map_or(line.len(), |(i, _)| i);
+                lines.append(&mut vec![Line {
+                    code: format!("{}{}", indent, text[..start - (line.len() - text.len())].trim_end()),
+                    comment: Some(format!("#{}", comment.trim_end())),
+                }]);
+            }
+            _ => lines.append(&mut vec![Line { code: format!("{}{}", indent, text.trim_end()), comment: None }]),
+        }
+    }
+
+    while lines.last().is_some_and(|line| line.code.is_empty() && line.comment.is_none()) {
+        lines.pop();
+    }
+
+    let mut formatted = String::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let group = lines[i..].iter().take_while(|line| line.comment.is_some()).count().max(1);
+        let width = lines[i..i + group].iter().map(|line| line.code.chars().count()).max().unwrap_or(0);
+        for line in &lines[i..i + group] {
+            match &line.comment {
+                Some(comment) => formatted.push_str(&format!("{:<width$} {}\n", line.code, comment, width = width)),
+                None => formatted.push_str(&format!("{}\n", line.code)),
+            }
+        }
+        i += group;
+    }
+
+    Ok(formatted)
+}
+
+// ------------------------------------
+// Tests
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use super::format_source;
+
+    #[test]
+    fn format_indent() -> Result<()> {
+        let input = "
+fn main:
+  let a 1
+
+
+  if a > 0:
+     add a 1
+  else:
+        sub a 1
+  end
+  match a:
+  2:
+   return 1
+  else:
+   return 0
+  end
+end
+enum Proto u8:
+ Tcp = 6
+end
+export fn nop:
+      asm:
+  nop
+      end
+end
+";
+        let expected = "fn main:
+    let a 1
+
+    if a > 0:
+        add a 1
+    else:
+        sub a 1
+    end
+    match a:
+    2:
+        return 1
+    else:
+        return 0
+    end
+end
+enum Proto u8:
+    Tcp = 6
+end
+export fn nop:
+    asm:
+        nop
+    end
+end
+";
+        assert_eq!(format_source(input)?, expected);
+        assert_eq!(format_source(expected)?, expected);
+
+        return Ok(());
+    }
+
+    #[test]
+    fn format_comments() -> Result<()> {
+        let input = "# header
+fn main:   # entry
+  # body
+  let a 1 # one
+  let value 22     # two
+  let b 3
+    return a  #   done
+end";
+        let expected = "# header
+fn main: # entry
+    # body
+    let a 1      # one
+    let value 22 # two
+    let b 3
+    return a #   done
+end
+";
+        assert_eq!(format_source(input)?, expected);
+        assert_eq!(format_source(expected)?, expected);
+
+        return Ok(());
+    }
+
+    #[test]
+    fn format_strings() -> Result<()> {
+        // the continuation lines are part of the string and keep their indentation
+        let input = "fn main:\n  let s \"a  \n  b # not a comment\n\" # c\n  print(s)\nend\n";
+        let expected = "fn main:\n    let s \"a  \n  b # not a comment\n\" # c\n    print(s)\nend\n";
+        assert_eq!(format_source(input)?, expected);
+
+        assert!(format_source("fn main:\n\tlet a 1\nend\n").is_err());
+        assert!(format_source("fn main:\n    if a 1:\n    end\nend\n").is_err());
+
+        return Ok(());
+    }
+}
@@ -0,0 +1,223 @@
+use crate::lexer::{Token, CONTEXTUAL_KEYWORDS, KEYWORDS};
+use crate::types::call_type::BUILTINS;
+use crate::types::data_type::TYPE_NAMES;
+use crate::types::op_type::INSTRUCTIONS;
+
+/// The few JSON values a grammar needs, objects keep their order.
+enum Json {
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    fn write(&self, out: &mut String, indent: usize) {
+        let pad = "  ".repeat(indent + 1);
+        match self {
+            Json::Str(text) => {
+                out.push('"');
+                for c in text.chars() {
+                    match c {
+                        '"' => out.push_str("\\\""),
+                        '\\' => out.push_str("\\\\"),
+                        c => out.push(c),
+                    }
+                }
+                out.push('"');
+            }
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    out.push_str(if i == 0 { "\n" } else { ",\n" });
+                    out.push_str(&pad);
+                    item.write(out, indent + 1);
+                }
+                out.push_str(&format!("\n{}]", "  ".repeat(indent)));
+            }
+            Json::Object(fields) => {
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    out.push_str(if i == 0 { "\n" } else { ",\n" });
+                    out.push_str(&format!("{}\"{}\": ", pad, key));
+                    value.write(out, indent + 1);
+                }
+                out.push_str(&format!("\n{}}}", "  ".repeat(indent)));
+            }
+        }
+    }
+}
+
+fn str(text: &str) -> Json {
+    Json::Str(text.to_string())
+}
+
+/// `{"name": scope, "match": regex}`
+fn rule(scope: &str, regex: &str) -> Json {
+    Json::Object(vec![("name", str(scope)), ("match", str(regex))])
+}
+
+/// `{"match": regex, "captures": {"1": {"name": scope}, ..}}`
+fn captures(regex: &str, scopes: &[(&'static str, &str)]) -> Json {
+    let names = scopes.iter().map(|(group, scope)| (*group, Json::Object(vec![("name", str(scope))]))).collect();
+    Json::Object(vec![("match", str(regex)), ("captures", Json::Object(names))])
+}
+
+/// `\b(a|b|c)\b`
+fn words(words: &[&str]) -> String {
+    format!("\\b({})\\b", words.join("|"))
+}
+
+/// TextMate grammar for `.masm` files, as JSON. Keywords, contextual keywords, instruction
+/// mnemonics, types and builtins come from the tables the lexer and parser use.
+pub fn textmate_grammar() -> String {
+    let keywords = |tokens: &[Token]| -> Vec<&str> {
+        KEYWORDS.iter().filter(|(_, t)| tokens.contains(t)).map(|(word, _)| *word).collect()
+    };
+    let declarations = keywords(&[Token::Function, Token::Struct, Token::Enum, Token::Let, Token::Const]);
+    let modifiers = keywords(&[Token::Extern, Token::Export]);
+    let control = keywords(&[Token::If, Token::Else, Token::Match, Token::Return, Token::End]);
+    let instructions: Vec<&str> = INSTRUCTIONS.iter().copied().filter(|op| *op != "addr").collect();
+
+    let repository = vec![
+        ("comment", rule("comment.line.number-sign.masm", "#.*$")),
+        (
+            "asm",
+            Json::Object(vec![
+                ("begin", str("^\\s*(asm)\\b([^#]*)")),
+                (
+                    "beginCaptures",
+                    Json::Object(vec![
+                        ("1", Json::Object(vec![("name", str("keyword.other.asm.masm"))])),
+                        (
+                            "2",
+                            Json::Object(vec![(
+                                "patterns",
+                                Json::Array(vec![
+                                    rule("keyword.other.masm", &words(CONTEXTUAL_KEYWORDS)),
+                                    Json::Object(vec![("include", str("#string"))]),
+                                ]),
+                            )]),
+                        ),
+                    ]),
+                ),
+                ("end", str("^\\s*(end)\\b")),
+                ("endCaptures", Json::Object(vec![("1", Json::Object(vec![("name", str("keyword.control.masm"))]))])),
+                ("contentName", str("source.asm.embedded.masm")),
+                ("patterns", Json::Array(vec![Json::Object(vec![("include", str("#comment"))])])),
+            ]),
+        ),
+        (
+            "string",
+            Json::Array(vec![
+                Json::Object(vec![
+                    ("name", str("string.quoted.double.masm")),
+                    ("begin", str("\"")),
+                    ("end", str("\"")),
+                    ("patterns", Json::Array(vec![rule("constant.character.escape.masm", "\\\\(x[0-9a-fA-F]{2}|.)")])),
+                ]),
+                Json::Object(vec![
+                    ("name", str("string.quoted.raw.masm")),
+                    ("begin", str("\\br(#*)\"")),
+                    ("end", str("\"\\1")),
+                ]),
+                rule("string.quoted.single.masm", "'(\\\\(x[0-9a-fA-F]{2}|.)|[^'\\\\])'"),
+            ]),
+        ),
+        (
+            "number",
+            rule(
+                "constant.numeric.masm",
+                "-?\\b(0[xX][0-9a-fA-F_]+|0[bB][01_]+|0[oO][0-7_]+|[0-9][0-9_]*(\\.[0-9][0-9_]*)?([eE][+-]?[0-9_]+)?)\\b",
+            ),
+        ),
+        ("bool", rule("constant.language.boolean.masm", &words(&["true", "false"]))),
+        (
+            "function",
+            captures(
+                &format!("{}\\s+([A-Za-z_][A-Za-z0-9_]*)", words(&keywords(&[Token::Function]))),
+                &[("1", "storage.type.masm"), ("2", "entity.name.function.masm")],
+            ),
+        ),
+        (
+            "type-definition",
+            captures(
+                &format!("{}\\s+([A-Za-z_][A-Za-z0-9_]*)", words(&keywords(&[Token::Struct, Token::Enum]))),
+                &[("1", "storage.type.masm"), ("2", "entity.name.type.masm")],
+            ),
+        ),
+        ("declaration", rule("storage.type.masm", &words(&declarations))),
+        ("modifier", rule("storage.modifier.masm", &words(&modifiers))),
+        ("control", rule("keyword.control.masm", &words(&control))),
+        ("contextual", rule("keyword.other.masm", &words(CONTEXTUAL_KEYWORDS))),
+        (
+            "instruction",
+            Json::Array(vec![
+                captures(&format!("^\\s*{}", words(&instructions)), &[("1", "keyword.operator.instruction.masm")]),
+                rule("keyword.operator.instruction.masm", &words(&["addr"])),
+            ]),
+        ),
+        ("type", rule("storage.type.builtin.masm", &words(TYPE_NAMES))),
+        ("builtin", captures(&format!("{}\\s*(?=\\()", words(BUILTINS)), &[("1", "support.function.builtin.masm")])),
+        ("call", captures("\\b([A-Za-z_][A-Za-z0-9_]*)\\s*(?=\\()", &[("1", "entity.name.function.call.masm")])),
+        ("constant", rule("variable.other.constant.masm", "\\b[A-Z][A-Z0-9_]*\\b")),
+    ];
+
+    // the order of `patterns` decides which rule wins
+    let patterns = Json::Array(
+        repository
+            .iter()
+            .map(|(name, _)| Json::Object(vec![("include", Json::Str(format!("#{}", name)))]))
+            .collect(),
+    );
+    let repository = Json::Object(
+        repository
+            .into_iter()
+            .map(|(name, value)| match value {
+                Json::Array(rules) => (name, Json::Object(vec![("patterns", Json::Array(rules))])),
+                value => (name, value),
+            })
+            .collect(),
+    );
+
+    let grammar = Json::Object(vec![
+        ("$schema", str("https://raw.githubusercontent.com/martinring/tmlanguage/master/tmlanguage.json")),
+        ("name", str("Modern Assembly")),
+        ("scopeName", str("source.masm")),
+        ("fileTypes", Json::Array(vec![str("masm")])),
+        ("patterns", patterns),
+        ("repository", repository),
+    ]);
+
+    let mut out = String::new();
+    grammar.write(&mut out, 0);
+    out.push('\n');
+    out
+}
+
+// ------------------------------------
+// Tests
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use super::textmate_grammar;
+
+    #[test]
+    fn grammar_tables() -> Result<()> {
+        let grammar = textmate_grammar();
+
+        assert!(grammar.contains(r#""match": "\\b(fn|struct|enum|let|const)\\b""#));
+        assert!(grammar.contains(r#""match": "\\b(extern|export|pub)\\b""#));
+        assert!(grammar.contains(r#""match": "\\b(if|else|match|return|end)\\b""#));
+        assert!(grammar.contains(
+            r#""match": "^\\s*\\b(add|sub|mul|div|mod|offset|neg|itof|ftoi|load|store|move|cast|bitcast)\\b""#
+        ));
+        assert!(grammar.contains(r#""match": "\\b(as|packed|align|intel|att|out|in|clobber)\\b""#));
+        assert!(grammar.contains(r##""include": "#comment""##));
+        assert!(grammar.contains(r#""match": "\\b(fn)\\b\\s+([A-Za-z_][A-Za-z0-9_]*)""#));
+        assert!(grammar.contains(r#""match": "\\b(struct|enum)\\b\\s+([A-Za-z_][A-Za-z0-9_]*)""#));
+
+        return Ok(());
+    }
+}
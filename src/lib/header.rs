@@ -0,0 +1,153 @@
+use crate::types::data_type::DataType;
+use crate::types::lang_type::LangType;
+
+/// Renders a C header declaring every struct and exported function of `ast`.
+pub fn c_header(name: &str, ast: &[LangType]) -> String {
+    let guard = format!(
+        "{}_H",
+        name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect::<String>()
+    );
+
+    let mut header = String::new();
+    header.push_str(&format!("#ifndef {}\n#define {}\n\n", guard, guard));
+    header.push_str("#include <stdbool.h>\n#include <stdint.h>\n\n");
+    header.push_str("#ifdef __cplusplus\nextern \"C\" {\n#endif\n\n");
+
+    for lang_t in ast {
+        if let LangType::Struct(struct_t) = lang_t {
+            header.push_str(&format!("struct {} {{\n", struct_t.name));
+            for field in &struct_t.fields {
+                header.push_str(&format!("    {};\n", c_decl(&field.data_type.clone().unwrap_or_default(), &field.name)));
+            }
+
+            let mut attributes = vec![];
+            if struct_t.packed {
+                attributes.append(&mut vec!["packed".to_string()]);
+            }
+            if let Some(align) = struct_t.align {
+                attributes.append(&mut vec![format!("aligned({})", align)]);
+            }
+            if attributes.is_empty() {
+                header.push_str("};\n\n");
+            } else {
+                header.push_str(&format!("}} __attribute__(({}));\n\n", attributes.join(", ")));
+            }
+        }
+    }
+
+    for lang_t in ast {
+        if let LangType::Func(func) = lang_t {
+            if !func.is_export {
+                continue;
+            }
+
+            let params = func
+                .param
+                .iter()
+                .enumerate()
+                .map(|(i, p)| {
+                    let name = if p.name.is_empty() { format!("arg{}", i) } else { p.name.clone() };
+                    format!("{} {}", c_type(&p.data_type.clone().unwrap_or_default()), name)
+                })
+                .collect::<Vec<String>>();
+            let params = if params.is_empty() { "void".to_string() } else { params.join(", ") };
+
+            header.push_str(&format!("{} {}({});\n", c_type(&func.ret), func.name, params));
+        }
+    }
+
+    header.push_str("\n#ifdef __cplusplus\n}\n#endif\n\n");
+    header.push_str(&format!("#endif /* {} */\n", guard));
+
+    header
+}
+
+fn c_type(data_type: &DataType) -> String {
+    match data_type {
+        DataType::I8 => "int8_t".to_string(),
+        DataType::I16 => "int16_t".to_string(),
+        DataType::I32 => "int32_t".to_string(),
+        DataType::I64 => "int64_t".to_string(),
+        DataType::U8 => "uint8_t".to_string(),
+        DataType::U16 => "uint16_t".to_string(),
+        DataType::U32 => "uint32_t".to_string(),
+        DataType::U64 => "uint64_t".to_string(),
+        DataType::F32 => "float".to_string(),
+        DataType::F64 => "double".to_string(),
+        DataType::Bool => "bool".to_string(),
+        DataType::Str => "const char *".to_string(),
+        DataType::Ptr(inner) => format!("{} *", c_type(inner)),
+        // arrays only reach C behind a pointer, which points at the first element
+        DataType::Array(elem, _) => format!("{} *", c_type(elem)),
+        DataType::Struct(name) => format!("struct {}", name),
+        DataType::Void => "void".to_string(),
+    }
+}
+
+/// Declaration of `name`, arrays keep their length as struct fields do.
+fn c_decl(data_type: &DataType, name: &str) -> String {
+    match data_type {
+        DataType::Array(elem, len) => c_decl(elem, &format!("{}[{}]", name, len)),
+        other => format!("{} {}", c_type(other), name),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use super::c_header;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn header_exports_only() -> Result<()> {
+        let input = r#"export fn square x:i64 -> i64:
+    mul x x
+    return x
+end
+fn helper:
+end
+pub fn greet name:str -> void:
+    print(name)
+end
+export fn fill buf:ptr<u8> raw:ptr -> void:
+end"#;
+
+        let ast = Parser::new(Lexer::new(input.into()).collect()?).parse_file()?;
+        let header = c_header("my-lib", &ast);
+
+        assert!(header.starts_with("#ifndef MY_LIB_H\n#define MY_LIB_H\n"));
+        assert!(header.contains("int64_t square(int64_t x);\n"));
+        assert!(header.contains("void greet(const char * name);\n"));
+        assert!(header.contains("void fill(uint8_t * buf, void * raw);\n"));
+        assert!(!header.contains("helper"));
+
+        return Ok(());
+    }
+
+    #[test]
+    fn header_structs() -> Result<()> {
+        let input = r#"struct Udp packed align(4):
+    src: u16
+    dst: u16
+    data: [[u8; 4]; 2]
+end
+export fn checksum pkt:ptr<Udp> -> u16:
+    load x pkt.src
+    return x
+end"#;
+
+        let ast = Parser::new(Lexer::new(input.into()).collect()?).parse_file()?;
+        let header = c_header("udp", &ast);
+
+        assert!(header.contains(
+            "struct Udp {\n    uint16_t src;\n    uint16_t dst;\n    uint8_t data[2][4];\n} __attribute__((packed, aligned(4)));\n"
+        ));
+        assert!(header.contains("uint16_t checksum(struct Udp * pkt);\n"));
+
+        return Ok(());
+    }
+}
@@ -0,0 +1,805 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::{anyhow, Result};
+
+use crate::checker::{literal_type, Checker, Scope};
+use crate::types::call_type::CallType;
+use crate::types::data_type::DataType;
+use crate::types::enum_type::EnumType;
+use crate::types::func_type::FuncType;
+use crate::types::if_type::IfType;
+use crate::types::lang_type::LangType;
+use crate::types::match_type::MatchType;
+use crate::types::op_type::OpType;
+use crate::types::op_type::Operation;
+use crate::types::primitive_type::format_g;
+use crate::types::primitive_type::PrimitiveType;
+use crate::types::primitive_type::Primitives;
+use crate::types::return_type::ReturnType;
+use crate::types::struct_type::StructType;
+
+// calls recurse on the host stack, `run` gives them a thread with room for this many
+const MAX_CALL_DEPTH: usize = 10_000;
+const STACK_SIZE: usize = 256 << 20;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(u64, DataType), // bits of the value, truncated to the width of the type
+    Float(f64, DataType),
+    Bool(bool),
+    Str(Vec<u8>),
+    Void,
+}
+
+impl Value {
+    /// Integer of `data_type`, wrapping `value` to its width like the generated code does.
+    pub fn int(value: i128, data_type: DataType) -> Self {
+        let bits = data_type.bit_width();
+        let mask = if bits >= 64 { u64::MAX } else { (1 << bits) - 1 };
+        Value::Int(value as u64 & mask, data_type)
+    }
+
+    /// Float of `data_type`, `f32` values are rounded to single precision.
+    pub fn float(value: f64, data_type: DataType) -> Self {
+        let value = if data_type == DataType::F32 { value as f32 as f64 } else { value };
+        Value::Float(value, data_type)
+    }
+
+    pub fn data_type(&self) -> DataType {
+        match self {
+            Value::Int(_, data_type) | Value::Float(_, data_type) => data_type.clone(),
+            Value::Bool(_) => DataType::Bool,
+            Value::Str(_) => DataType::Str,
+            Value::Void => DataType::Void,
+        }
+    }
+
+    /// Numeric value of an integer or bool, sign extended for signed types.
+    pub fn to_i128(&self) -> Option<i128> {
+        match self {
+            Value::Int(bits, data_type) if data_type.is_signed() => {
+                let shift = 64 - data_type.bit_width();
+                Some(((*bits << shift) as i64 >> shift) as i128)
+            }
+            Value::Int(bits, _) => Some(*bits as i128),
+            Value::Bool(value) => Some(*value as i128),
+            _ => None,
+        }
+    }
+
+    fn zero(data_type: &DataType) -> Result<Self> {
+        Ok(match data_type {
+            t if t.is_int() => Value::int(0, t.clone()),
+            t if t.is_float() => Value::float(0.0, t.clone()),
+            DataType::Bool => Value::Bool(false),
+            DataType::Str => Value::Str(vec![]),
+            other => return Err(anyhow!("The interpreter does not support values of type {}", other)),
+        })
+    }
+}
+
+enum Flow {
+    Next,
+    Return(Value),
+}
+
+/// Executes the AST directly, without LLVM. Follows the typing rules of codegen, so programs
+/// it accepts print the same as the compiled binary. Pointers, arrays, structs, asm and
+/// syscalls are not supported.
+pub struct Interpreter<W: Write> {
+    functions: HashMap<String, FuncType>,
+    constants: HashMap<String, PrimitiveType>,       // of the file
+    frame_constants: HashMap<String, PrimitiveType>, // declared by the running function
+    enums: HashMap<String, EnumType>,
+    variables: HashMap<String, Value>,
+    return_type: DataType,
+    depth: usize,
+    out: W,
+}
+
+/// Only enums, the interpreter has no structs.
+impl<W: Write> Scope for Interpreter<W> {
+    fn variable(&self, name: &str) -> Option<DataType> {
+        self.variables.get(name).map(|v| v.data_type())
+    }
+
+    fn constant(&self, name: &str) -> Option<&PrimitiveType> {
+        self.frame_constants.get(name).or_else(|| self.constants.get(name))
+    }
+
+    fn function(&self, name: &str) -> Option<&FuncType> {
+        self.functions.get(name)
+    }
+
+    fn struct_type(&self, _: &str) -> Option<&StructType> {
+        None
+    }
+
+    fn enum_type(&self, name: &str) -> Option<&EnumType> {
+        self.enums.get(name)
+    }
+}
+
+impl<W: Write> Interpreter<W> {
+    pub fn new(ast: Vec<LangType>, out: W) -> Result<Self> {
+        // codegen rejects these before anything runs, so does the interpreter
+        if let Some(error) = Checker::check(&ast).errors.into_iter().next() {
+            return Err(anyhow!(error.message));
+        }
+
+        let mut interp = Self {
+            functions: HashMap::new(),
+            constants: HashMap::new(),
+            frame_constants: HashMap::new(),
+            enums: HashMap::new(),
+            variables: HashMap::new(),
+            return_type: DataType::Void,
+            depth: 0,
+            out,
+        };
+
+        for lang_t in ast {
+            match lang_t {
+                LangType::Func(func) => {
+                    interp.functions.insert(func.name.clone(), func);
+                }
+                LangType::Const(con) => {
+                    interp.constants.insert(con.name, con.value);
+                }
+                LangType::Enum(enum_t) => {
+                    interp.enums.insert(enum_t.name.clone(), enum_t);
+                }
+                LangType::Struct(_) | LangType::Comment(_) => {}
+                _ => return Err(anyhow!("Expression outside of function!")),
+            }
+        }
+
+        Ok(interp)
+    }
+
+    /// Runs `main` and returns its exit code, 0 for a void `main`.
+    pub fn run_main(&mut self) -> Result<i64> {
+        let main = CallType {
+            name: "main".to_string(),
+            param: vec![],
+        };
+
+        match self.call(main)? {
+            Value::Void => Ok(0),
+            value => value
+                .to_i128()
+                .filter(|_| value.data_type().is_int())
+                .map(|code| code as i64)
+                .ok_or_else(|| anyhow!("main must return an integer or void, not {}", value.data_type())),
+        }
+    }
+
+    pub fn into_output(self) -> W {
+        self.out
+    }
+
+    fn exec_block(&mut self, body: Vec<LangType>) -> Result<Flow> {
+        for stmt in body {
+            if let Flow::Return(value) = self.exec_stmt(stmt)? {
+                return Ok(Flow::Return(value));
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn exec_stmt(&mut self, stmt: LangType) -> Result<Flow> {
+        match stmt {
+            LangType::Op(op) if matches!(op.op, Operation::Assign) => self.exec_assign(op)?,
+            LangType::Op(op) if matches!(op.op, Operation::Store) => return Err(unsupported("store")),
+            LangType::Call(call) => {
+                self.call(call)?;
+            }
+            LangType::If(if_t) => return self.exec_if(if_t),
+            LangType::Match(match_t) => return self.exec_match(match_t),
+            LangType::Return(ret) => return self.exec_return(ret),
+            LangType::Asm(_) => return Err(unsupported("inline asm")),
+            LangType::Const(con) => {
+                self.frame_constants.insert(con.name, con.value);
+            }
+            LangType::Comment(_) | LangType::Location(_) => {}
+            other => return Err(anyhow!("Unexpected statement {:?}", other)),
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn exec_assign(&mut self, op: OpType) -> Result<()> {
+        let (name, declared_type) = match *op.lhs {
+            LangType::Var(var) => (var.name, var.data_type),
+            other => return Err(anyhow!("Cannot assign to {:?}", other)),
+        };
+
+        if self.constant(&name).is_some() {
+            return Err(anyhow!("Cannot assign to constant {}", name));
+        }
+
+        if let Some(data_type) = declared_type {
+            if self.variables.contains_key(&name) {
+                return Err(anyhow!("Variable {} is already declared", name));
+            }
+            let value = match *op.rhs {
+                LangType::Undefined => Value::zero(&data_type)?,
+                rhs => self.eval(rhs, Some(&data_type))?,
+            };
+            if value.data_type() != data_type {
+                return Err(anyhow!("Cannot assign {} to {} of type {}", value.data_type(), name, data_type));
+            }
+            self.variables.insert(name, value);
+
+            return Ok(());
+        }
+
+        let value = match self.variables.get(&name).map(|v| v.data_type()) {
+            Some(data_type) => {
+                let value = self.eval(*op.rhs, Some(&data_type))?;
+                if value.data_type() != data_type {
+                    return Err(anyhow!("Cannot assign {} to {} of type {}", value.data_type(), name, data_type));
+                }
+                value
+            }
+            None => self.eval(*op.rhs, None)?,
+        };
+        self.variables.insert(name, value);
+
+        Ok(())
+    }
+
+    fn exec_if(&mut self, if_t: IfType) -> Result<Flow> {
+        match self.eval(*if_t.condition, None)? {
+            Value::Bool(true) => self.exec_block(if_t.body),
+            Value::Bool(false) => self.exec_block(if_t.else_body),
+            other => Err(anyhow!("If condition must be bool, got {}", other.data_type())),
+        }
+    }
+
+    fn exec_match(&mut self, match_t: MatchType) -> Result<Flow> {
+        let value = self.eval(*match_t.value.clone(), None)?;
+        let value_type = value.data_type();
+        if !value_type.is_int() {
+            return Err(anyhow!("Cannot match on {}", value_type));
+        }
+        self.check_match(&match_t, &value_type)?;
+
+        for arm in match_t.arms {
+            for pattern in &arm.patterns {
+                let case = self.eval(pattern.clone(), Some(&value_type))?;
+                if case == value {
+                    return self.exec_block(arm.body);
+                }
+            }
+        }
+
+        match match_t.default {
+            Some(body) => self.exec_block(body),
+            None => Err(anyhow!("No match arm for {:?}", value)),
+        }
+    }
+
+    fn exec_return(&mut self, ret: ReturnType) -> Result<Flow> {
+        let return_type = self.return_type.clone();
+
+        let value = match (*ret.value, &return_type) {
+            (LangType::Undefined, DataType::Void) => Value::Void,
+            (LangType::Undefined, _) => return Err(anyhow!("Missing return value of type {}", return_type)),
+            (_, DataType::Void) => return Err(anyhow!("Cannot return a value from a void function")),
+            (value, _) => {
+                let value = self.eval(value, Some(&return_type))?;
+                if value.data_type() != return_type {
+                    return Err(anyhow!(
+                        "Cannot return {} from a function returning {}",
+                        value.data_type(),
+                        return_type
+                    ));
+                }
+                value
+            }
+        };
+
+        Ok(Flow::Return(value))
+    }
+
+    fn call(&mut self, call: CallType) -> Result<Value> {
+        if !self.functions.contains_key(&call.name) {
+            match call.name.as_str() {
+                "print" => return self.print(call.param).map(|_| Value::Void),
+                "len" | "substr" => return self.str_call(call),
+                "syscall" => return Err(unsupported("syscall")),
+                _ => {}
+            }
+        }
+
+        let func = self
+            .functions
+            .get(&call.name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown function {}", call.name))?;
+        if func.is_extern {
+            return Err(anyhow!("The interpreter cannot call extern function {}", func.name));
+        }
+        if call.param.len() != func.param.len() {
+            return Err(anyhow!(
+                "Function {} expects {} arguments, got {}",
+                call.name,
+                func.param.len(),
+                call.param.len()
+            ));
+        }
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(anyhow!("Call depth exceeded {} calling {}", MAX_CALL_DEPTH, call.name));
+        }
+
+        let mut frame = HashMap::new();
+        for (i, (arg, param)) in call.param.into_iter().zip(&func.param).enumerate() {
+            let expected = param.data_type.clone().unwrap_or_default();
+            let value = self.eval(arg, Some(&expected))?;
+            if value.data_type() != expected {
+                return Err(anyhow!(
+                    "Argument {} of {} must be {}, got {}",
+                    i + 1,
+                    call.name,
+                    expected,
+                    value.data_type()
+                ));
+            }
+            frame.insert(param.name.clone(), value);
+        }
+
+        let caller_variables = std::mem::replace(&mut self.variables, frame);
+        let caller_constants = std::mem::take(&mut self.frame_constants);
+        let caller_return = std::mem::replace(&mut self.return_type, func.ret.clone());
+        self.depth += 1;
+        let flow = self.exec_block(func.body);
+        self.depth -= 1;
+        self.variables = caller_variables;
+        self.frame_constants = caller_constants;
+        self.return_type = caller_return;
+
+        match flow? {
+            Flow::Return(value) => Ok(value),
+            // falling off the end returns zero, like the generated code
+            Flow::Next if func.ret == DataType::Void => Ok(Value::Void),
+            Flow::Next => Value::zero(&func.ret),
+        }
+    }
+
+    /// Same output as the `printf` call codegen emits.
+    fn print(&mut self, params: Vec<LangType>) -> Result<()> {
+        let mut line: Vec<u8> = vec![];
+
+        for param in params {
+            if !line.is_empty() {
+                line.push(b' ');
+            }
+
+            match self.eval(param, None)? {
+                Value::Str(s) => line.extend_from_slice(&s),
+                Value::Float(value, _) => line.extend_from_slice(format_g(value).as_bytes()),
+                Value::Void => return Err(anyhow!("Cannot print a value of type void")),
+                value => {
+                    let value = value.to_i128().unwrap_or_default();
+                    line.extend_from_slice(value.to_string().as_bytes());
+                }
+            }
+        }
+        line.push(b'\n');
+
+        self.out.write_all(&line)?;
+        Ok(())
+    }
+
+    /// `len(s) -> u64` and `substr(s, start, count) -> str`.
+    fn str_call(&mut self, call: CallType) -> Result<Value> {
+        let expected = if call.name == "len" { 1 } else { 3 };
+        if call.param.len() != expected {
+            return Err(anyhow!("{} expects {} arguments, got {}", call.name, expected, call.param.len()));
+        }
+
+        let mut params = call.param.into_iter();
+        let s = match self.eval(params.next().unwrap_or(LangType::Undefined), None)? {
+            Value::Str(s) => s,
+            other => return Err(anyhow!("{} expects a str, got {}", call.name, other.data_type())),
+        };
+
+        let mut bounds = vec![];
+        for param in params {
+            let value = self.eval(param, Some(&DataType::U64))?;
+            if !value.data_type().is_int() {
+                return Err(anyhow!("{} expects integer bounds, got {}", call.name, value.data_type()));
+            }
+            // the runtime takes the bounds as u64, negative ones become huge
+            bounds.push(value.to_i128().unwrap_or_default() as u64);
+        }
+
+        if call.name == "len" {
+            return Ok(Value::int(s.len() as i128, DataType::U64));
+        }
+
+        let start = (bounds[0] as usize).min(s.len());
+        let count = (bounds[1] as usize).min(s.len() - start);
+        Ok(Value::Str(s[start..start + count].to_vec()))
+    }
+
+    fn eval(&mut self, expr: LangType, hint: Option<&DataType>) -> Result<Value> {
+        match expr {
+            LangType::Primitive(p) => primitive(p, hint),
+            LangType::Var(var) => {
+                if let Some(con) = self.constant(&var.name).cloned() {
+                    return primitive(con, hint);
+                }
+
+                self.variables
+                    .get(&var.name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Unknown variable {}", var.name))
+            }
+            LangType::Op(op) => self.eval_op(op, hint),
+            LangType::Call(call) => {
+                let name = call.name.clone();
+                match self.call(call)? {
+                    Value::Void => Err(anyhow!("Function {} does not return a value", name)),
+                    value => Ok(value),
+                }
+            }
+            other => Err(anyhow!("Unexpected expression {:?}", other)),
+        }
+    }
+
+    fn eval_op(&mut self, op: OpType, hint: Option<&DataType>) -> Result<Value> {
+        match op.op {
+            Operation::Field => return self.eval_variant(*op.lhs, *op.rhs),
+            Operation::Addr | Operation::Load | Operation::Offset | Operation::Index => {
+                return Err(unsupported("pointers"))
+            }
+            Operation::Store => return Err(anyhow!("store does not produce a value")),
+            Operation::Neg => return self.eval_neg(*op.lhs, hint),
+            Operation::IntToFloat => {
+                let to = hint.filter(|t| t.is_float()).cloned().unwrap_or(DataType::F64);
+                let value = self.eval(*op.lhs, None)?;
+                if !value.data_type().is_int() {
+                    return Err(anyhow!("itof expects an integer, got {}", value.data_type()));
+                }
+                return cast(value, to);
+            }
+            Operation::FloatToInt => {
+                let to = hint.filter(|t| t.is_int()).cloned().unwrap_or(DataType::I64);
+                let value = self.eval(*op.lhs, None)?;
+                if !value.data_type().is_float() {
+                    return Err(anyhow!("ftoi expects a float, got {}", value.data_type()));
+                }
+                return cast(value, to);
+            }
+            Operation::Cast => {
+                let to = match *op.rhs {
+                    LangType::Type(data_type) => data_type,
+                    other => return Err(anyhow!("Expected a type to cast to, got {:?}", other)),
+                };
+                let value = self.eval(*op.lhs, None)?;
+                return cast(value, to);
+            }
+            Operation::Bitcast => {
+                let to = match *op.rhs {
+                    LangType::Type(data_type) => data_type,
+                    other => return Err(anyhow!("Expected a type to cast to, got {:?}", other)),
+                };
+                let value = self.eval(*op.lhs, None)?;
+                return bitcast(value, to);
+            }
+            _ => {}
+        }
+
+        let is_compare = matches!(
+            op.op,
+            Operation::Equal | Operation::NotEqual | Operation::LessThan | Operation::GreaterThan
+        );
+
+        // literals take the width of the other operand
+        let operand_hint = self
+            .type_of(&op.lhs)
+            .or_else(|| self.type_of(&op.rhs))
+            .or_else(|| if is_compare { None } else { hint.cloned() });
+
+        let lhs = self.eval(*op.lhs, operand_hint.as_ref())?;
+        let rhs = self.eval(*op.rhs, Some(&lhs.data_type()))?;
+        let data_type = lhs.data_type();
+        if data_type != rhs.data_type() {
+            return Err(anyhow!("Mismatched operand types {} and {}", data_type, rhs.data_type()));
+        }
+
+        let order = match (&lhs, &rhs) {
+            (Value::Str(a), Value::Str(b)) => {
+                if let Operation::Add = op.op {
+                    return Ok(Value::Str([a.as_slice(), b.as_slice()].concat()));
+                }
+                a.cmp(b)
+            }
+            (Value::Float(a, _), Value::Float(b, _)) => {
+                let value = match op.op {
+                    Operation::Add => a + b,
+                    Operation::Sub => a - b,
+                    Operation::Mul => a * b,
+                    Operation::Div => a / b,
+                    Operation::Mod => a % b,
+                    // NaN is unordered, only != holds
+                    Operation::Equal => return Ok(Value::Bool(a == b)),
+                    Operation::NotEqual => return Ok(Value::Bool(a != b)),
+                    Operation::LessThan => return Ok(Value::Bool(a < b)),
+                    Operation::GreaterThan => return Ok(Value::Bool(a > b)),
+                    _ => return Err(anyhow!("Operation {:?} is not supported for {}", op.op, data_type)),
+                };
+                return Ok(Value::float(value, data_type));
+            }
+            (Value::Bool(a), Value::Bool(b)) if matches!(op.op, Operation::Equal | Operation::NotEqual) => a.cmp(b),
+            (Value::Int(..), Value::Int(..)) => {
+                let a = lhs.to_i128().unwrap_or_default();
+                let b = rhs.to_i128().unwrap_or_default();
+                if is_compare {
+                    a.cmp(&b)
+                } else {
+                    if matches!(op.op, Operation::Div | Operation::Mod) && b == 0 {
+                        return Err(anyhow!("Division by zero"));
+                    }
+                    let value = match op.op {
+                        Operation::Add => a.wrapping_add(b),
+                        Operation::Sub => a.wrapping_sub(b),
+                        Operation::Mul => a.wrapping_mul(b),
+                        Operation::Div => a / b,
+                        Operation::Mod => a % b,
+                        _ => return Err(anyhow!("Unexpected operation {:?}", op.op)),
+                    };
+                    return Ok(Value::int(value, data_type));
+                }
+            }
+            _ => return Err(anyhow!("Operation {:?} is not supported for {}", op.op, data_type)),
+        };
+
+        Ok(Value::Bool(match op.op {
+            Operation::Equal => order.is_eq(),
+            Operation::NotEqual => order.is_ne(),
+            Operation::LessThan => order.is_lt(),
+            Operation::GreaterThan => order.is_gt(),
+            _ => return Err(anyhow!("Operation {:?} is not supported for {}", op.op, data_type)),
+        }))
+    }
+
+    fn eval_neg(&mut self, value: LangType, hint: Option<&DataType>) -> Result<Value> {
+        let value_hint = self.type_of(&value).or_else(|| hint.cloned());
+        match self.eval(value, value_hint.as_ref())? {
+            Value::Float(value, data_type) => Ok(Value::float(-value, data_type)),
+            value if value.data_type().is_signed() => {
+                Ok(Value::int(value.to_i128().unwrap_or_default().wrapping_neg(), value.data_type()))
+            }
+            value => Err(anyhow!(
+                "Cannot negate {}, only signed integers and floats can be negated",
+                value.data_type()
+            )),
+        }
+    }
+
+    /// `Enum.Variant`, the only kind of field access without pointers.
+    fn eval_variant(&mut self, base: LangType, field: LangType) -> Result<Value> {
+        let enum_t = match (&base, &field) {
+            (LangType::Var(var), LangType::Var(_)) if !self.variables.contains_key(&var.name) => {
+                self.enums.get(&var.name).cloned()
+            }
+            _ => None,
+        };
+        let (enum_t, field) = match (enum_t, field) {
+            (Some(enum_t), LangType::Var(field)) => (enum_t, field),
+            _ => return Err(unsupported("struct fields")),
+        };
+
+        let variant = enum_t
+            .variant(&field.name)
+            .ok_or_else(|| anyhow!("Enum {} has no variant {}", enum_t.name, field.name))?;
+        Ok(Value::int(variant.value as i128, enum_t.data_type.clone()))
+    }
+
+}
+
+/// Runs `main` of `ast` on a thread with a large stack, writing printed lines to `out`.
+pub fn run<W: Write + Send>(ast: Vec<LangType>, out: W) -> Result<i64> {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .name("interp".to_string())
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, move || Interpreter::new(ast, out)?.run_main())?
+            .join()
+            .map_err(|_| anyhow!("The interpreter panicked"))?
+    })
+}
+
+fn primitive(p: PrimitiveType, hint: Option<&DataType>) -> Result<Value> {
+    Ok(match p.primitive {
+        Primitives::Int => {
+            let value = p
+                .to_i128()
+                .ok_or_else(|| anyhow!("Integer literal {} does not fit in 64 bits", p.value))?;
+            let data_type = literal_type(&p, hint).unwrap_or(DataType::I64);
+            if !data_type.fits_literal(value, p.radix) {
+                return Err(anyhow!("Integer literal {} does not fit in {}", value, data_type));
+            }
+
+            Value::int(value, data_type)
+        }
+        Primitives::Float => {
+            let data_type = literal_type(&p, hint).unwrap_or(DataType::F64);
+            let value: f64 = p.value.parse().map_err(|_| anyhow!("Invalid float literal {}", p.value))?;
+            let finite = if data_type == DataType::F32 { (value as f32).is_finite() } else { value.is_finite() };
+            if !finite {
+                return Err(anyhow!("Float literal {} does not fit in {}", p.value, data_type));
+            }
+
+            Value::float(value, data_type)
+        }
+        Primitives::Bool => Value::Bool(p.value == "true"),
+        Primitives::String => Value::Str(p.value.into_bytes()),
+    })
+}
+
+/// The conversions of `cast` that don't involve pointers.
+fn cast(value: Value, to: DataType) -> Result<Value> {
+    let from = value.data_type();
+    if !from.can_cast_to(&to) {
+        return Err(anyhow!("Cannot cast {} to {}", from, to));
+    }
+
+    Ok(match value {
+        _ if from == to => value,
+        Value::Int(..) | Value::Bool(_) if to.is_int() => Value::int(value.to_i128().unwrap_or_default(), to),
+        Value::Int(..) if to.is_float() => Value::float(value.to_i128().unwrap_or_default() as f64, to),
+        // out of range is poison in LLVM, here it saturates
+        Value::Float(v, _) if to.is_int() => Value::int(v as i128, to),
+        Value::Float(v, _) if to.is_float() => Value::float(v, to),
+        // the other conversions `can_cast_to` allows involve pointers
+        _ => return Err(unsupported("pointers")),
+    })
+}
+
+/// `value` with its bits reinterpreted as `to`, like LLVM's bitcast.
+fn bitcast(value: Value, to: DataType) -> Result<Value> {
+    let from = value.data_type();
+    let bits = match value {
+        _ if !from.can_bitcast_to(&to) => return Err(anyhow!("Cannot bitcast {} to {}", from, to)),
+        Value::Int(bits, _) => bits,
+        Value::Float(v, DataType::F32) => (v as f32).to_bits() as u64,
+        Value::Float(v, _) => v.to_bits(),
+        _ => return Err(anyhow!("Cannot bitcast {} to {}", from, to)),
+    };
+
+    Ok(match to {
+        DataType::F32 => Value::float(f32::from_bits(bits as u32) as f64, to),
+        DataType::F64 => Value::float(f64::from_bits(bits), to),
+        _ => Value::int(bits as i128, to),
+    })
+}
+
+fn unsupported(what: &str) -> anyhow::Error {
+    anyhow!("The interpreter does not support {}", what)
+}
+
+// ------------------------------------
+// Tests
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use super::run;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::types::primitive_type::format_g;
+
+    fn interpret(input: &str) -> Result<(i64, String)> {
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+        let mut out = vec![];
+        let code = run(ast, &mut out)?;
+
+        return Ok((code, String::from_utf8(out)?));
+    }
+
+    #[test]
+    fn interp_calls() -> Result<()> {
+        let input = r#"fn fib n:i32 -> i32:
+    if n < 2:
+        return n
+    end
+    sub a n 1
+    sub b n 2
+    let fa fib(a)
+    let fb fib(b)
+    add r fa fb
+    return r
+end
+fn main -> i32:
+    let x fib(10)
+    print("fib", x)
+    return 3
+end"#;
+
+        assert_eq!(interpret(input)?, (3, "fib 55\n".to_string()));
+
+        return Ok(());
+    }
+
+    #[test]
+    fn interp_types() -> Result<()> {
+        let input = r#"enum Color u8:
+    Red
+    Green = 200
+end
+fn main:
+    let small:u8
+    let green Color.Green
+    add small green 100
+    let big -1
+    cast wide big as u64
+    div q -7 2
+    let f 1.5
+    mul f f 3.0
+    ftoi n f
+    let s "ab"
+    add s s "c"
+    let l len(s)
+    let k:i64 5
+    match small:
+    44:
+        print(small, wide, q, f, n, s, l, k)
+    else:
+        print("wrapping broke")
+    end
+end"#;
+
+        let (code, out) = interpret(input)?;
+        assert_eq!(code, 0);
+        assert_eq!(out, "44 18446744073709551615 -3 4.5 4 abc 3 5\n");
+
+        return Ok(());
+    }
+
+    #[test]
+    fn interp_bitcast() -> Result<()> {
+        let input = r#"fn main:
+    let one 1.0
+    bitcast bits one as u64
+    let raw:u32 0x3fc00000
+    bitcast half raw as f32
+    bitcast back half as i32
+    print(bits, half, back)
+end"#;
+
+        assert_eq!(interpret(input)?, (0, "4607182418800017408 1.5 1069547520\n".to_string()));
+        assert!(interpret("fn main:\n    let one 1.0\n    bitcast x one as i32\nend").is_err());
+
+        return Ok(());
+    }
+
+    #[test]
+    fn interp_errors() -> Result<()> {
+        assert!(interpret("fn main:\n    let a 1\n    add a a true\nend").is_err());
+        assert!(interpret("fn main:\n    let a 1\n    div a a 0\nend").is_err());
+        assert!(interpret("fn main:\n    let a 1\n    let p addr a\nend").is_err());
+        assert!(interpret("fn main:\n    let a:u8 true\nend").is_err());
+        assert!(interpret("fn deep n:i32:\n    deep(n)\nend\nfn main:\n    deep(1)\nend").is_err());
+
+        return Ok(());
+    }
+
+    #[test]
+    fn interp_format_g() -> Result<()> {
+        let formatted: Vec<String> = [1234.5678, 100000.0, 1e6, 0.0001, 1e-9, -2.5, 0.1 + 0.2]
+            .iter()
+            .map(|v| format_g(*v))
+            .collect();
+        assert_eq!(formatted, vec!["1234.57", "100000", "1e+06", "0.0001", "1e-09", "-2.5", "0.3"]);
+
+        return Ok(());
+    }
+}
@@ -1,15 +1,20 @@
 use std::fmt::Display;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+
+use crate::types::primitive_type::PrimitiveType;
 
 #[allow(dead_code)]
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     Ident(String),
     Int(String),
+    Float(String),
     String(String),
     Bool(bool),
+    Char(char),
     Comment(String),
+    AsmLine(String),
 
     Illegal,
     NewLine,
@@ -17,8 +22,16 @@ pub enum Token {
 
     Bang,
     Colon,
+    Comma,
     Lparen,
     Rparen,
+    Lbracket,
+    Rbracket,
+    Semicolon,
+    Minus,
+    Arrow,
+    Ellipsis,
+    Dot,
 
     Equal,
     NotEqual,
@@ -26,12 +39,18 @@ pub enum Token {
     GreaterThan,
 
     Function,
+    Extern,
+    Export,
+    Struct,
+    Enum,
     Const,
     Let,
 
     If,
     Else,
+    Match,
     Return,
+    Asm,
 
     End,
 }
@@ -41,9 +60,12 @@ impl Display for Token {
         match self {
             Token::Ident(x) => write!(f, "Ident({})", x),
             Token::Int(x) => write!(f, "Int({})", x),
+            Token::Float(x) => write!(f, "Float({})", x),
             Token::String(x) => write!(f, "String({})", x),
             Token::Bool(x) => write!(f, "Bool({})", x),
+            Token::Char(x) => write!(f, "Char({:?})", x),
             Token::Comment(x) => write!(f, "Comment({})", x),
+            Token::AsmLine(x) => write!(f, "AsmLine({})", x),
             Token::Illegal => write!(f, "Illegal"),
             Token::NewLine => write!(f, "NewLine"),
             Token::Eof => write!(f, "Eof"),
@@ -53,25 +75,78 @@ impl Display for Token {
             Token::LessThan => write!(f, "LessThan"),
             Token::GreaterThan => write!(f, "GreaterThan"),
             Token::Colon => write!(f, "Colon"),
+            Token::Comma => write!(f, "Comma"),
             Token::Lparen => write!(f, "Lparen"),
             Token::Rparen => write!(f, "Rparen"),
+            Token::Lbracket => write!(f, "Lbracket"),
+            Token::Rbracket => write!(f, "Rbracket"),
+            Token::Semicolon => write!(f, "Semicolon"),
+            Token::Minus => write!(f, "Minus"),
+            Token::Arrow => write!(f, "Arrow"),
+            Token::Ellipsis => write!(f, "Ellipsis"),
+            Token::Dot => write!(f, "Dot"),
             Token::Function => write!(f, "Function"),
+            Token::Extern => write!(f, "Extern"),
+            Token::Export => write!(f, "Export"),
+            Token::Struct => write!(f, "Struct"),
+            Token::Enum => write!(f, "Enum"),
             Token::Const => write!(f, "Const"),
             Token::Let => write!(f, "Let"),
             Token::If => write!(f, "If"),
             Token::Else => write!(f, "Else"),
+            Token::Match => write!(f, "Match"),
             Token::Return => write!(f, "Return"),
+            Token::Asm => write!(f, "Asm"),
             Token::End => write!(f, "End"),
         }
     }
 }
 
+/// Where a token starts, line and column counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Words the lexer turns into tokens of their own, `pub` is another spelling of `export`.
+pub const KEYWORDS: &[(&str, Token)] = &[
+    ("fn", Token::Function),
+    ("extern", Token::Extern),
+    ("export", Token::Export),
+    ("pub", Token::Export),
+    ("struct", Token::Struct),
+    ("enum", Token::Enum),
+    ("let", Token::Let),
+    ("const", Token::Const),
+    ("if", Token::If),
+    ("else", Token::Else),
+    ("match", Token::Match),
+    ("return", Token::Return),
+    ("asm", Token::Asm),
+    ("end", Token::End),
+];
+
+/// Identifiers the parser reads as keywords where they appear: `cast x y as u8`, `struct S packed align(8)`
+/// and the `asm` header's `intel`, `att`, `out`, `in` and `clobber`.
+pub const CONTEXTUAL_KEYWORDS: &[&str] = &["as", "packed", "align", "intel", "att", "out", "in", "clobber"];
+
+/// Inside an `asm:` block every line up to `end` is kept as raw text.
+#[derive(Debug, PartialEq)]
+enum AsmState {
+    None,
+    Header,
+    Body,
+}
+
 #[derive(Debug)]
 pub struct Lexer {
     position: usize,
     read_position: usize,
     ch: u8,
     input: Vec<u8>,
+    asm_state: AsmState,
+    start: usize, // offset of the token being read
 }
 
 impl Lexer {
@@ -81,6 +156,8 @@ impl Lexer {
             read_position: 0,
             ch: 0,
             input: input.into_bytes(),
+            asm_state: AsmState::None,
+            start: 0,
         };
         lex.read_char();
 
@@ -88,10 +165,18 @@ impl Lexer {
     }
 
     pub fn next_token(&mut self) -> Result<Token> {
+        if self.asm_state == AsmState::Body {
+            if let Some(line) = self.read_asm_line() {
+                return Ok(Token::AsmLine(line));
+            }
+        }
+
         self.skip_whitespace();
+        self.start = self.position;
 
         let tok = match self.ch {
             b':' => Token::Colon,
+            b',' => Token::Comma,
             b'!' => {
                 if self.peek() == b'=' {
                     self.read_char();
@@ -102,16 +187,51 @@ impl Lexer {
             }
             b'(' => Token::Lparen,
             b')' => Token::Rparen,
+            b'[' => Token::Lbracket,
+            b']' => Token::Rbracket,
+            b';' => Token::Semicolon,
+            b'-' => {
+                if self.peek() == b'>' {
+                    self.read_char();
+                    Token::Arrow
+                } else if self.peek().is_ascii_digit() {
+                    // there is no binary minus, so a dash before digits is a negative literal
+                    self.read_char();
+                    return Ok(match self.read_number()? {
+                        Token::Int(literal) => Token::Int(format!("-{}", literal)),
+                        Token::Float(literal) => Token::Float(format!("-{}", literal)),
+                        other => other,
+                    });
+                } else {
+                    Token::Minus
+                }
+            }
+            b'.' => {
+                if self.peek() == b'.' && self.peek_n(1) == b'.' {
+                    self.read_char();
+                    self.read_char();
+                    Token::Ellipsis
+                } else {
+                    Token::Dot
+                }
+            }
             b'>' => Token::GreaterThan,
             b'<' => Token::LessThan,
             b'=' => {
-                self.read_char();
+                // `=` and `==` both compare, a lone `=` also gives enum variants their value
+                if self.peek() == b'=' {
+                    self.read_char();
+                }
                 Token::Equal
             }
             b'"' => {
                 let string_literal = self.read_string()?;
                 Token::String(string_literal)
             }
+            b'r' if self.peek() == b'"' || self.peek() == b'#' => {
+                let string_literal = self.read_raw_string()?;
+                Token::String(string_literal)
+            }
             b'#' => {
                 let comment = self.read_comment()?;
                 Token::Comment(comment)
@@ -119,20 +239,26 @@ impl Lexer {
             b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
                 let ident = self.read_ident();
                 return Ok(match ident.as_str() {
-                    "fn" => Token::Function,
-                    "let" => Token::Let,
-                    "const" => Token::Const,
-                    "if" => Token::If,
                     "false" => Token::Bool(false),
                     "true" => Token::Bool(true),
-                    "return" => Token::Return,
-                    "else" => Token::Else,
-                    "end" => Token::End,
-                    _ => Token::Ident(ident),
+                    name => match KEYWORDS.iter().find(|(keyword, _)| *keyword == name) {
+                        Some((_, Token::Asm)) => {
+                            self.asm_state = AsmState::Header;
+                            Token::Asm
+                        }
+                        Some((_, token)) => token.clone(),
+                        None => Token::Ident(ident.clone()),
+                    },
                 });
             }
-            b'0'..=b'9' => return Ok(Token::Int(self.read_int())),
-            b'\n' => Token::NewLine,
+            b'\'' => Token::Char(self.read_char_literal()?),
+            b'0'..=b'9' => return self.read_number(),
+            b'\n' => {
+                if self.asm_state == AsmState::Header {
+                    self.asm_state = AsmState::Body;
+                }
+                Token::NewLine
+            }
             0 => Token::Eof,
             _ => Token::Illegal,
         };
@@ -142,10 +268,14 @@ impl Lexer {
     }
 
     fn peek(&self) -> u8 {
-        if self.read_position >= self.input.len() {
+        self.peek_n(0)
+    }
+
+    fn peek_n(&self, n: usize) -> u8 {
+        if self.read_position + n >= self.input.len() {
             0
         } else {
-            self.input[self.read_position]
+            self.input[self.read_position + n]
         }
     }
 
@@ -166,44 +296,253 @@ impl Lexer {
         }
     }
 
+    /// Current line and column, where reading stopped after an error.
+    pub fn span(&self) -> Span {
+        let consumed = &self.input[..self.position.min(self.input.len())];
+        let line_start = consumed.iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1);
+        let line = consumed.iter().filter(|&&c| c == b'\n').count() + 1;
+        let column = String::from_utf8_lossy(&consumed[line_start..]).chars().count() + 1;
+
+        Span { line: line as u32, column: column as u32 }
+    }
+
+    /// Error message pointing at the current line and column.
+    fn error(&self, msg: &str) -> anyhow::Error {
+        let span = self.span();
+        anyhow!("{} at line {}, column {}", msg, span.line, span.column)
+    }
+
+    /// Reads a string literal, decoding escapes. Strings may span several lines.
     fn read_string(&mut self) -> Result<String> {
-        let mut string_literal = String::new();
+        let mut bytes = vec![];
         self.read_char();
         while self.ch != b'"' {
+            match self.ch {
+                0 => return Err(self.error("Unclosed string literal")),
+                b'\\' => {
+                    self.read_char();
+                    self.read_escape(&mut bytes)?;
+                }
+                c => bytes.push(c),
+            }
+            self.read_char();
+        }
+
+        String::from_utf8(bytes).map_err(|_| self.error("Invalid UTF-8 in string literal"))
+    }
+
+    /// Decodes the escape after a backslash into `bytes`, leaving `ch` on its last byte.
+    fn read_escape(&mut self, bytes: &mut Vec<u8>) -> Result<()> {
+        match self.ch {
+            b'n' => bytes.push(b'\n'),
+            b't' => bytes.push(b'\t'),
+            b'r' => bytes.push(b'\r'),
+            b'0' => bytes.push(0),
+            b'\\' | b'"' | b'\'' => bytes.push(self.ch),
+            b'x' => {
+                let digits = [self.peek(), self.peek_n(1)];
+                let value = std::str::from_utf8(&digits)
+                    .ok()
+                    .and_then(|d| u8::from_str_radix(d, 16).ok())
+                    .ok_or_else(|| self.error("Expected two hex digits after \\x"))?;
+                if value > 0x7f {
+                    return Err(self.error("\\x escapes only go up to \\x7f, use \\u{..} for other characters"));
+                }
+                self.read_char();
+                self.read_char();
+                bytes.push(value);
+            }
+            b'u' => {
+                if self.peek() != b'{' {
+                    return Err(self.error("Expected { after \\u"));
+                }
+                self.read_char();
+                let mut digits = String::new();
+                while self.peek().is_ascii_hexdigit() && digits.len() < 6 {
+                    self.read_char();
+                    digits.push(self.ch as char);
+                }
+                if self.peek() != b'}' {
+                    return Err(self.error("Expected up to six hex digits and } in \\u{..}"));
+                }
+                self.read_char();
+
+                let c = u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| self.error("Invalid unicode escape"))?;
+                bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            }
+            // a backslash at the end of a line joins it with the next, without the indentation
+            b'\n' | b'\r' => {
+                while matches!(self.peek(), b' ' | b'\t' | b'\n' | b'\r') {
+                    self.read_char();
+                }
+            }
+            0 => return Err(self.error("Unclosed string literal")),
+            c => {
+                let escape = String::from_utf8_lossy(&self.input[self.position..]).chars().next().unwrap_or(c as char);
+                return Err(self.error(&format!("Unknown escape sequence \\{}", escape)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads `r"..."` or `r#"..."#` verbatim; add more `#` to put `"#` inside.
+    fn read_raw_string(&mut self) -> Result<String> {
+        self.read_char();
+        let mut hashes = 0;
+        while self.ch == b'#' {
+            hashes += 1;
+            self.read_char();
+        }
+        if self.ch != b'"' {
+            return Err(self.error("Expected \" to start raw string"));
+        }
+
+        let start = self.position + 1;
+        loop {
+            self.read_char();
             if self.ch == 0 {
-                return Err(anyhow::anyhow!("Unclosed string literal"));
+                return Err(self.error("Unclosed raw string literal"));
             }
-            string_literal.push(self.ch as char);
+            if self.ch == b'"' && (0..hashes).all(|i| self.peek_n(i) == b'#') {
+                let end = self.position;
+                for _ in 0..hashes {
+                    self.read_char();
+                }
+
+                return String::from_utf8(self.input[start..end].to_vec())
+                    .map_err(|_| self.error("Invalid UTF-8 in string literal"));
+            }
+        }
+    }
+
+    /// Reads one line of an asm body verbatim. Returns `None` for blank and
+    /// comment lines and for the closing `end`, which are lexed normally.
+    fn read_asm_line(&mut self) -> Option<String> {
+        while self.ch == b' ' || self.ch == b'\t' {
             self.read_char();
         }
-        Ok(string_literal)
+
+        let pos = self.position;
+        self.start = pos;
+        let mut end = pos;
+        while end < self.input.len() && self.input[end] != b'\n' {
+            end += 1;
+        }
+        let line = String::from_utf8_lossy(&self.input[pos.min(end)..end]).trim_end().to_string();
+
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        if line.split('#').next().map(|l| l.trim_end()) == Some("end") {
+            self.asm_state = AsmState::None;
+            return None;
+        }
+
+        while self.ch != b'\n' && self.ch != 0 {
+            self.read_char();
+        }
+
+        Some(line)
     }
 
     fn read_comment(&mut self) -> Result<String> {
-        let mut comment = String::new();
+        let pos = self.read_position;
         while !(self.peek() == b'\n' || self.peek() == 0) {
             self.read_char();
-            comment.push(self.ch as char);
         }
-        Ok(comment)
+        Ok(String::from_utf8_lossy(&self.input[pos.min(self.read_position)..self.read_position]).to_string())
     }
 
     fn read_ident(&mut self) -> String {
         let pos = self.position;
-        while self.ch.is_ascii_alphabetic() || self.ch == b'_' {
+        while self.ch.is_ascii_alphanumeric() || self.ch == b'_' {
             self.read_char();
         }
 
         return String::from_utf8_lossy(&self.input[pos..self.position]).to_string();
     }
 
-    fn read_int(&mut self) -> String {
+    /// Reads an integer or float literal as written, prefixes, exponents and `_` separators included.
+    fn read_number(&mut self) -> Result<Token> {
         let pos = self.position;
-        while self.ch.is_ascii_digit() {
+        self.read_alphanumeric();
+
+        let prefix = &self.input[pos..self.input.len().min(pos + 2)];
+        let decimal = !matches!(prefix, [b'0', b'x' | b'X' | b'b' | b'B' | b'o' | b'O']);
+        if decimal && self.ch == b'.' && self.peek().is_ascii_digit() {
+            self.read_char();
+            self.read_alphanumeric();
+        }
+        // the exponent sign isn't alphanumeric, `1e-9` continues after it
+        if decimal && matches!(self.input[self.position - 1], b'e' | b'E') && matches!(self.ch, b'+' | b'-') {
             self.read_char();
+            self.read_alphanumeric();
         }
+        let literal = String::from_utf8_lossy(&self.input[pos..self.position]).to_string();
 
-        return String::from_utf8_lossy(&self.input[pos..self.position]).to_string();
+        if decimal && literal.contains(['.', 'e', 'E']) {
+            if literal.ends_with('_') || literal.replace('_', "").parse::<f64>().is_err() {
+                return Err(self.error(&format!("Invalid float literal {}", literal)));
+            }
+            return Ok(Token::Float(literal));
+        }
+
+
+        let int = PrimitiveType::int(&literal);
+        if let Some(c) = int.value.chars().find(|c| !c.is_digit(int.radix)) {
+            return Err(self.error(&format!("Invalid digit {} in integer literal {}", c, literal)));
+        }
+        if int.value.is_empty() {
+            return Err(self.error(&format!("Integer literal {} has no digits", literal)));
+        }
+
+        Ok(Token::Int(literal))
+    }
+
+    fn read_alphanumeric(&mut self) {
+        while self.ch.is_ascii_alphanumeric() || self.ch == b'_' {
+            self.read_char();
+        }
+    }
+
+    /// Reads `'A'` or an escape like `'\n'`, leaving `ch` on the closing quote.
+    fn read_char_literal(&mut self) -> Result<char> {
+        self.read_char();
+        let c = match self.ch {
+            b'\'' => return Err(self.error("Empty character literal")),
+            0 | b'\n' => return Err(self.error("Unclosed character literal")),
+            b'\\' => {
+                self.read_char();
+                let mut bytes = vec![];
+                self.read_escape(&mut bytes)?;
+                String::from_utf8(bytes)
+                    .ok()
+                    .and_then(|s| s.chars().next())
+                    .ok_or_else(|| self.error("Invalid character literal"))?
+            }
+            _ => {
+                let rest = &self.input[self.position..self.input.len().min(self.position + 4)];
+                let c = String::from_utf8_lossy(rest)
+                    .chars()
+                    .next()
+                    .ok_or_else(|| self.error("Invalid character literal"))?;
+                for _ in 1..c.len_utf8() {
+                    self.read_char();
+                }
+                c
+            }
+        };
+
+        self.read_char();
+        if self.ch != b'\'' {
+            return Err(self.error("Expected ' to close character literal"));
+        }
+
+        Ok(c)
     }
 
     pub fn collect(&mut self) -> Result<Vec<Token>> {
@@ -217,13 +556,51 @@ impl Lexer {
         }
         Ok(tokens)
     }
+
+    /// Like `collect`, with the position every token starts at.
+    pub fn collect_spanned(&mut self) -> Result<Vec<(Token, Span)>> {
+        Ok(self.collect_ranges()?.into_iter().map(|(t, start, _)| (t, start)).collect())
+    }
+
+    /// Like `collect_spanned`, with the position after every token as well.
+    pub fn collect_ranges(&mut self) -> Result<Vec<(Token, Span, Span)>> {
+        let mut tokens = Vec::new();
+        let mut span = Span { line: 1, column: 1 };
+        let mut offset = 0;
+        loop {
+            let token = self.next_token()?;
+            if token == Token::Eof {
+                break;
+            }
+
+            let start = advance(&self.input, &mut span, &mut offset, self.start);
+            let end = advance(&self.input, &mut span, &mut offset, self.position);
+            tokens.push((token, start, end));
+        }
+        Ok(tokens)
+    }
+}
+
+/// Moves `span` from `offset` to `to`, which is where it stays when `to` lies before `offset`.
+fn advance(input: &[u8], span: &mut Span, offset: &mut usize, to: usize) -> Span {
+    let to = to.min(input.len());
+    for &c in &input[(*offset).min(to)..to] {
+        if c == b'\n' {
+            *span = Span { line: span.line + 1, column: 1 };
+        } else if c & 0xC0 != 0x80 {
+            // UTF-8 continuation bytes don't start a new column
+            span.column += 1;
+        }
+    }
+    *offset = (*offset).max(to);
+    *span
 }
 
 #[cfg(test)]
 mod test {
     use anyhow::Result;
 
-    use super::{Lexer, Token};
+    use super::{Lexer, Span, Token};
 
     #[test]
     fn get_next_token() -> Result<()> {
@@ -325,4 +702,143 @@ mod test {
 
         return Ok(());
     }
+
+    #[test]
+    fn get_next_strings() -> Result<()> {
+        let input = r###"let a "tab\there\n\"q\" \\ \0 \x41 \u{1F600} grüße"
+let b r"C:\dir\n"
+let c r##"say "#hi"#"##
+let d "one \
+       two" # ünïcode"###;
+
+        let tokens = Lexer::new(input.into()).collect()?;
+        let strings: Vec<&Token> = tokens.iter().filter(|t| matches!(t, Token::String(_))).collect();
+
+        assert_eq!(
+            strings,
+            vec![
+                &Token::String(String::from("tab\there\n\"q\" \\ \0 A \u{1F600} grüße")),
+                &Token::String(String::from("C:\\dir\\n")),
+                &Token::String(String::from("say \"#hi\"#")),
+                &Token::String(String::from("one two")),
+            ]
+        );
+        assert_eq!(tokens.last(), Some(&Token::Comment(String::from(" ünïcode"))));
+
+        let err = Lexer::new("let x 1\nlet s \"bad \\q\"".into()).collect().unwrap_err();
+        assert_eq!(err.to_string(), "Unknown escape sequence \\q at line 2, column 13");
+        assert!(Lexer::new("let s \"\\x80\"".into()).collect().is_err());
+        assert!(Lexer::new("let s \"open".into()).collect().is_err());
+        assert!(Lexer::new("let s r#\"open\"".into()).collect().is_err());
+
+        return Ok(());
+    }
+
+    #[test]
+    fn get_next_numbers() -> Result<()> {
+        let input = r"let a 0xFF 0b1010 0o755 1_000_000 'A' '\n' 'ü' '\'' -5 -0x80 -x 3.14 1e-9 -2.5E+3 0xE5";
+
+        let tokens = Lexer::new(input.into()).collect()?;
+
+        assert_eq!(
+            tokens[2..],
+            vec![
+                Token::Int(String::from("0xFF")),
+                Token::Int(String::from("0b1010")),
+                Token::Int(String::from("0o755")),
+                Token::Int(String::from("1_000_000")),
+                Token::Char('A'),
+                Token::Char('\n'),
+                Token::Char('ü'),
+                Token::Char('\''),
+                Token::Int(String::from("-5")),
+                Token::Int(String::from("-0x80")),
+                Token::Minus,
+                Token::Ident(String::from("x")),
+                Token::Float(String::from("3.14")),
+                Token::Float(String::from("1e-9")),
+                Token::Float(String::from("-2.5E+3")),
+                Token::Int(String::from("0xE5")),
+            ]
+        );
+
+        assert!(Lexer::new("let a 0b102".into()).collect().is_err());
+        assert!(Lexer::new("let a 0x".into()).collect().is_err());
+        assert!(Lexer::new("let a 12ab".into()).collect().is_err());
+        assert!(Lexer::new("let a 'ab'".into()).collect().is_err());
+        assert!(Lexer::new("let a 1e".into()).collect().is_err());
+        assert!(Lexer::new("let a 1.5x".into()).collect().is_err());
+
+        return Ok(());
+    }
+
+    #[test]
+    fn get_next_asm() -> Result<()> {
+        let input = "asm out x \"=r\":\n\tmov $$1, %eax # raw\n\n    # note\n    end\nlet y 2";
+
+        let tokens = Lexer::new(input.into()).collect()?;
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Asm,
+                Token::Ident(String::from("out")),
+                Token::Ident(String::from("x")),
+                Token::String(String::from("=r")),
+                Token::Colon,
+                Token::NewLine,
+                Token::AsmLine(String::from("mov $$1, %eax # raw")),
+                Token::NewLine,
+                Token::NewLine,
+                Token::Comment(String::from(" note")),
+                Token::NewLine,
+                Token::End,
+                Token::NewLine,
+                Token::Let,
+                Token::Ident(String::from("y")),
+                Token::Int(String::from("2")),
+            ]
+        );
+
+        return Ok(());
+    }
+
+    #[test]
+    fn get_spans() -> Result<()> {
+        let input = "fn main:\n    let s \"é\" # c\n    asm:\n        nop\n    end\n";
+
+        let spans: Vec<(Token, u32, u32)> = Lexer::new(input.into())
+            .collect_spanned()?
+            .into_iter()
+            .filter(|(t, _)| *t != Token::NewLine)
+            .map(|(t, Span { line, column })| (t, line, column))
+            .collect();
+
+        assert_eq!(
+            spans,
+            vec![
+                (Token::Function, 1, 1),
+                (Token::Ident(String::from("main")), 1, 4),
+                (Token::Colon, 1, 8),
+                (Token::Let, 2, 5),
+                (Token::Ident(String::from("s")), 2, 9),
+                (Token::String(String::from("é")), 2, 11),
+                (Token::Comment(String::from(" c")), 2, 15),
+                (Token::Asm, 3, 5),
+                (Token::Colon, 3, 8),
+                (Token::AsmLine(String::from("nop")), 4, 9),
+                (Token::End, 5, 5),
+            ]
+        );
+
+        let ends: Vec<(u32, u32)> = Lexer::new(input.into())
+            .collect_ranges()?
+            .into_iter()
+            .filter(|(t, _, _)| matches!(t, Token::String(_) | Token::Comment(_) | Token::AsmLine(_)))
+            .map(|(_, _, end)| (end.line, end.column))
+            .collect();
+        assert_eq!(ends, vec![(2, 14), (2, 18), (4, 12)]);
+
+        return Ok(());
+    }
 }
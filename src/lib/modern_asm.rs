@@ -1,4 +1,21 @@
 pub mod lexer;
 pub mod parser;
 pub mod types;
+pub mod checker;
+#[cfg(feature = "llvm")]
 pub mod codegen;
+#[cfg(feature = "llvm")]
+pub mod runtime;
+#[cfg(feature = "llvm")]
+pub mod optimizer;
+#[cfg(feature = "llvm")]
+pub mod repl;
+#[cfg(feature = "interp")]
+pub mod interp;
+#[cfg(feature = "vm")]
+pub mod vm;
+pub mod header;
+pub mod formatter;
+pub mod analysis;
+pub mod grammar;
+pub mod driver;
@@ -0,0 +1,126 @@
+use anyhow::{anyhow, Result};
+
+use inkwell::module::Module;
+use inkwell::passes::{PassManager, PassManagerBuilder};
+use inkwell::values::FunctionValue;
+use inkwell::OptimizationLevel;
+
+use crate::driver::OptLevel;
+
+/// Runs the passes of `level` over `module`. Debug builds of the compiler verify the IR after
+/// every stage, so a broken pass or codegen bug is reported at the stage that introduced it.
+pub fn optimize(module: &Module, level: OptLevel) -> Result<()> {
+    verify(module, "code generation")?;
+    if level == OptLevel::O0 {
+        return Ok(());
+    }
+
+    let builder = PassManagerBuilder::create();
+    builder.set_optimization_level(llvm_level(level));
+    builder.set_size_level(if level == OptLevel::Os { 1 } else { 0 });
+    if let Some(threshold) = inline_threshold(level) {
+        builder.set_inliner_with_threshold(threshold);
+    }
+
+    // codegen keeps every variable in an alloca, the other passes need them promoted first
+    let functions: PassManager<FunctionValue> = PassManager::create(module);
+    functions.add_promote_memory_to_register_pass();
+    functions.add_instruction_combining_pass();
+    functions.add_reassociate_pass();
+    functions.add_gvn_pass();
+    functions.add_cfg_simplification_pass();
+    builder.populate_function_pass_manager(&functions);
+
+    functions.initialize();
+    for function in module.get_functions() {
+        functions.run_on(&function);
+    }
+    functions.finalize();
+    verify(module, "function passes")?;
+
+    let modules: PassManager<Module> = PassManager::create(());
+    if inline_threshold(level).is_none() {
+        modules.add_always_inliner_pass();
+    }
+    builder.populate_module_pass_manager(&modules);
+    // functions other than main are internal unless exported or extern, so inlined ones can go
+    modules.add_global_dce_pass();
+
+    modules.run_on(module);
+    verify(module, "module passes")
+}
+
+/// LLVM's level for `level`, for the pass builder as well as the `TargetMachine`.
+pub fn llvm_level(level: OptLevel) -> OptimizationLevel {
+    match level {
+        OptLevel::O0 => OptimizationLevel::None,
+        OptLevel::O1 => OptimizationLevel::Less,
+        OptLevel::O2 | OptLevel::Os => OptimizationLevel::Default,
+        OptLevel::O3 => OptimizationLevel::Aggressive,
+    }
+}
+
+/// Clang's thresholds, -O1 only inlines `alwaysinline` functions.
+fn inline_threshold(level: OptLevel) -> Option<u32> {
+    match level {
+        OptLevel::O0 | OptLevel::O1 => None,
+        OptLevel::O2 => Some(225),
+        OptLevel::O3 => Some(275),
+        OptLevel::Os => Some(75),
+    }
+}
+
+fn verify(module: &Module, stage: &str) -> Result<()> {
+    if cfg!(debug_assertions) {
+        module
+            .verify()
+            .map_err(|e| anyhow!("Invalid IR after {}: {}", stage, e.to_string()))?;
+    }
+
+    Ok(())
+}
+
+// ------------------------------------
+// Tests
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use inkwell::context::Context;
+
+    use super::optimize;
+    use crate::codegen::CodeGen;
+    use crate::driver::OptLevel;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn optimizer_drops_inlined_helpers() -> Result<()> {
+        let input = r#"fn helper n:i32 -> i32:
+    add r n 1
+    return r
+end
+export fn shared n:i32 -> i32:
+    add r n 2
+    return r
+end
+fn main -> i32:
+    let a helper(1)
+    let b shared(a)
+    return b
+end"#;
+
+        let context = Context::create();
+        let ast = Parser::new(Lexer::new(input.into()).collect()?).parse_file()?;
+        let module = CodeGen::new(&context).compile_module("test".to_string(), ast)?;
+        optimize(&module, OptLevel::O2)?;
+
+        // exported functions stay for the programs linking the object, even when inlined
+        assert!(module.get_function("helper").is_none());
+        assert!(module.get_function("shared").is_some());
+        assert!(module.get_function("main").is_some());
+
+        return Ok(());
+    }
+}
@@ -1,7 +1,9 @@
+use std::collections::{HashMap, HashSet};
+
 use anyhow::Ok;
 use anyhow::{anyhow, Result};
 
-use crate::lexer::Token;
+use crate::lexer::{Span, Token};
 use crate::types::func_type::FuncType;
 use crate::types::if_type::IfType;
 use crate::types::lang_type::LangType;
@@ -12,6 +14,12 @@ use crate::types::primitive_type::Primitives;
 use crate::types::var_type::VarType;
 use crate::types::const_type::ConstType;
 use crate::types::call_type::CallType;
+use crate::types::data_type::DataType;
+use crate::types::return_type::ReturnType;
+use crate::types::asm_type::{AsmOperand, AsmType};
+use crate::types::struct_type::StructType;
+use crate::types::enum_type::{EnumType, EnumVariant};
+use crate::types::match_type::{MatchArm, MatchType};
 
 pub struct ParserResult {
     pub lang_t: LangType,
@@ -29,15 +37,53 @@ impl ParserResult {
 #[derive(Debug)]
 pub struct Parser {
     organized_tokenlist: Vec<Vec<Token>>, //Token list splitted by new line
+    spans: Vec<Span>,                     //Start of every line, empty without spans
+    line: usize,                          //Line parsed last, where an error stopped parsing
+    types: HashSet<String>,               //Struct and enum names types may refer to
 }
 
 impl Parser {
     pub fn new(tokenlist: Vec<Token>) -> Self {
+        let organized_tokenlist = organize_tokenlist(&tokenlist);
+        Self {
+            types: declared_types(&organized_tokenlist),
+            organized_tokenlist,
+            spans: vec![],
+            line: 0,
+        }
+    }
+
+    /// Parser that puts a `LangType::Location` before every statement in a body.
+    pub fn with_spans(tokenlist: Vec<(Token, Span)>) -> Self {
+        let (tokens, spans): (Vec<Token>, Vec<Span>) = tokenlist.into_iter().unzip();
+
+        // the first token of every line organize_tokenlist keeps
+        let mut line_spans = vec![];
+        let mut line_start = true;
+        for (t, span) in tokens.iter().zip(spans) {
+            if t == &Token::NewLine {
+                line_start = true;
+            } else if line_start {
+                line_spans.append(&mut vec![span]);
+                line_start = false;
+            }
+        }
+
+        let organized_tokenlist = organize_tokenlist(&tokens);
         Self {
-            organized_tokenlist: organize_tokenlist(&tokenlist),
+            types: declared_types(&organized_tokenlist),
+            organized_tokenlist,
+            spans: line_spans,
+            line: 0,
         }
     }
 
+    /// Also accepts the struct and enum `names` as types, for code using definitions parsed earlier.
+    pub fn with_types(mut self, names: impl IntoIterator<Item = String>) -> Self {
+        self.types.extend(names);
+        self
+    }
+
     pub fn parse_file(&mut self) -> Result<Vec<LangType>> {
         let max_pos = self.organized_tokenlist.len();
         let mut ast = vec![];
@@ -57,10 +103,26 @@ impl Parser {
             pos = lang_t.pos + 1;
         }
 
+        resolve_enum_types(&mut ast);
+
         Ok(ast)
     }
 
+    /// Start of the line parsed last, after an error the line it is about. `None` without spans.
+    pub fn error_span(&self) -> Option<Span> {
+        self.spans.get(self.line).copied()
+    }
+
+    /// `lang_t` parsed from line `pos`, preceded by its location when parsing with spans.
+    fn located(&self, pos: usize, lang_t: LangType) -> Vec<LangType> {
+        match self.spans.get(pos) {
+            Some(span) if !matches!(lang_t, LangType::Comment(_)) => vec![LangType::Location(*span), lang_t],
+            _ => vec![lang_t],
+        }
+    }
+
     fn parse_line(&mut self, mut pos: usize) -> Result<ParserResult> {
+        self.line = pos;
         if pos >= self.organized_tokenlist.len() {
             return Err(anyhow!("Expected end of input at position {}", pos));
         }
@@ -93,7 +155,7 @@ impl Parser {
                         ))
                     } else {
                         let var_name;
-                        if let Token::Ident(name) = &self.organized_tokenlist[pos][1] {
+                        if let Some(Token::Ident(name)) = self.organized_tokenlist[pos].get(1) {
                             var_name = name.to_string();
                         } else {
                             return Err(anyhow!("Unexpected Operand at position {}", pos));
@@ -109,28 +171,126 @@ impl Parser {
                         ))
                     }
                 } 
-                else if self.organized_tokenlist[pos].len() > 2 {
-                    if self.organized_tokenlist[pos][1] == Token::Lparen {
-                        let mut param = Vec::new();
-                        let mut win = false;
-                        let mut i = 1;
-                        for p in self.organized_tokenlist[pos].clone() {
-                            if !win && p == Token::Lparen {
-                                win = true;
-                                continue;
-                            }
-                            else if win && p == Token::Rparen {
-                                break;
-                            }
+                else if let Some(op) = OpType::get_unary_op(op_name) {
+                    let var_name;
+                    if let Some(Token::Ident(name)) = self.organized_tokenlist[pos].get(1) {
+                        var_name = name.to_string();
+                    } else {
+                        return Err(anyhow!("Unexpected Operand at position {}", pos));
+                    }
 
-                            if win {
-                                let arg = get_hs(self.organized_tokenlist.to_vec(), pos, i)?;
-                                param.append(&mut vec![arg]);
-                            }
+                    // `neg x` works in place, `neg dest x` stores the result in dest
+                    let dest = LangType::Var(VarType::new(var_name));
+                    let value = if self.organized_tokenlist[pos].len() > 2 {
+                        get_place(&self.organized_tokenlist, pos, 2)?.0
+                    } else {
+                        dest.clone()
+                    };
+
+                    let result = LangType::Op(OpType::new(op, value, LangType::Undefined));
+                    Ok(ParserResult::new(
+                        LangType::Op(OpType::new(Operation::Assign, dest, result)),
+                        pos,
+                    ))
+                }
+                else if op_name == "load" {
+                    let var_name;
+                    if let Some(Token::Ident(name)) = self.organized_tokenlist[pos].get(1) {
+                        var_name = name.to_string();
+                    } else {
+                        return Err(anyhow!("Unexpected Operand at position {}", pos));
+                    }
+                    if self.organized_tokenlist[pos].len() < 3 {
+                        return Err(anyhow!("Missing pointer at position {}", pos));
+                    }
 
-                            i = i + 1;
+                    let dest = LangType::Var(VarType::new(var_name));
+                    let ptr = if is_indexed(&self.organized_tokenlist[pos], 3) {
+                        let (base, next) = get_place(&self.organized_tokenlist, pos, 2)?;
+                        if is_indexed(&self.organized_tokenlist[pos], next) {
+                            let index = get_hs(self.organized_tokenlist.to_vec(), pos, next)?;
+                            LangType::Op(OpType::new(Operation::Index, base, index))
+                        } else {
+                            base
                         }
-                        Ok(ParserResult::new(LangType::Call(CallType::new(op_name.to_string(), param)), pos))
+                    } else {
+                        get_value(&self.organized_tokenlist, pos, 2)?
+                    };
+
+                    let result = LangType::Op(OpType::new(Operation::Load, ptr, LangType::Undefined));
+                    Ok(ParserResult::new(
+                        LangType::Op(OpType::new(Operation::Assign, dest, result)),
+                        pos,
+                    ))
+                }
+                else if op_name == "store" {
+                    if self.organized_tokenlist[pos].len() < 3 {
+                        return Err(anyhow!("Missing operand at position {}", pos));
+                    }
+
+                    let (mut ptr, mut value_index) = get_place(&self.organized_tokenlist, pos, 1)?;
+                    if is_indexed(&self.organized_tokenlist[pos], value_index + 1) {
+                        let index = get_hs(self.organized_tokenlist.to_vec(), pos, value_index)?;
+                        ptr = LangType::Op(OpType::new(Operation::Index, ptr, index));
+                        value_index += 1;
+                    }
+                    let value = get_value(&self.organized_tokenlist, pos, value_index)?;
+
+                    Ok(ParserResult::new(
+                        LangType::Op(OpType::new(Operation::Store, ptr, value)),
+                        pos,
+                    ))
+                }
+                else if op_name == "move" {
+                    let var_name;
+                    if let Some(Token::Ident(name)) = self.organized_tokenlist[pos].get(1) {
+                        var_name = name.to_string();
+                    } else {
+                        return Err(anyhow!("Unexpected Operand at position {}", pos));
+                    }
+                    if self.organized_tokenlist[pos].len() < 3 {
+                        return Err(anyhow!("Missing value at position {}", pos));
+                    }
+
+                    let lhs = LangType::Var(VarType::new(var_name));
+                    let rhs = get_value(&self.organized_tokenlist, pos, 2)?;
+
+                    Ok(ParserResult::new(
+                        LangType::Op(OpType::new(Operation::Assign, lhs, rhs)),
+                        pos,
+                    ))
+                }
+                else if op_name == "cast" || op_name == "bitcast" {
+                    let var_name;
+                    if let Some(Token::Ident(name)) = self.organized_tokenlist[pos].get(1) {
+                        var_name = name.to_string();
+                    } else {
+                        return Err(anyhow!("Unexpected Operand at position {}", pos));
+                    }
+                    if self.organized_tokenlist[pos].len() < 3 {
+                        return Err(anyhow!("Missing value at position {}", pos));
+                    }
+
+                    // cast dest value as type
+                    let line = &self.organized_tokenlist[pos];
+                    let (value, next) = get_place(&self.organized_tokenlist, pos, 2)?;
+                    if line.get(next) != Some(&Token::Ident("as".to_string())) {
+                        return Err(anyhow!("Expected as after {} value at position {}", op_name, pos));
+                    }
+                    let (data_type, _) = get_type(line, next + 1, pos, &self.types)?;
+
+                    let op = if op_name == "cast" { Operation::Cast } else { Operation::Bitcast };
+                    let dest = LangType::Var(VarType::new(var_name));
+                    let result = LangType::Op(OpType::new(op, value, LangType::Type(data_type)));
+                    Ok(ParserResult::new(
+                        LangType::Op(OpType::new(Operation::Assign, dest, result)),
+                        pos,
+                    ))
+                }
+                else if self.organized_tokenlist[pos].len() > 2 {
+                    if self.organized_tokenlist[pos][1] == Token::Lparen {
+                        let call = get_call(&self.organized_tokenlist, pos, 0)?;
+                        Ok(ParserResult::new(LangType::Call(call), pos))
                     }
                     else {
                         Err(anyhow!("Invalid Operation at position {}", pos))
@@ -145,14 +305,41 @@ impl Parser {
             Token::Let => {
                 // make sure var is var
                 let var_name;
-                if let Token::Ident(name) = &self.organized_tokenlist[pos][1] {
+                if let Some(Token::Ident(name)) = self.organized_tokenlist[pos].get(1) {
                     var_name = name.to_string();
                 } else {
                     return Err(anyhow!("Unexpected Variable Name at position {}", pos));
                 }
 
+                // `let buf [u8; 64]` and `let hdr:Header` declare a zeroed variable,
+                // `let x:i64 5` a typed one holding the value
+                let type_start = match self.organized_tokenlist[pos].get(2) {
+                    Some(Token::Lbracket) => Some(2),
+                    Some(Token::Colon) => Some(3),
+                    _ => None,
+                };
+                if let Some(type_start) = type_start {
+                    let line = &self.organized_tokenlist[pos];
+                    let (data_type, next) = get_type(line, type_start, pos, &self.types)?;
+                    let rhs = if next < line.len() {
+                        let (value, end) = get_value_next(&self.organized_tokenlist, pos, next)?;
+                        if end < line.len() {
+                            return Err(anyhow!("Unexpected token after value at position {}", pos));
+                        }
+                        value
+                    } else {
+                        LangType::Undefined
+                    };
+                    let lhs = LangType::Var(VarType::typed(var_name, data_type));
+
+                    return Ok(ParserResult::new(
+                        LangType::Op(OpType::new(Operation::Assign, lhs, rhs)),
+                        pos,
+                    ));
+                }
+
                 let lhs = LangType::Var(VarType::new(var_name));
-                let rhs = get_hs(self.organized_tokenlist.to_vec(), pos, 2)?;
+                let rhs = get_value(&self.organized_tokenlist, pos, 2)?;
 
                 Ok(ParserResult::new(
                     LangType::Op(OpType::new(Operation::Assign, lhs, rhs)),
@@ -163,7 +350,7 @@ impl Parser {
             //Const parser
             Token::Const => {
                 let con_name;
-                if let Token::Ident(name) = &self.organized_tokenlist[pos][1] {
+                if let Some(Token::Ident(name)) = self.organized_tokenlist[pos].get(1) {
                     con_name = name.to_string();
                 } else {
                     return Err(anyhow!("Unexpected Variable Name at position {}", pos));
@@ -178,42 +365,42 @@ impl Parser {
                 }
 
                 Ok(ParserResult::new(
-                    LangType::Const(ConstType::new(con_name,prim.value)),
+                    LangType::Const(ConstType::new(con_name, prim)),
                     pos,
                 ))
             }
 
             //Function parser
-            Token::Function => {
+            Token::Function | Token::Export => {
+                let is_export = matches!(tok, Token::Export);
+                let name_index = if is_export { 2 } else { 1 };
+                if is_export && self.organized_tokenlist[pos].get(1) != Some(&Token::Function) {
+                    return Err(anyhow!("Expected fn after export at position {}", pos));
+                }
+
                 //get name
                 let fn_name;
-                if let Token::Ident(name) = &self.organized_tokenlist[pos][1] {
+                if let Some(Token::Ident(name)) = self.organized_tokenlist[pos].get(name_index) {
                     fn_name = name.to_string();
                 } else {
                     return Err(anyhow!("Unexpected Function Name at position {}", pos));
                 }
 
                 //get params
-                let mut params: Vec<VarType> = vec![];
-                for par in self.organized_tokenlist[pos].clone() {
-                    match par {
-                        Token::Colon => {
-                            break;
-                        }
-
-                        Token::Ident(name) => {
-                            if name == fn_name{ continue; }
-                            params.append(&mut vec![VarType::new(name)])
-                        },
-
-                        _ => {}
-                    }
+                let (params, ret, variadic) =
+                    parse_fn_header(&self.organized_tokenlist[pos][name_index + 1..], false, pos, &self.types)?;
+                if variadic {
+                    return Err(anyhow!("Only extern functions can be variadic at position {}", pos));
                 }
+                let ret = ret.unwrap_or(DataType::I32);
 
                 //get body
+                let line = self.spans.get(pos).map_or(0, |span| span.line);
                 let mut fn_body: Vec<LangType> = vec![];
                 loop {
-                    let lang_t = self.parse_line(pos + 1)?;
+                    let start = pos + 1;
+                    let lang_t = self.parse_line(start)?;
+                    pos = lang_t.pos;
                     if matches!(lang_t.lang_t, LangType::End) {
                         break;
                     }
@@ -221,12 +408,210 @@ impl Parser {
                         break;
                     }
 
-                    fn_body.append(&mut vec![lang_t.lang_t]);
+                    fn_body.append(&mut self.located(start, lang_t.lang_t));
+                }
+
+                let mut func = FuncType::new(fn_name, params, ret, fn_body);
+                func.is_export = is_export;
+                func.line = line;
+
+                Ok(ParserResult::new(LangType::Func(func), pos))
+            }
+
+            //Extern function parser
+            Token::Extern => {
+                if self.organized_tokenlist[pos].get(1) != Some(&Token::Function) {
+                    return Err(anyhow!("Expected fn after extern at position {}", pos));
+                }
+
+                let fn_name;
+                if let Some(Token::Ident(name)) = self.organized_tokenlist[pos].get(2) {
+                    fn_name = name.to_string();
+                } else {
+                    return Err(anyhow!("Unexpected Function Name at position {}", pos));
+                }
+
+                let (params, ret, variadic) =
+                    parse_fn_header(&self.organized_tokenlist[pos][3..], true, pos, &self.types)?;
+
+                Ok(ParserResult::new(
+                    LangType::Func(FuncType::new_extern(
+                        fn_name,
+                        params,
+                        ret.unwrap_or(DataType::Void),
+                        variadic,
+                    )),
+                    pos,
+                ))
+            }
+
+            //Struct parser
+            Token::Struct => {
+                let struct_name;
+                if let Some(Token::Ident(name)) = self.organized_tokenlist[pos].get(1) {
+                    struct_name = name.to_string();
+                } else {
+                    return Err(anyhow!("Unexpected Struct Name at position {}", pos));
+                }
+
+                //get layout attributes
+                let header = self.organized_tokenlist[pos].clone();
+                let mut packed = false;
+                let mut align = None;
+                let mut i = 2;
+                while i < header.len() {
+                    match &header[i] {
+                        Token::Ident(attr) if attr == "packed" => packed = true,
+                        Token::Ident(attr) if attr == "align" => {
+                            let n = match (header.get(i + 1), header.get(i + 2), header.get(i + 3)) {
+                                (Some(Token::Lparen), Some(Token::Int(n)), Some(Token::Rparen)) => get_int::<u32>(n, pos)?,
+                                _ => return Err(anyhow!("Expected align(N) at position {}", pos)),
+                            };
+                            if !n.is_power_of_two() {
+                                return Err(anyhow!("Alignment must be a power of two at position {}", pos));
+                            }
+                            align = Some(n);
+                            i += 4;
+                            continue;
+                        }
+                        Token::Colon => break,
+                        Token::Comment(_) => {}
+                        _ => return Err(anyhow!("Unexpected token in struct header at position {}", pos)),
+                    }
+                    i += 1;
+                }
+
+                //get fields
+                let mut fields: Vec<VarType> = vec![];
+                loop {
+                    pos += 1;
+                    let line = match self.organized_tokenlist.get(pos) {
+                        Some(line) => line,
+                        None => return Err(anyhow!("Unclosed struct at position {}", pos)),
+                    };
+                    match (line.first(), line.get(1)) {
+                        (Some(Token::End), _) => break,
+                        (Some(Token::Comment(_)), _) => {}
+                        (Some(Token::Ident(name)), Some(Token::Colon)) => {
+                            if fields.iter().any(|f| &f.name == name) {
+                                return Err(anyhow!("Duplicate field {} at position {}", name, pos));
+                            }
+                            let (data_type, _) = get_type(line, 2, pos, &self.types)?;
+                            fields.append(&mut vec![VarType::typed(name.to_string(), data_type)]);
+                        }
+                        _ => return Err(anyhow!("Expected field: type at position {}", pos)),
+                    }
+                }
+
+                Ok(ParserResult::new(
+                    LangType::Struct(StructType::new(struct_name, fields, packed, align)),
+                    pos,
+                ))
+            }
+
+            //Enum parser
+            Token::Enum => {
+                let enum_name;
+                if let Some(Token::Ident(name)) = self.organized_tokenlist[pos].get(1) {
+                    enum_name = name.to_string();
+                } else {
+                    return Err(anyhow!("Unexpected Enum Name at position {}", pos));
+                }
+
+                //underlying type, defaults to i32 like C
+                let data_type = match self.organized_tokenlist[pos].get(2) {
+                    Some(Token::Colon) | None => DataType::I32,
+                    Some(_) => get_type(&self.organized_tokenlist[pos], 2, pos, &self.types)?.0,
+                };
+                if !data_type.is_int() {
+                    return Err(anyhow!("Enum {} must be an integer type at position {}", enum_name, pos));
+                }
+
+                //get variants
+                let mut variants: Vec<EnumVariant> = vec![];
+                loop {
+                    pos += 1;
+                    let line = match self.organized_tokenlist.get(pos) {
+                        Some(line) => line,
+                        None => return Err(anyhow!("Unclosed enum at position {}", pos)),
+                    };
+                    let name = match line.first() {
+                        Some(Token::End) => break,
+                        Some(Token::Comment(_)) => continue,
+                        Some(Token::Ident(name)) => name.to_string(),
+                        _ => return Err(anyhow!("Expected enum variant at position {}", pos)),
+                    };
+                    if variants.iter().any(|v| v.name == name) {
+                        return Err(anyhow!("Duplicate variant {} at position {}", name, pos));
+                    }
+
+                    let value = match (line.get(1), line.get(2)) {
+                        (Some(Token::Equal), Some(Token::Int(value))) => get_int::<i64>(value, pos)?,
+                        (None | Some(Token::Comment(_)), _) => match variants.last() {
+                            Some(prev) => prev
+                                .value
+                                .checked_add(1)
+                                .ok_or_else(|| anyhow!("Enum value overflow at position {}", pos))?,
+                            None => 0,
+                        },
+                        _ => return Err(anyhow!("Expected = value at position {}", pos)),
+                    };
+                    variants.append(&mut vec![EnumVariant::new(name, value)]);
+                }
+
+                Ok(ParserResult::new(
+                    LangType::Enum(EnumType::new(enum_name, data_type, variants)),
+                    pos,
+                ))
+            }
+
+            //Match parser
+            Token::Match => {
+                let (value, _) = get_place(&self.organized_tokenlist, pos, 1)?;
+
+                //arms run until the next arm, else or end
+                let mut arms: Vec<MatchArm> = vec![];
+                let mut default: Option<Vec<LangType>> = None;
+                loop {
+                    pos += 1;
+                    let line = match self.organized_tokenlist.get(pos) {
+                        Some(line) => line.clone(),
+                        None => return Err(anyhow!("Unclosed match at position {}", pos)),
+                    };
+                    match line.first() {
+                        Some(Token::End) => break,
+                        Some(Token::Comment(_)) => continue,
+                        Some(Token::Else) => {
+                            if default.is_some() {
+                                return Err(anyhow!("Duplicate else arm at position {}", pos));
+                            }
+                            default = Some(vec![]);
+                            continue;
+                        }
+                        _ if is_match_arm(&line) => {
+                            if default.is_some() {
+                                return Err(anyhow!("Match arm after else at position {}", pos));
+                            }
+                            let patterns = get_patterns(&self.organized_tokenlist, pos)?;
+                            arms.append(&mut vec![MatchArm::new(patterns, vec![])]);
+                            continue;
+                        }
+                        _ => {}
+                    }
+
+                    let start = pos;
+                    let lang_t = self.parse_line(start)?;
                     pos = lang_t.pos;
+                    let body = match (&mut default, arms.last_mut()) {
+                        (Some(default), _) => default,
+                        (None, Some(arm)) => &mut arm.body,
+                        (None, None) => return Err(anyhow!("Expected match arm at position {}", pos)),
+                    };
+                    body.append(&mut self.located(start, lang_t.lang_t));
                 }
 
                 Ok(ParserResult::new(
-                    LangType::Func(FuncType::new(fn_name,params, fn_body)),
+                    LangType::Match(MatchType::new(value, arms, default)),
                     pos,
                 ))
             }
@@ -234,19 +619,19 @@ impl Parser {
             //If/else parser
             Token::If => {
                 //lhs & rhs for the condition
-                let lhs = get_hs(self.organized_tokenlist.to_vec(), pos, 1)?;
+                let (lhs, op_index) = get_place(&self.organized_tokenlist, pos, 1)?;
 
-                let rhs = get_hs(self.organized_tokenlist.to_vec(), pos, 3)?;
+                let (rhs, _) = get_place(&self.organized_tokenlist, pos, op_index + 1)?;
 
                 //op for the condition
-                let condition = match &self.organized_tokenlist[pos][2] {
-                    Token::Equal => LangType::Op(OpType::new(Operation::Equal, lhs, rhs)),
+                let condition = match self.organized_tokenlist[pos].get(op_index) {
+                    Some(Token::Equal) => LangType::Op(OpType::new(Operation::Equal, lhs, rhs)),
 
-                    Token::NotEqual => LangType::Op(OpType::new(Operation::NotEqual, lhs, rhs)),
+                    Some(Token::NotEqual) => LangType::Op(OpType::new(Operation::NotEqual, lhs, rhs)),
 
-                    Token::LessThan => LangType::Op(OpType::new(Operation::LessThan, lhs, rhs)),
+                    Some(Token::LessThan) => LangType::Op(OpType::new(Operation::LessThan, lhs, rhs)),
 
-                    Token::GreaterThan => {
+                    Some(Token::GreaterThan) => {
                         LangType::Op(OpType::new(Operation::GreaterThan, lhs, rhs))
                     }
 
@@ -256,44 +641,135 @@ impl Parser {
                 //get if/else bodys
 
                 let mut if_body: Vec<LangType> = vec![];
+                let mut has_else = false;
                 loop {
-                    let lang_t = self.parse_line(pos + 1)?;
+                    let start = pos + 1;
+                    let lang_t = self.parse_line(start)?;
+                    pos = lang_t.pos;
                     if matches!(lang_t.lang_t, LangType::End) {
                         break;
                     }
                     if matches!(lang_t.lang_t, LangType::Else) {
+                        has_else = true;
                         break;
                     }
                     if matches!(lang_t.lang_t, LangType::Eof) {
                         break;
                     }
 
-                    if_body.append(&mut vec![lang_t.lang_t]);
-                    pos = lang_t.pos;
+                    if_body.append(&mut self.located(start, lang_t.lang_t));
                 }
 
                 let mut else_body: Vec<LangType> = vec![];
-                loop {
-                    let lang_t = self.parse_line(pos + 1)?;
-                    if matches!(lang_t.lang_t, LangType::End) {
-                        break;
+                if has_else {
+                    loop {
+                        let start = pos + 1;
+                        let lang_t = self.parse_line(start)?;
+                        pos = lang_t.pos;
+                        if matches!(lang_t.lang_t, LangType::End) {
+                            break;
+                        }
+                        if matches!(lang_t.lang_t, LangType::Eof) {
+                            break;
+                        }
+
+                        else_body.append(&mut self.located(start, lang_t.lang_t));
                     }
-                    if matches!(lang_t.lang_t, LangType::Eof) {
-                        break;
+                }
+
+                Ok(ParserResult::new(
+                    LangType::If(IfType::new(condition, if_body, else_body)),
+                    pos,
+                ))
+            }
+            //Return parser
+            Token::Return => {
+                let value = if self.organized_tokenlist[pos].len() > 1 {
+                    get_value(&self.organized_tokenlist, pos, 1)?
+                } else {
+                    LangType::Undefined
+                };
+
+                Ok(ParserResult::new(
+                    LangType::Return(ReturnType::new(value)),
+                    pos,
+                ))
+            }
+
+            //Inline asm parser
+            Token::Asm => {
+                let header = self.organized_tokenlist[pos].clone();
+                let mut outputs = vec![];
+                let mut inputs = vec![];
+                let mut clobbers = vec![];
+                let mut intel = false;
+
+                let mut i = 1;
+                while i < header.len() {
+                    match &header[i] {
+                        Token::Ident(kind) if kind == "intel" => intel = true,
+                        Token::Ident(kind) if kind == "att" => intel = false,
+                        Token::Ident(kind) if kind == "out" || kind == "in" => {
+                            if i + 2 >= header.len() {
+                                return Err(anyhow!("Incomplete asm operand at position {}", pos));
+                            }
+                            let value = get_hs(self.organized_tokenlist.to_vec(), pos, i + 1)?;
+                            let constraint = match &header[i + 2] {
+                                Token::String(c) => c.to_string(),
+                                _ => return Err(anyhow!("Expected asm constraint at position {}", pos)),
+                            };
+
+                            if kind == "out" {
+                                if !matches!(value, LangType::Var(_)) {
+                                    return Err(anyhow!("Asm output must be a variable at position {}", pos));
+                                }
+                                outputs.append(&mut vec![AsmOperand::new(constraint, value)]);
+                            } else {
+                                inputs.append(&mut vec![AsmOperand::new(constraint, value)]);
+                            }
+                            i += 3;
+                            continue;
+                        }
+                        Token::Ident(kind) if kind == "clobber" => {
+                            if let Some(Token::String(reg)) = header.get(i + 1) {
+                                clobbers.append(&mut vec![reg.to_string()]);
+                            } else {
+                                return Err(anyhow!("Expected clobbered register at position {}", pos));
+                            }
+                            i += 2;
+                            continue;
+                        }
+                        Token::Colon => break,
+                        Token::Comment(_) => {}
+                        _ => return Err(anyhow!("Unexpected token in asm header at position {}", pos)),
                     }
+                    i += 1;
+                }
 
-                    else_body.append(&mut vec![lang_t.lang_t]);
-                    pos = lang_t.pos;
+                //get raw body lines
+                let mut lines = vec![];
+                loop {
+                    pos += 1;
+                    match self.organized_tokenlist.get(pos).and_then(|l| l.first()) {
+                        Some(Token::AsmLine(line)) => lines.append(&mut vec![line.to_string()]),
+                        Some(Token::Comment(_)) => {}
+                        Some(Token::End) => break,
+                        _ => return Err(anyhow!("Unclosed asm block at position {}", pos)),
+                    }
                 }
 
                 Ok(ParserResult::new(
-                    LangType::If(IfType::new(condition, if_body, else_body)),
+                    LangType::Asm(AsmType::new(lines, outputs, inputs, clobbers, intel)),
                     pos,
                 ))
             }
+
             //Comment
             Token::Comment(c) => Ok(ParserResult::new(LangType::Comment(c.to_string()), pos)),
 
+            //Else
+            Token::Else => Ok(ParserResult::new(LangType::Else, pos)),
+
             //End
             Token::End => Ok(ParserResult::new(LangType::End, pos)),
 
@@ -325,15 +801,369 @@ pub fn organize_tokenlist(tokenlist: &[Token]) -> Vec<Vec<Token>> {
         }
     }
 
+    if !level.is_empty() {
+        if organized_list[0] == vec![] {
+            organized_list[0] = level;
+        } else {
+            organized_list.append(&mut vec![level]);
+        }
+    }
+
     organized_list
 }
 
+/// Whether `line` starts a `fn`, `if`, `match`, `struct`, `enum` or `asm` block, which `end` closes.
+pub fn opens_block(line: &[Token]) -> bool {
+    match line.first() {
+        Some(Token::Function | Token::If | Token::Match | Token::Struct | Token::Enum | Token::Asm) => true,
+        Some(Token::Export) => line.get(1) == Some(&Token::Function),
+        _ => false,
+    }
+}
+
+/// Parses everything after the function name: `x y:i64 ... -> i32:`.
+/// Untyped identifiers are parameter names, except in extern declarations
+/// where a bare identifier is the parameter type.
+fn parse_fn_header(
+    tokens: &[Token],
+    is_extern: bool,
+    pos: usize,
+    types: &HashSet<String>,
+) -> Result<(Vec<VarType>, Option<DataType>, bool)> {
+    let mut params: Vec<VarType> = vec![];
+    let mut ret = None;
+    let mut variadic = false;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        match &tokens[i] {
+            Token::Ident(name) => {
+                if let (Some(Token::Colon), Some(Token::Ident(_) | Token::Lbracket)) = (tokens.get(i + 1), tokens.get(i + 2)) {
+                    let (data_type, next) = get_type(tokens, i + 2, pos, types)?;
+                    params.append(&mut vec![VarType::typed(name.to_string(), data_type)]);
+                    i = next;
+                    continue;
+                }
+
+                if is_extern {
+                    let (data_type, next) = get_type(tokens, i, pos, types)?;
+                    params.append(&mut vec![VarType::typed("".to_string(), data_type)]);
+                    i = next;
+                    continue;
+                }
+
+                params.append(&mut vec![VarType::new(name.to_string())]);
+            }
+
+            Token::Lbracket if is_extern => {
+                let (data_type, next) = get_type(tokens, i, pos, types)?;
+                params.append(&mut vec![VarType::typed("".to_string(), data_type)]);
+                i = next;
+                continue;
+            }
+
+            Token::Ellipsis => {
+                if variadic || tokens.get(i + 1).is_some_and(|t| t != &Token::Arrow && t != &Token::Colon) {
+                    return Err(anyhow!("Variadic marker must be the last parameter at position {}", pos));
+                }
+                variadic = true;
+            }
+
+            Token::Arrow => {
+                let (data_type, next) = get_type(tokens, i + 1, pos, types)?;
+                ret = Some(data_type);
+                i = next;
+                continue;
+            }
+
+            Token::Colon => break,
+
+            Token::Comment(_) => {}
+
+            _ => return Err(anyhow!("Unexpected token in function header at position {}", pos)),
+        }
+
+        i += 1;
+    }
+
+    Ok((params, ret, variadic))
+}
+
+/// Parses a type such as `i32`, `[u8; 64]` or `ptr<ptr<u8>>` starting at token `start`,
+/// returning it together with the index of the next token.
+fn get_type(tokens: &[Token], start: usize, pos: usize, types: &HashSet<String>) -> Result<(DataType, usize)> {
+    if tokens.get(start) == Some(&Token::Lbracket) {
+        let (elem, next) = get_type(tokens, start + 1, pos, types)?;
+        let len = match (tokens.get(next), tokens.get(next + 1), tokens.get(next + 2)) {
+            (Some(Token::Semicolon), Some(Token::Int(len)), Some(Token::Rbracket)) => get_int::<u32>(len, pos)?,
+            _ => return Err(anyhow!("Expected [type; length] at position {}", pos)),
+        };
+        return Ok((DataType::array(elem, len), next + 3));
+    }
+
+    let name = match tokens.get(start) {
+        Some(Token::Ident(name)) => name,
+        _ => return Err(anyhow!("Expected type at position {}", pos)),
+    };
+
+    if name == "ptr" && tokens.get(start + 1) == Some(&Token::LessThan) {
+        let (pointee, next) = get_type(tokens, start + 2, pos, types)?;
+        if tokens.get(next) != Some(&Token::GreaterThan) {
+            return Err(anyhow!("Expected > after pointer type at position {}", pos));
+        }
+        return Ok((DataType::ptr(pointee), next + 1));
+    }
+
+    // struct and enum names are resolved by codegen and `resolve_enum_types`
+    let data_type = match DataType::from_name(name) {
+        Some(data_type) => data_type,
+        None if types.contains(name) => DataType::Struct(name.to_string()),
+        None => return Err(anyhow!("Unknown type {} at position {}", name, pos)),
+    };
+    Ok((data_type, start + 1))
+}
+
+/// Names of the structs and enums declared anywhere in the file, which may be used before their declaration.
+fn declared_types(organized_tokenlist: &[Vec<Token>]) -> HashSet<String> {
+    organized_tokenlist
+        .iter()
+        .filter_map(|line| match (line.first(), line.get(1)) {
+            (Some(Token::Struct | Token::Enum), Some(Token::Ident(name))) => Some(name.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// `load`/`store` take an extra index operand when the line continues past
+/// `last` with something other than a call's argument list.
+fn is_indexed(line: &[Token], last: usize) -> bool {
+    line.len() > last && line.get(last) != Some(&Token::Lparen)
+}
+
+/// Parses an operand followed by any number of `.field` accesses such as `hdr.ip.len`,
+/// returning it together with the index of the next token. A leading `-` negates it.
+fn get_place(organized_tokenlist: &[Vec<Token>], x_pos: usize, start: usize) -> Result<(LangType, usize)> {
+    if organized_tokenlist[x_pos].get(start) == Some(&Token::Minus) {
+        if start + 1 >= organized_tokenlist[x_pos].len() {
+            return Err(anyhow!("Expected operand after - at position {}", x_pos));
+        }
+        let (value, next) = get_place(organized_tokenlist, x_pos, start + 1)?;
+        return Ok((LangType::Op(OpType::new(Operation::Neg, value, LangType::Undefined)), next));
+    }
+
+    let mut place = get_hs(organized_tokenlist.to_vec(), x_pos, start)?;
+    let mut i = start + 1;
+    while organized_tokenlist[x_pos].get(i) == Some(&Token::Dot) {
+        let field = match organized_tokenlist[x_pos].get(i + 1) {
+            Some(Token::Ident(field)) => LangType::Var(VarType::new(field.to_string())),
+            _ => return Err(anyhow!("Expected field name at position {}", x_pos)),
+        };
+        place = LangType::Op(OpType::new(Operation::Field, place, field));
+        i += 2;
+    }
+
+    Ok((place, i))
+}
+
+/// A line like `Color.Red, Color.Green:` or `3:` starts a match arm.
+pub fn is_match_arm(line: &[Token]) -> bool {
+    matches!(line.first(), Some(Token::Int(_) | Token::Char(_) | Token::Ident(_) | Token::Bool(_)))
+        && line.iter().rev().find(|t| !matches!(t, Token::Comment(_))) == Some(&Token::Colon)
+}
+
+/// Comma separated patterns of a match arm.
+fn get_patterns(organized_tokenlist: &[Vec<Token>], x_pos: usize) -> Result<Vec<LangType>> {
+    let mut patterns = vec![];
+    let mut i = 0;
+    loop {
+        let (pattern, next) = get_place(organized_tokenlist, x_pos, i)?;
+        patterns.append(&mut vec![pattern]);
+        match organized_tokenlist[x_pos].get(next) {
+            Some(Token::Comma) => i = next + 1,
+            Some(Token::Colon) => break,
+            _ => return Err(anyhow!("Expected , or : after pattern at position {}", x_pos)),
+        }
+    }
+
+    Ok(patterns)
+}
+
+/// Enum names used as types are replaced by the enum's integer type.
+fn resolve_enum_types(ast: &mut [LangType]) {
+    let enums: HashMap<String, DataType> = ast
+        .iter()
+        .filter_map(|lang_t| match lang_t {
+            LangType::Enum(enum_t) => Some((enum_t.name.clone(), enum_t.data_type.clone())),
+            _ => None,
+        })
+        .collect();
+    if enums.is_empty() {
+        return;
+    }
+
+    for lang_t in ast {
+        resolve_lang_type(lang_t, &enums);
+    }
+}
+
+fn resolve_lang_type(lang_t: &mut LangType, enums: &HashMap<String, DataType>) {
+    match lang_t {
+        LangType::Func(func) => {
+            for param in &mut func.param {
+                if let Some(data_type) = &mut param.data_type {
+                    resolve_data_type(data_type, enums);
+                }
+            }
+            resolve_data_type(&mut func.ret, enums);
+            for stmt in &mut func.body {
+                resolve_lang_type(stmt, enums);
+            }
+        }
+        LangType::Struct(struct_t) => {
+            for field in &mut struct_t.fields {
+                if let Some(data_type) = &mut field.data_type {
+                    resolve_data_type(data_type, enums);
+                }
+            }
+        }
+        // typed declarations and casts are the only statements carrying a type
+        LangType::Op(op) => {
+            resolve_lang_type(&mut op.lhs, enums);
+            resolve_lang_type(&mut op.rhs, enums);
+        }
+        LangType::Type(data_type) => resolve_data_type(data_type, enums),
+        LangType::Var(var) => {
+            if let Some(data_type) = &mut var.data_type {
+                resolve_data_type(data_type, enums);
+            }
+        }
+        LangType::If(if_t) => {
+            for stmt in if_t.body.iter_mut().chain(if_t.else_body.iter_mut()) {
+                resolve_lang_type(stmt, enums);
+            }
+        }
+        LangType::Match(match_t) => {
+            for arm in &mut match_t.arms {
+                for stmt in &mut arm.body {
+                    resolve_lang_type(stmt, enums);
+                }
+            }
+            for stmt in match_t.default.iter_mut().flatten() {
+                resolve_lang_type(stmt, enums);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn resolve_data_type(data_type: &mut DataType, enums: &HashMap<String, DataType>) {
+    match data_type {
+        DataType::Struct(name) => {
+            if let Some(int_type) = enums.get(name.as_str()).cloned() {
+                *data_type = int_type;
+            }
+        }
+        DataType::Ptr(inner) | DataType::Array(inner, _) => resolve_data_type(inner, enums),
+        _ => {}
+    }
+}
+
+/// Value of an integer literal token in any radix, checked against the range of `T`.
+fn get_int<T: TryFrom<i128>>(literal: &str, pos: usize) -> Result<T> {
+    PrimitiveType::int(literal)
+        .to_i128()
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or_else(|| anyhow!("Integer {} is out of range at position {}", literal, pos))
+}
+
+/// Parses `name(arg, ...)` starting at token `start`; commas between arguments are optional.
+fn get_call(organized_tokenlist: &[Vec<Token>], x_pos: usize, start: usize) -> Result<CallType> {
+    let line = &organized_tokenlist[x_pos];
+    let name = match line.get(start) {
+        Some(Token::Ident(name)) => name.to_string(),
+        _ => return Err(anyhow!("Expected function name at position {}", x_pos)),
+    };
+    if line.get(start + 1) != Some(&Token::Lparen) {
+        return Err(anyhow!("Expected ( at position {}", x_pos));
+    }
+
+    let mut param = Vec::new();
+    let mut i = start + 2;
+    loop {
+        match line.get(i) {
+            Some(Token::Rparen) => break,
+            Some(Token::Comma) => {}
+            Some(_) => {
+                let (arg, next) = get_place(organized_tokenlist, x_pos, i)?;
+                param.append(&mut vec![arg]);
+                i = next;
+                continue;
+            }
+            None => return Err(anyhow!("Unclosed call at position {}", x_pos)),
+        }
+        i += 1;
+    }
+
+    Ok(CallType::new(name, param))
+}
+
+/// Like `get_hs`, but also accepts a call whose result is the value, `Enum.Variant`, `-x`
+/// and `addr x` or `addr x.field`.
+fn get_value(organized_tokenlist: &[Vec<Token>], x_pos: usize, y_pos: usize) -> Result<LangType> {
+    Ok(get_value_next(organized_tokenlist, x_pos, y_pos)?.0)
+}
+
+/// `get_value` together with the index of the next token.
+fn get_value_next(organized_tokenlist: &[Vec<Token>], x_pos: usize, y_pos: usize) -> Result<(LangType, usize)> {
+    let line = &organized_tokenlist[x_pos];
+    if line.get(y_pos + 1) == Some(&Token::Lparen) {
+        let call = get_call(organized_tokenlist, x_pos, y_pos)?;
+        // calls can't nest, so the first ) closes it
+        let end = line[y_pos..].iter().position(|t| *t == Token::Rparen).map_or(line.len(), |i| y_pos + i + 1);
+        return Ok((LangType::Call(call), end));
+    }
+    if line.get(y_pos + 1) == Some(&Token::Dot) || line.get(y_pos) == Some(&Token::Minus) {
+        return get_place(organized_tokenlist, x_pos, y_pos);
+    }
+    if line.get(y_pos) == Some(&Token::Ident("addr".to_string())) {
+        if let Some(Token::Ident(_)) = line.get(y_pos + 1) {
+            // a field access already is an address
+            let (place, next) = get_place(organized_tokenlist, x_pos, y_pos + 1)?;
+            return Ok((
+                match place {
+                    LangType::Var(var) => {
+                        LangType::Op(OpType::new(Operation::Addr, LangType::Var(var), LangType::Undefined))
+                    }
+                    field => field,
+                },
+                next,
+            ));
+        }
+        return Err(anyhow!("Expected variable after addr at position {}", x_pos));
+    }
+
+    Ok((get_hs(organized_tokenlist.to_vec(), x_pos, y_pos)?, y_pos + 1))
+}
+
 fn get_hs(organized_tokenlist: Vec<Vec<Token>>, x_pos: usize, y_pos: usize) -> Result<LangType> {
-    let hs = match &organized_tokenlist[x_pos][y_pos] {
+    let token = match organized_tokenlist.get(x_pos).and_then(|line| line.get(y_pos)) {
+        Some(token) => token,
+        None => return Err(anyhow!("Missing operand at position {}, {}", x_pos, y_pos)),
+    };
+    let hs = match token {
         Token::Ident(ident) => LangType::Var(VarType::new(ident.to_string())),
 
         Token::Int(value) => {
-            LangType::Primitive(PrimitiveType::new(value.to_string(), Primitives::Int))
+            LangType::Primitive(PrimitiveType::int(value))
+        }
+
+        Token::Float(value) => {
+            LangType::Primitive(PrimitiveType::float(value))
+        }
+
+        // character literals are integers holding the code point
+        Token::Char(value) => {
+            LangType::Primitive(PrimitiveType::new((*value as u32).to_string(), Primitives::Int))
         }
 
         Token::String(value) => {
@@ -359,7 +1189,13 @@ mod test {
 
     use super::Parser;
     use crate::lexer::Lexer;
+    use crate::lexer::Span;
     use crate::lexer::Token;
+    use crate::lexer::{CONTEXTUAL_KEYWORDS, KEYWORDS};
+    use crate::types::call_type::BUILTINS;
+    use crate::types::data_type::{DataType, TYPE_NAMES};
+    use crate::types::lang_type::LangType;
+    use crate::types::op_type::{OpType, Operation, INSTRUCTIONS};
 
     #[test]
     fn parse_string() -> Result<()> {
@@ -408,4 +1244,654 @@ mod test {
 
         return Ok(());
     }
+
+    #[test]
+    fn parse_nested_blocks() -> Result<()> {
+        let input = r#"fn main:
+        let a 5
+        if a != 4:
+            print(a)
+        else:
+            add a 1
+        end
+        print(a)
+    end"#;
+
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        assert_eq!(ast.len(), 1);
+        if let LangType::Func(func) = &ast[0] {
+            assert_eq!(func.body.len(), 3);
+            if let LangType::If(if_t) = &func.body[1] {
+                assert_eq!(if_t.body.len(), 1);
+                assert_eq!(if_t.else_body.len(), 1);
+            } else {
+                panic!("expected if, got {:?}", func.body[1]);
+            }
+        } else {
+            panic!("expected function, got {:?}", ast[0]);
+        }
+
+        return Ok(());
+    }
+
+    #[test]
+    fn parse_extern() -> Result<()> {
+        let input = r#"extern fn printf str ... -> i32
+extern fn exit code:i32
+fn add x:i64 y:i64 -> i64:
+    add x y
+end"#;
+
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        assert_eq!(ast.len(), 3);
+        if let LangType::Func(func) = &ast[0] {
+            assert!(func.is_extern);
+            assert!(func.variadic);
+            assert_eq!(func.ret, DataType::I32);
+            assert_eq!(func.param.len(), 1);
+            assert_eq!(func.param[0].data_type, Some(DataType::Str));
+        } else {
+            panic!("expected function, got {:?}", ast[0]);
+        }
+        if let LangType::Func(func) = &ast[1] {
+            assert_eq!(func.ret, DataType::Void);
+            assert_eq!(func.param[0].name, "code");
+        } else {
+            panic!("expected function, got {:?}", ast[1]);
+        }
+        if let LangType::Func(func) = &ast[2] {
+            assert!(!func.is_extern);
+            assert_eq!(func.ret, DataType::I64);
+            assert_eq!(func.param[1].data_type, Some(DataType::I64));
+            assert_eq!(func.body.len(), 1);
+        } else {
+            panic!("expected function, got {:?}", ast[2]);
+        }
+
+        assert!(Parser::new(Lexer::new("fn f x ...:\nend".into()).collect()?).parse_file().is_err());
+
+        return Ok(());
+    }
+
+    #[test]
+    fn parse_export() -> Result<()> {
+        let input = r#"export fn square x:i64 -> i64:
+    mul x x
+    return x
+end
+pub fn nothing -> void:
+    return
+end"#;
+
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        assert_eq!(ast.len(), 2);
+        if let LangType::Func(func) = &ast[0] {
+            assert!(func.is_export);
+            assert!(matches!(func.body[1], LangType::Return(_)));
+        } else {
+            panic!("expected function, got {:?}", ast[0]);
+        }
+        if let LangType::Func(func) = &ast[1] {
+            assert!(func.is_export);
+            assert_eq!(func.ret, DataType::Void);
+            if let LangType::Return(ret) = &func.body[0] {
+                assert!(matches!(*ret.value, LangType::Undefined));
+            } else {
+                panic!("expected return, got {:?}", func.body[0]);
+            }
+        } else {
+            panic!("expected function, got {:?}", ast[1]);
+        }
+
+        return Ok(());
+    }
+
+    #[test]
+    fn parse_asm() -> Result<()> {
+        let input = r#"fn main:
+    let x 0
+    asm intel out x "=r" in 40 "r" in 2 "r" clobber "cc":
+        mov $0, $1
+        add $0, $2
+    end
+    print(x)
+end"#;
+
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        if let LangType::Func(func) = &ast[0] {
+            assert_eq!(func.body.len(), 3);
+            if let LangType::Asm(asm) = &func.body[1] {
+                assert!(asm.intel);
+                assert_eq!(asm.lines, vec!["mov $0, $1", "add $0, $2"]);
+                assert_eq!(asm.outputs.len(), 1);
+                assert_eq!(asm.inputs.len(), 2);
+                assert_eq!(asm.inputs[0].constraint, "r");
+                assert_eq!(asm.clobbers, vec!["cc"]);
+            } else {
+                panic!("expected asm, got {:?}", func.body[1]);
+            }
+        } else {
+            panic!("expected function, got {:?}", ast[0]);
+        }
+
+        return Ok(());
+    }
+
+    #[test]
+    fn parse_call_values() -> Result<()> {
+        let input = r#"fn main:
+    let n syscall(39)
+    move n syscall(1, 1, "hi", 2)
+    return n
+end"#;
+
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        if let LangType::Func(func) = &ast[0] {
+            assert_eq!(func.body.len(), 3);
+            for stmt in &func.body[0..2] {
+                if let LangType::Op(op) = stmt {
+                    assert!(matches!(*op.rhs, LangType::Call(_)));
+                } else {
+                    panic!("expected assignment, got {:?}", stmt);
+                }
+            }
+            if let LangType::Op(op) = &func.body[1] {
+                if let LangType::Call(call) = &*op.rhs {
+                    assert_eq!(call.name, "syscall");
+                    assert_eq!(call.param.len(), 4);
+                }
+            }
+        } else {
+            panic!("expected function, got {:?}", ast[0]);
+        }
+
+        return Ok(());
+    }
+
+    #[test]
+    fn parse_pointers() -> Result<()> {
+        let input = r#"fn bump p:ptr<ptr<u8>> -> void:
+    let x 1
+    let q addr x
+    load y q
+    store q 5
+    offset q q 2
+end"#;
+
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        if let LangType::Func(func) = &ast[0] {
+            assert_eq!(
+                func.param[0].data_type,
+                Some(DataType::ptr(DataType::ptr(DataType::U8)))
+            );
+            assert_eq!(func.ret, DataType::Void);
+
+            let ops: Vec<&OpType> = func
+                .body
+                .iter()
+                .map(|stmt| match stmt {
+                    LangType::Op(op) => op,
+                    other => panic!("expected op, got {:?}", other),
+                })
+                .collect();
+            assert!(matches!(&*ops[1].rhs, LangType::Op(o) if matches!(o.op, Operation::Addr)));
+            assert!(matches!(&*ops[2].rhs, LangType::Op(o) if matches!(o.op, Operation::Load)));
+            assert!(matches!(ops[3].op, Operation::Store));
+            assert!(matches!(&*ops[4].rhs, LangType::Op(o) if matches!(o.op, Operation::Offset)));
+        } else {
+            panic!("expected function, got {:?}", ast[0]);
+        }
+
+        return Ok(());
+    }
+
+    #[test]
+    fn parse_arrays() -> Result<()> {
+        let input = r#"fn main:
+    let buf [u8; 64]
+    store buf 3 7
+    load x buf 3
+    load y buf
+end
+extern fn fill p:ptr<[i32; 4]>"#;
+
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        let LangType::Func(func) = &ast[0] else { panic!("expected function, got {:?}", ast[0]) };
+        let ops: Vec<&OpType> = func
+            .body
+            .iter()
+            .map(|stmt| match stmt {
+                LangType::Op(op) => op,
+                other => panic!("expected op, got {:?}", other),
+            })
+            .collect();
+        let is_index = |t: &LangType| matches!(t, LangType::Op(o) if matches!(o.op, Operation::Index));
+        assert!(matches!(&*ops[0].lhs, LangType::Var(var) if var.data_type == Some(DataType::array(DataType::U8, 64))));
+        assert!(matches!(*ops[0].rhs, LangType::Undefined));
+        assert!(matches!(ops[1].op, Operation::Store));
+        assert!(is_index(&ops[1].lhs));
+        assert!(matches!(&*ops[2].rhs, LangType::Op(load) if is_index(&load.lhs)));
+        assert!(matches!(&*ops[3].rhs, LangType::Op(load) if matches!(&*load.lhs, LangType::Var(_))));
+
+        let LangType::Func(func) = &ast[1] else { panic!("expected function, got {:?}", ast[1]) };
+        assert_eq!(func.param[0].data_type, Some(DataType::ptr(DataType::array(DataType::I32, 4))));
+
+        return Ok(());
+    }
+
+    #[test]
+    fn parse_structs() -> Result<()> {
+        let input = r#"struct Ipv4 packed align(4):
+    version: u8
+    len: u16 # total length
+    src: [u8; 4]
+end
+fn main:
+    let hdr:Ipv4
+    store hdr.len 20
+    store hdr.src 0 127
+    load n hdr.len
+    let p addr hdr.src
+end"#;
+
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        let LangType::Struct(struct_t) = &ast[0] else { panic!("expected struct, got {:?}", ast[0]) };
+        assert_eq!(struct_t.name, "Ipv4");
+        assert!(struct_t.packed);
+        assert_eq!(struct_t.align, Some(4));
+        assert_eq!(struct_t.fields.len(), 3);
+        assert_eq!(struct_t.fields[2].data_type, Some(DataType::array(DataType::U8, 4)));
+
+        let LangType::Func(func) = &ast[1] else { panic!("expected function, got {:?}", ast[1]) };
+        let ops: Vec<&OpType> = func
+            .body
+            .iter()
+            .map(|stmt| match stmt {
+                LangType::Op(op) => op,
+                other => panic!("expected op, got {:?}", other),
+            })
+            .collect();
+        let is_field = |t: &LangType| matches!(t, LangType::Op(o) if matches!(o.op, Operation::Field));
+        assert!(matches!(&*ops[0].lhs, LangType::Var(var) if var.data_type == Some(DataType::Struct("Ipv4".to_string()))));
+        assert!(matches!(ops[1].op, Operation::Store));
+        assert!(is_field(&ops[1].lhs));
+        assert!(matches!(&*ops[2].lhs, LangType::Op(o) if matches!(o.op, Operation::Index) && is_field(&o.lhs)));
+        assert!(matches!(&*ops[3].rhs, LangType::Op(o) if is_field(&o.lhs)));
+        assert!(is_field(&ops[4].rhs));
+
+        // only declared structs and enums name types, wherever they are declared
+        let err = Parser::new(Lexer::new("fn main:\n    let x:i46 5\nend".into()).collect()?).parse_file();
+        assert_eq!(err.unwrap_err().to_string(), "Unknown type i46 at position 1");
+        assert!(Parser::new(Lexer::new("extern fn f p:ptr<Missing>".into()).collect()?).parse_file().is_err());
+        assert!(Parser::new(Lexer::new("fn f p:Later:\nend\nstruct Later:\n    x: i32\nend".into()).collect()?)
+            .parse_file()
+            .is_ok());
+        let lex = Lexer::new("fn f p:Earlier:\nend".into()).collect()?;
+        assert!(Parser::new(lex).with_types(vec!["Earlier".to_string()]).parse_file().is_ok());
+        assert!(Parser::new(Lexer::new("struct S align(3):\nend".into()).collect()?).parse_file().is_err());
+
+        return Ok(());
+    }
+
+    #[test]
+    fn parse_typed_let() -> Result<()> {
+        let input = r#"fn main:
+    let x:i64 5
+    let p:ptr<i32> addr y
+    let c:u8 len(s)
+end"#;
+
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        let LangType::Func(func) = &ast[0] else { panic!("expected function, got {:?}", ast[0]) };
+        let LangType::Op(op) = &func.body[0] else { panic!("expected op, got {:?}", func.body[0]) };
+        assert!(matches!(&*op.lhs, LangType::Var(var) if var.data_type == Some(DataType::I64)));
+        assert!(matches!(&*op.rhs, LangType::Primitive(p) if p.value == "5"));
+        let LangType::Op(op) = &func.body[1] else { panic!("expected op, got {:?}", func.body[1]) };
+        assert!(matches!(&*op.rhs, LangType::Op(o) if matches!(o.op, Operation::Addr)));
+        let LangType::Op(op) = &func.body[2] else { panic!("expected op, got {:?}", func.body[2]) };
+        assert!(matches!(&*op.rhs, LangType::Call(call) if call.name == "len"));
+
+        for extra in ["let x:i64 5 6", "let c:u8 len(s) 1", "let b [u8; 4] 0 1"] {
+            let lex = Lexer::new(format!("fn main:\n    {}\nend", extra)).collect()?;
+            assert!(Parser::new(lex).parse_file().is_err(), "{}", extra);
+        }
+
+        return Ok(());
+    }
+
+    #[test]
+    fn parse_int_literals() -> Result<()> {
+        let input = r#"fn main:
+    let a 0xFF
+    let b 1_000
+    let c 'A'
+    let d [u8; 0x10]
+end"#;
+
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        if let LangType::Func(func) = &ast[0] {
+            let literals: Vec<(String, u32)> = func.body[0..3]
+                .iter()
+                .map(|stmt| match stmt {
+                    LangType::Op(op) => match &*op.rhs {
+                        LangType::Primitive(p) => (p.value.clone(), p.radix),
+                        other => panic!("expected literal, got {:?}", other),
+                    },
+                    other => panic!("expected op, got {:?}", other),
+                })
+                .collect();
+            assert_eq!(
+                literals,
+                vec![("FF".to_string(), 16), ("1000".to_string(), 10), ("65".to_string(), 10)]
+            );
+            if let LangType::Op(op) = &func.body[3] {
+                if let LangType::Var(var) = &*op.lhs {
+                    assert_eq!(var.data_type, Some(DataType::array(DataType::U8, 16)));
+                }
+            }
+        } else {
+            panic!("expected function, got {:?}", ast[0]);
+        }
+
+        return Ok(());
+    }
+
+    #[test]
+    fn parse_enums() -> Result<()> {
+        let input = r#"enum Proto u8:
+    Icmp = 1
+    Tcp = 6
+    Udp # 7
+end
+fn port p:Proto -> u16:
+    match p:
+    Proto.Tcp, Proto.Udp:
+        if p == Proto.Udp:
+            return 53
+        end
+        return 80
+    else:
+        return 0
+    end
+end"#;
+
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        if let LangType::Enum(enum_t) = &ast[0] {
+            assert_eq!(enum_t.data_type, DataType::U8);
+            let values: Vec<i64> = enum_t.variants.iter().map(|v| v.value).collect();
+            assert_eq!(values, vec![1, 6, 7]);
+        } else {
+            panic!("expected enum, got {:?}", ast[0]);
+        }
+
+        if let LangType::Func(func) = &ast[1] {
+            // enum names used as types become the enum's integer type
+            assert_eq!(func.param[0].data_type, Some(DataType::U8));
+            if let LangType::Match(match_t) = &func.body[0] {
+                assert_eq!(match_t.arms.len(), 1);
+                assert_eq!(match_t.arms[0].patterns.len(), 2);
+                assert_eq!(match_t.arms[0].body.len(), 2);
+                assert_eq!(match_t.default.as_ref().map(|d| d.len()), Some(1));
+            } else {
+                panic!("expected match, got {:?}", func.body[0]);
+            }
+        } else {
+            panic!("expected function, got {:?}", ast[1]);
+        }
+
+        return Ok(());
+    }
+
+    #[test]
+    fn parse_negatives() -> Result<()> {
+        let input = r#"enum Status i8:
+    Err = -1
+    Ok
+end
+fn main:
+    let a -0x10
+    neg a
+    neg b a
+    let c -a
+end"#;
+
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        if let LangType::Enum(enum_t) = &ast[0] {
+            let values: Vec<i64> = enum_t.variants.iter().map(|v| v.value).collect();
+            assert_eq!(values, vec![-1, 0]);
+        } else {
+            panic!("expected enum, got {:?}", ast[0]);
+        }
+
+        if let LangType::Func(func) = &ast[1] {
+            if let LangType::Op(op) = &func.body[0] {
+                if let LangType::Primitive(p) = &*op.rhs {
+                    assert_eq!((p.value.as_str(), p.radix, p.to_i128()), ("-10", 16, Some(-16)));
+                } else {
+                    panic!("expected literal, got {:?}", op.rhs);
+                }
+            }
+            for stmt in &func.body[1..] {
+                match stmt {
+                    LangType::Op(op) => match &*op.rhs {
+                        LangType::Op(neg) => assert!(matches!(neg.op, Operation::Neg)),
+                        other => panic!("expected neg, got {:?}", other),
+                    },
+                    other => panic!("expected op, got {:?}", other),
+                }
+            }
+        } else {
+            panic!("expected function, got {:?}", ast[1]);
+        }
+
+        let err = Parser::new(Lexer::new("fn main:\n    let x -\nend".into()).collect()?).parse_file();
+        assert_eq!(err.unwrap_err().to_string(), "Expected operand after - at position 1");
+
+        return Ok(());
+    }
+
+    #[test]
+    fn parse_casts() -> Result<()> {
+        let input = r#"enum Proto u8:
+    Tcp = 6
+end
+fn main:
+    cast small big as u8
+    cast bytes hdr_ptr as ptr<u8>
+    cast kind n as Proto
+    bitcast bits f as u64
+end"#;
+
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        if let LangType::Func(func) = &ast[1] {
+            let targets: Vec<DataType> = func
+                .body
+                .iter()
+                .map(|stmt| match stmt {
+                    LangType::Op(op) => match &*op.rhs {
+                        LangType::Op(cast) => match &*cast.rhs {
+                            LangType::Type(data_type) => data_type.clone(),
+                            other => panic!("expected type, got {:?}", other),
+                        },
+                        other => panic!("expected cast, got {:?}", other),
+                    },
+                    other => panic!("expected op, got {:?}", other),
+                })
+                .collect();
+            // enum names resolve to their integer type
+            assert_eq!(targets, vec![DataType::U8, DataType::ptr(DataType::U8), DataType::U8, DataType::U64]);
+            let is_bitcast = |t: &LangType| matches!(t, LangType::Op(o) if matches!(o.op, Operation::Bitcast));
+            assert!(matches!(&func.body[3], LangType::Op(op) if is_bitcast(&op.rhs)));
+        } else {
+            panic!("expected function, got {:?}", ast[1]);
+        }
+
+        let lex = Lexer::new("fn main:\n    cast a b u8\nend".into()).collect()?;
+        assert!(Parser::new(lex).parse_file().is_err());
+
+        return Ok(());
+    }
+
+    #[test]
+    fn parse_spans() -> Result<()> {
+        let input = r#"
+# leading comment
+fn main:
+    let a 1
+    if a > 0:
+        # note
+        add a 1
+    else:
+        sub a 1
+    end
+    return a
+end"#;
+
+        let lex = Lexer::new(input.into()).collect_spanned()?;
+        let ast = Parser::with_spans(lex).parse_file()?;
+
+        let lines = |body: &[LangType]| -> Vec<u32> {
+            body.iter()
+                .filter_map(|stmt| match stmt {
+                    LangType::Location(span) => Some(span.line),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        if let LangType::Func(func) = &ast[1] {
+            assert_eq!(func.line, 3);
+            assert_eq!(lines(&func.body), vec![4, 5, 11]);
+            assert!(matches!(func.body[0], LangType::Location(Span { line: 4, column: 5 })));
+            if let LangType::If(if_t) = &func.body[3] {
+                assert_eq!(lines(&if_t.body), vec![7]);
+                assert_eq!(lines(&if_t.else_body), vec![9]);
+            } else {
+                panic!("expected if, got {:?}", func.body[3]);
+            }
+        } else {
+            panic!("expected function, got {:?}", ast[1]);
+        }
+
+        // without spans the bodies only hold statements
+        let ast = Parser::new(Lexer::new(input.into()).collect()?).parse_file()?;
+        if let LangType::Func(func) = &ast[1] {
+            assert_eq!((func.line, lines(&func.body).len()), (0, 0));
+        }
+
+        return Ok(());
+    }
+
+    /// The last statement of `main` with `line` in its body.
+    fn parse_statement(line: &str) -> Result<LangType> {
+        let input = format!("fn main:\n    let a 1\n    {}\nend", line);
+        let lex = Lexer::new(input).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        let Some(LangType::Func(func)) = ast.into_iter().next() else {
+            panic!("expected function for {}", line);
+        };
+        return Ok(func.body.into_iter().last().unwrap_or(LangType::Undefined));
+    }
+
+    /// Words in the string literals of `source` up to its tests.
+    fn literal_words(source: &str) -> Vec<&str> {
+        let code = source.split("// Tests").next().unwrap_or_default();
+        code.split('"')
+            .skip(1)
+            .step_by(2)
+            .filter(|s| !s.is_empty() && s.chars().all(|c| c.is_ascii_lowercase()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_tables() -> Result<()> {
+        let instructions = [
+            ("add", "add a a 1"),
+            ("sub", "sub a a 1"),
+            ("mul", "mul a a 2"),
+            ("div", "div a a 2"),
+            ("mod", "mod a a 2"),
+            ("offset", "offset p p 1"),
+            ("neg", "neg a"),
+            ("itof", "itof f a"),
+            ("ftoi", "ftoi a f"),
+            ("load", "load a p"),
+            ("store", "store p a"),
+            ("move", "move a 2"),
+            ("cast", "cast b a as u8"),
+            ("bitcast", "bitcast b a as u32"),
+            ("addr", "let p addr a"),
+        ];
+        let names: Vec<&str> = instructions.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, INSTRUCTIONS);
+        for (name, line) in instructions {
+            let statement = parse_statement(line)?;
+            assert!(matches!(statement, LangType::Op(_)), "{} parsed as {:?}", name, statement);
+        }
+
+        let contextual = [
+            ("as", "fn main:\n    cast b a as u8\nend"),
+            ("packed", "struct S packed:\n    x: u8\nend"),
+            ("align", "struct S align(8):\n    x: u8\nend"),
+            ("intel", "fn main:\n    asm intel:\n        nop\n    end\nend"),
+            ("att", "fn main:\n    asm att:\n        nop\n    end\nend"),
+            ("out", "fn main:\n    let x 0\n    asm out x \"=r\":\n        nop\n    end\nend"),
+            ("in", "fn main:\n    asm in 1 \"r\":\n        nop\n    end\nend"),
+            ("clobber", "fn main:\n    asm clobber \"cc\":\n        nop\n    end\nend"),
+        ];
+        let names: Vec<&str> = contextual.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, CONTEXTUAL_KEYWORDS);
+        for (name, input) in contextual {
+            let lex = Lexer::new(input.into()).collect()?;
+            assert!(Parser::new(lex).parse_file().is_ok(), "{} is not accepted", name);
+        }
+
+        for name in TYPE_NAMES {
+            assert!(matches!(parse_statement(&format!("let x:{}", name))?, LangType::Op(_)), "{}", name);
+        }
+        for name in BUILTINS {
+            let statement = parse_statement(&format!("{}(a)", name))?;
+            assert!(matches!(statement, LangType::Call(call) if call.name == *name), "{}", name);
+        }
+
+        // every word the parser and the operation lookup match on is in one of the tables
+        let tables: Vec<&str> = [INSTRUCTIONS, CONTEXTUAL_KEYWORDS, TYPE_NAMES, BUILTINS]
+            .concat()
+            .into_iter()
+            .chain(KEYWORDS.iter().map(|(word, _)| *word))
+            .collect();
+        let sources = [include_str!("parser.rs"), include_str!("types/op_type.rs")];
+        for word in sources.into_iter().flat_map(literal_words) {
+            assert!(tables.contains(&word), "{} is missing from the tables", word);
+        }
+
+        return Ok(());
+    }
 }
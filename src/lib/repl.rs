@@ -0,0 +1,288 @@
+use std::io::{BufRead, Write};
+
+use anyhow::{anyhow, Result};
+
+use inkwell::context::Context;
+use inkwell::execution_engine::ExecutionEngine;
+use inkwell::module::Module;
+use inkwell::targets::{InitializationConfig, Target};
+use inkwell::OptimizationLevel;
+
+use crate::codegen::CodeGen;
+use crate::lexer::{Lexer, Token};
+use crate::parser::{opens_block, organize_tokenlist, Parser};
+use crate::types::data_type::DataType;
+use crate::types::lang_type::LangType;
+
+extern "C" {
+    // jitted code prints through C stdio, which buffers when stdout is not a terminal
+    fn fflush(stream: *mut std::ffi::c_void) -> i32;
+}
+
+const HELP: &str = ":tokens <code>  show the tokens of <code>
+:ast <code>     show the syntax tree of <code>
+:ir [<code>]    show the LLVM IR of <code> without running it, or of the last entry
+:history        list the entries so far
+:help           show this help
+:quit           leave the REPL";
+
+/// A compiled entry that has not been added to the JIT yet.
+struct Entry<'ctx> {
+    name: String,
+    module: Module<'ctx>,
+    items: Vec<LangType>,
+    variables: Vec<(String, DataType)>,
+}
+
+/// Evaluates entries in one JIT. Definitions are recompiled into every entry, variables are
+/// globals of the entry that declared them, so later entries keep seeing their values.
+pub struct Repl<'ctx> {
+    context: &'ctx Context,
+    engine: Option<ExecutionEngine<'ctx>>,
+    modules: Vec<Module<'ctx>>,
+    items: Vec<LangType>,
+    variables: Vec<(String, DataType)>,
+    history: Vec<String>,
+    last_ir: Option<String>,
+}
+
+impl<'ctx> Repl<'ctx> {
+    pub fn new(context: &'ctx Context) -> Result<Self> {
+        Target::initialize_native(&InitializationConfig::default()).map_err(|e| anyhow!(e))?;
+
+        Ok(Self {
+            context,
+            engine: None,
+            modules: vec![],
+            items: vec![],
+            variables: vec![],
+            history: vec![],
+            last_ir: None,
+        })
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Evaluates a complete entry or meta-command. Program output goes to stdout through
+    /// printf, everything the REPL prints itself goes to `out`.
+    pub fn eval<W: Write>(&mut self, input: &str, out: &mut W) -> Result<()> {
+        self.history.append(&mut vec![input.to_string()]);
+
+        let (command, code) = match input.trim_start().strip_prefix(':') {
+            Some(meta) => meta.split_once(char::is_whitespace).unwrap_or((meta, "")),
+            None => return self.run(input),
+        };
+
+        match command {
+            "tokens" => {
+                for line in organize_tokenlist(&Lexer::new(code.to_string()).collect()?) {
+                    let tokens: Vec<String> = line.iter().map(|t| t.to_string()).collect();
+                    writeln!(out, "{}", tokens.join(" "))?;
+                }
+            }
+            "ast" => {
+                for lang_t in self.parse(code)? {
+                    writeln!(out, "{:#?}", lang_t)?;
+                }
+            }
+            "ir" if code.trim().is_empty() => {
+                let ir = self.last_ir.as_ref().ok_or_else(|| anyhow!("Nothing was compiled yet"))?;
+                write!(out, "{}", ir)?;
+            }
+            "ir" => write!(out, "{}", self.compile(code)?.module.print_to_string().to_string())?,
+            "history" => {
+                for (i, entry) in self.history.iter().enumerate() {
+                    writeln!(out, "{:>4}  {}", i + 1, entry.replace('\n', "\n      "))?;
+                }
+            }
+            "help" => writeln!(out, "{}", HELP)?,
+            other => return Err(anyhow!("Unknown command :{}, see :help", other)),
+        }
+
+        Ok(())
+    }
+
+    /// Compiles `input`, adds it to the JIT and runs its statements.
+    fn run(&mut self, input: &str) -> Result<()> {
+        let entry = self.compile(&self.echo(input))?;
+
+        match &self.engine {
+            Some(engine) => engine
+                .add_module(&entry.module)
+                .map_err(|_| anyhow!("Cannot add {} to the JIT", entry.name))?,
+            None => {
+                let engine = entry
+                    .module
+                    .create_jit_execution_engine(OptimizationLevel::None)
+                    .map_err(|e| anyhow!(e.to_string()))?;
+                self.engine = Some(engine);
+            }
+        }
+
+        // the module is part of the JIT now, its definitions and variables stay even if it fails
+        self.last_ir = Some(entry.module.print_to_string().to_string());
+        self.items = entry.items;
+        self.variables = entry.variables;
+        self.modules.append(&mut vec![entry.module]);
+
+        let engine = self.engine.as_ref().ok_or_else(|| anyhow!("JIT is not initialised"))?;
+        std::io::stdout().flush()?;
+        // SAFETY: codegen emitted the entry as a C function without parameters or result
+        unsafe {
+            let function = engine.get_function::<unsafe extern "C" fn()>(&entry.name)?;
+            function.call();
+            fflush(std::ptr::null_mut());
+        }
+
+        Ok(())
+    }
+
+    /// Compiles `input` as the next entry on top of the definitions and variables so far.
+    fn compile(&self, input: &str) -> Result<Entry<'ctx>> {
+        let mut items = self.items.clone();
+        let mut body = vec![];
+
+        for lang_t in self.parse(input)? {
+            match item_name(&lang_t) {
+                // a new definition replaces the old one of the same name
+                Some(name) => {
+                    items.retain(|item| item_name(item) != Some(name));
+                    items.append(&mut vec![lang_t]);
+                }
+                None if matches!(lang_t, LangType::Comment(_)) => {}
+                None => body.append(&mut vec![lang_t]),
+            }
+        }
+
+        let name = format!("repl.entry{}", self.modules.len());
+        let mut variables = self.variables.clone();
+        let module = CodeGen::new(self.context).compile_repl_entry(&name, items.clone(), body, &mut variables)?;
+
+        Ok(Entry {
+            name,
+            module,
+            items,
+            variables,
+        })
+    }
+
+    /// Parses `input`, which may use the structs and enums of earlier entries as types.
+    fn parse(&self, input: &str) -> Result<Vec<LangType>> {
+        let types = self.items.iter().filter_map(|item| match item {
+            LangType::Struct(struct_t) => Some(struct_t.name.clone()),
+            LangType::Enum(enum_t) => Some(enum_t.name.clone()),
+            _ => None,
+        });
+        Parser::new(Lexer::new(input.to_string()).collect()?).with_types(types).parse_file()
+    }
+
+    /// A line that is just a variable or constant prints it.
+    fn echo(&self, input: &str) -> String {
+        let tokens = Lexer::new(input.to_string()).collect().unwrap_or_default();
+        let tokens: Vec<&Token> = tokens.iter().filter(|t| !matches!(t, Token::NewLine | Token::Eof)).collect();
+
+        match tokens[..] {
+            [Token::Ident(name)] if self.is_value(name) => format!("print({})", name),
+            _ => input.to_string(),
+        }
+    }
+
+    fn is_value(&self, name: &str) -> bool {
+        self.variables.iter().any(|(var, _)| var == name)
+            || self.items.iter().any(|item| matches!(item, LangType::Const(con) if con.name == name))
+    }
+}
+
+/// Whether `input` has `fn`, `if`, `match`, `struct`, `enum` or `asm` blocks without their `end`.
+/// Meta-commands are judged by the code after the command.
+pub fn is_incomplete(input: &str) -> bool {
+    let code = match input.trim_start().strip_prefix(':') {
+        Some(meta) => meta.split_once(char::is_whitespace).map(|(_, code)| code).unwrap_or_default(),
+        None => input,
+    };
+    // lexer errors are reported once the entry is evaluated
+    let tokens = Lexer::new(code.to_string()).collect().unwrap_or_default();
+
+    let mut depth = 0;
+    for line in organize_tokenlist(&tokens) {
+        if opens_block(&line) {
+            depth += 1;
+        } else if line.first() == Some(&Token::End) {
+            depth -= 1;
+        }
+    }
+
+    depth > 0
+}
+
+/// Reads entries from `input` until it ends or `:quit`, prompting on `out`.
+pub fn run<R: BufRead, W: Write>(mut input: R, mut out: W) -> Result<()> {
+    let context = Context::create();
+    let mut repl = Repl::new(&context)?;
+    let mut entry = String::new();
+
+    loop {
+        write!(out, "{}", if entry.is_empty() { "> " } else { "... " })?;
+        out.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            break;
+        }
+        entry.push_str(&line);
+
+        if entry.trim().is_empty() {
+            entry.clear();
+            continue;
+        }
+        if is_incomplete(&entry) {
+            continue;
+        }
+
+        let complete = std::mem::take(&mut entry);
+        let complete = complete.trim_end();
+        if complete == ":quit" || complete == ":q" {
+            break;
+        }
+        if let Err(e) = repl.eval(complete, &mut out) {
+            writeln!(out, "error: {}", e)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn item_name(lang_t: &LangType) -> Option<&str> {
+    match lang_t {
+        LangType::Func(func) => Some(&func.name),
+        LangType::Const(con) => Some(&con.name),
+        LangType::Enum(enum_t) => Some(&enum_t.name),
+        LangType::Struct(struct_t) => Some(&struct_t.name),
+        _ => None,
+    }
+}
+
+// ------------------------------------
+// Tests
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use super::is_incomplete;
+
+    #[test]
+    fn repl_incomplete() -> Result<()> {
+        assert!(!is_incomplete("let a 1"));
+        assert!(is_incomplete("fn f n:i32 -> i32:\n    if n < 2:\n        return n\n    end\n"));
+        assert!(!is_incomplete("fn f n:i32 -> i32:\n    if n < 2:\n        return n\n    end\nend\n"));
+        assert!(is_incomplete("export fn f:\n    asm:\n        nop\n    end\n"));
+        assert!(!is_incomplete("extern fn puts str -> i32"));
+        assert!(is_incomplete(":ast if a < 1:"));
+        assert!(!is_incomplete(":ir"));
+
+        return Ok(());
+    }
+}
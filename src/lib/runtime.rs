@@ -0,0 +1,232 @@
+use anyhow::{anyhow, Result};
+
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::module::{Linkage, Module};
+use inkwell::types::{FunctionType, PointerType};
+use inkwell::values::{FunctionValue, IntValue, PointerValue};
+use inkwell::{AddressSpace, IntPredicate};
+
+// Strings are NUL terminated byte arrays. The runtime functions are emitted into the
+// module the first time they are used, so programs without string ops don't carry them.
+const STR_LEN: &str = "__masm_str_len";
+const STR_CMP: &str = "__masm_str_cmp";
+const STR_CONCAT: &str = "__masm_str_concat";
+const STR_SUB: &str = "__masm_str_sub";
+
+pub struct Runtime<'a, 'ctx> {
+    context: &'ctx Context,
+    module: &'a Module<'ctx>,
+}
+
+impl<'a, 'ctx> Runtime<'a, 'ctx> {
+    pub fn new(context: &'ctx Context, module: &'a Module<'ctx>) -> Self {
+        Self { context, module }
+    }
+
+    /// `len(s) -> i64`, the number of bytes before the terminator.
+    pub fn str_len(&self) -> Result<FunctionValue<'ctx>> {
+        if let Some(fn_val) = self.module.get_function(STR_LEN) {
+            return Ok(fn_val);
+        }
+
+        let i64_type = self.context.i64_type();
+        let fn_type = i64_type.fn_type(&[self.str_type().into()], false);
+        let fn_val = self.add_function(STR_LEN, fn_type);
+        let s = self.param(fn_val, 0)?;
+
+        let builder = self.context.create_builder();
+        let entry = self.context.append_basic_block(fn_val, "entry");
+        let loop_bb = self.context.append_basic_block(fn_val, "loop");
+        let exit_bb = self.context.append_basic_block(fn_val, "exit");
+
+        builder.position_at_end(entry);
+        builder.build_unconditional_branch(loop_bb)?;
+
+        builder.position_at_end(loop_bb);
+        let i = builder.build_phi(i64_type, "i")?;
+        let c = self.load_byte(&builder, s, i.as_basic_value().into_int_value())?;
+        let next = builder.build_int_add(i.as_basic_value().into_int_value(), i64_type.const_int(1, false), "next")?;
+        let done = builder.build_int_compare(IntPredicate::EQ, c, self.context.i8_type().const_zero(), "done")?;
+        i.add_incoming(&[(&i64_type.const_zero(), entry), (&next, loop_bb)]);
+        builder.build_conditional_branch(done, exit_bb, loop_bb)?;
+
+        builder.position_at_end(exit_bb);
+        builder.build_return(Some(&i.as_basic_value()))?;
+
+        Ok(fn_val)
+    }
+
+    /// `cmp(a, b) -> i32`, negative, zero or positive like `strcmp`. Bytes compare unsigned.
+    pub fn str_cmp(&self) -> Result<FunctionValue<'ctx>> {
+        if let Some(fn_val) = self.module.get_function(STR_CMP) {
+            return Ok(fn_val);
+        }
+
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+        let fn_type = i32_type.fn_type(&[self.str_type().into(), self.str_type().into()], false);
+        let fn_val = self.add_function(STR_CMP, fn_type);
+        let a = self.param(fn_val, 0)?;
+        let b = self.param(fn_val, 1)?;
+
+        let builder = self.context.create_builder();
+        let entry = self.context.append_basic_block(fn_val, "entry");
+        let loop_bb = self.context.append_basic_block(fn_val, "loop");
+        let same_bb = self.context.append_basic_block(fn_val, "same");
+        let differ_bb = self.context.append_basic_block(fn_val, "differ");
+        let equal_bb = self.context.append_basic_block(fn_val, "equal");
+
+        builder.position_at_end(entry);
+        builder.build_unconditional_branch(loop_bb)?;
+
+        builder.position_at_end(loop_bb);
+        let i = builder.build_phi(i64_type, "i")?;
+        let ca = self.load_byte(&builder, a, i.as_basic_value().into_int_value())?;
+        let cb = self.load_byte(&builder, b, i.as_basic_value().into_int_value())?;
+        let differ = builder.build_int_compare(IntPredicate::NE, ca, cb, "differ")?;
+        builder.build_conditional_branch(differ, differ_bb, same_bb)?;
+
+        // equal bytes, done once both strings end
+        builder.position_at_end(same_bb);
+        let end = builder.build_int_compare(IntPredicate::EQ, ca, self.context.i8_type().const_zero(), "end")?;
+        let next = builder.build_int_add(i.as_basic_value().into_int_value(), i64_type.const_int(1, false), "next")?;
+        i.add_incoming(&[(&i64_type.const_zero(), entry), (&next, same_bb)]);
+        builder.build_conditional_branch(end, equal_bb, loop_bb)?;
+
+        builder.position_at_end(differ_bb);
+        let ca = builder.build_int_z_extend(ca, i32_type, "ca")?;
+        let cb = builder.build_int_z_extend(cb, i32_type, "cb")?;
+        builder.build_return(Some(&builder.build_int_sub(ca, cb, "diff")?))?;
+
+        builder.position_at_end(equal_bb);
+        builder.build_return(Some(&i32_type.const_zero()))?;
+
+        Ok(fn_val)
+    }
+
+    /// `concat(a, b) -> str`, a new string allocated with `malloc`.
+    pub fn str_concat(&self) -> Result<FunctionValue<'ctx>> {
+        if let Some(fn_val) = self.module.get_function(STR_CONCAT) {
+            return Ok(fn_val);
+        }
+
+        let fn_type = self.str_type().fn_type(&[self.str_type().into(), self.str_type().into()], false);
+        let fn_val = self.add_function(STR_CONCAT, fn_type);
+        let a = self.param(fn_val, 0)?;
+        let b = self.param(fn_val, 1)?;
+
+        let builder = self.context.create_builder();
+        builder.position_at_end(self.context.append_basic_block(fn_val, "entry"));
+
+        let len_a = self.call_len(&builder, a)?;
+        let len_b = self.call_len(&builder, b)?;
+        let len = builder.build_int_add(len_a, len_b, "len")?;
+        let result = self.alloc_str(&builder, len)?;
+
+        builder.build_memcpy(result, 1, a, 1, len_a).map_err(|e| anyhow!(e))?;
+        let tail = unsafe { builder.build_gep(self.context.i8_type(), result, &[len_a], "tail")? };
+        builder.build_memcpy(tail, 1, b, 1, len_b).map_err(|e| anyhow!(e))?;
+        builder.build_return(Some(&result))?;
+
+        Ok(fn_val)
+    }
+
+    /// `substr(s, start, count) -> str`, a new string allocated with `malloc`.
+    /// `start` and `count` are clamped to the end of `s`.
+    pub fn str_sub(&self) -> Result<FunctionValue<'ctx>> {
+        if let Some(fn_val) = self.module.get_function(STR_SUB) {
+            return Ok(fn_val);
+        }
+
+        let i64_type = self.context.i64_type();
+        let fn_type = self
+            .str_type()
+            .fn_type(&[self.str_type().into(), i64_type.into(), i64_type.into()], false);
+        let fn_val = self.add_function(STR_SUB, fn_type);
+        let s = self.param(fn_val, 0)?;
+        let start = fn_val
+            .get_nth_param(1)
+            .ok_or_else(|| anyhow!("Missing parameter"))?
+            .into_int_value();
+        let count = fn_val
+            .get_nth_param(2)
+            .ok_or_else(|| anyhow!("Missing parameter"))?
+            .into_int_value();
+
+        let builder = self.context.create_builder();
+        builder.position_at_end(self.context.append_basic_block(fn_val, "entry"));
+
+        let len = self.call_len(&builder, s)?;
+        let start = self.build_umin(&builder, start, len)?;
+        let rest = builder.build_int_sub(len, start, "rest")?;
+        let count = self.build_umin(&builder, count, rest)?;
+        let result = self.alloc_str(&builder, count)?;
+
+        let from = unsafe { builder.build_gep(self.context.i8_type(), s, &[start], "from")? };
+        builder.build_memcpy(result, 1, from, 1, count).map_err(|e| anyhow!(e))?;
+        builder.build_return(Some(&result))?;
+
+        Ok(fn_val)
+    }
+
+    fn str_type(&self) -> PointerType<'ctx> {
+        self.context.i8_type().ptr_type(AddressSpace::default())
+    }
+
+    fn add_function(&self, name: &str, fn_type: FunctionType<'ctx>) -> FunctionValue<'ctx> {
+        self.module.add_function(name, fn_type, Some(Linkage::Internal))
+    }
+
+    fn param(&self, fn_val: FunctionValue<'ctx>, n: u32) -> Result<PointerValue<'ctx>> {
+        Ok(fn_val
+            .get_nth_param(n)
+            .ok_or_else(|| anyhow!("Missing parameter"))?
+            .into_pointer_value())
+    }
+
+    fn load_byte(&self, builder: &Builder<'ctx>, s: PointerValue<'ctx>, i: IntValue<'ctx>) -> Result<IntValue<'ctx>> {
+        let i8_type = self.context.i8_type();
+        let ptr = unsafe { builder.build_gep(i8_type, s, &[i], "p")? };
+
+        Ok(builder.build_load(i8_type, ptr, "c")?.into_int_value())
+    }
+
+    fn call_len(&self, builder: &Builder<'ctx>, s: PointerValue<'ctx>) -> Result<IntValue<'ctx>> {
+        let len = builder.build_call(self.str_len()?, &[s.into()], "len")?;
+
+        Ok(len
+            .try_as_basic_value()
+            .left()
+            .ok_or_else(|| anyhow!("len did not produce a value"))?
+            .into_int_value())
+    }
+
+    /// Allocates `len + 1` bytes and writes the terminator at `len`.
+    fn alloc_str(&self, builder: &Builder<'ctx>, len: IntValue<'ctx>) -> Result<PointerValue<'ctx>> {
+        let i64_type = self.context.i64_type();
+        let malloc = self.module.get_function("malloc").unwrap_or_else(|| {
+            let malloc_type = self.str_type().fn_type(&[i64_type.into()], false);
+            self.module.add_function("malloc", malloc_type, Some(Linkage::External))
+        });
+
+        let size = builder.build_int_add(len, i64_type.const_int(1, false), "size")?;
+        let result = builder
+            .build_call(malloc, &[size.into()], "str")?
+            .try_as_basic_value()
+            .left()
+            .ok_or_else(|| anyhow!("malloc did not produce a value"))?
+            .into_pointer_value();
+
+        let end = unsafe { builder.build_gep(self.context.i8_type(), result, &[len], "end")? };
+        builder.build_store(end, self.context.i8_type().const_zero())?;
+
+        Ok(result)
+    }
+
+    fn build_umin(&self, builder: &Builder<'ctx>, a: IntValue<'ctx>, b: IntValue<'ctx>) -> Result<IntValue<'ctx>> {
+        let less = builder.build_int_compare(IntPredicate::ULT, a, b, "less")?;
+
+        Ok(builder.build_select(less, a, b, "min")?.into_int_value())
+    }
+}
@@ -0,0 +1,42 @@
+use crate::types::lang_type::LangType;
+
+#[derive(Clone, Debug)]
+pub struct AsmOperand {
+    pub constraint: String,
+    pub value: LangType,
+}
+
+impl AsmOperand {
+    pub fn new(constraint: String, value: LangType) -> Self {
+        Self { constraint, value }
+    }
+}
+
+/// Inline assembly block. Operands are referenced as `$0`, `$1`, ... in
+/// the template, outputs first, and a literal `$` is written as `$$`.
+#[derive(Clone, Debug, Default)]
+pub struct AsmType {
+    pub lines: Vec<String>,
+    pub outputs: Vec<AsmOperand>,
+    pub inputs: Vec<AsmOperand>,
+    pub clobbers: Vec<String>,
+    pub intel: bool,
+}
+
+impl AsmType {
+    pub fn new(
+        lines: Vec<String>,
+        outputs: Vec<AsmOperand>,
+        inputs: Vec<AsmOperand>,
+        clobbers: Vec<String>,
+        intel: bool,
+    ) -> Self {
+        Self {
+            lines,
+            outputs,
+            inputs,
+            clobbers,
+            intel,
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use crate::types::lang_type::LangType;
 
+/// Functions every backend provides, unless the program defines one with the same name.
+pub const BUILTINS: &[&str] = &["print", "len", "substr", "syscall"];
+
 #[derive(Clone, Debug)]
 pub struct CallType {
     pub name: String,
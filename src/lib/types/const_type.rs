@@ -1,20 +1,22 @@
+use super::primitive_type::PrimitiveType;
+
 #[derive(Clone, Debug)]
 pub struct ConstType {
     pub name: String,
-    pub value: String,
+    pub value: PrimitiveType,
 }
 
 impl Default for ConstType {
     fn default() -> Self {
         Self {
             name: "".to_string(),
-            value: "".to_string(),
+            value: PrimitiveType::default(),
         }
     }
 }
 
 impl ConstType {
-    pub fn new(name: String, value: String) -> Self {
+    pub fn new(name: String, value: PrimitiveType) -> Self {
         Self { name, value }
     }
 }
@@ -0,0 +1,179 @@
+use std::fmt::Display;
+
+/// Names `DataType::from_name` knows.
+pub const TYPE_NAMES: &[&str] = &[
+    "i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64", "f32", "f64", "bool", "str", "ptr", "void",
+];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum DataType {
+    I8,
+    I16,
+    #[default]
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+    Bool,
+    Str,
+    Ptr(Box<DataType>),
+    Array(Box<DataType>, u32),
+    Struct(String),
+    Void,
+}
+
+impl Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataType::I8 => write!(f, "i8"),
+            DataType::I16 => write!(f, "i16"),
+            DataType::I32 => write!(f, "i32"),
+            DataType::I64 => write!(f, "i64"),
+            DataType::U8 => write!(f, "u8"),
+            DataType::U16 => write!(f, "u16"),
+            DataType::U32 => write!(f, "u32"),
+            DataType::U64 => write!(f, "u64"),
+            DataType::F32 => write!(f, "f32"),
+            DataType::F64 => write!(f, "f64"),
+            DataType::Bool => write!(f, "bool"),
+            DataType::Str => write!(f, "str"),
+            DataType::Ptr(inner) => {
+                if **inner == DataType::Void {
+                    write!(f, "ptr")
+                } else {
+                    write!(f, "ptr<{}>", inner)
+                }
+            }
+            DataType::Array(elem, len) => write!(f, "[{}; {}]", elem, len),
+            DataType::Struct(name) => write!(f, "{}", name),
+            DataType::Void => write!(f, "void"),
+        }
+    }
+}
+
+impl DataType {
+    pub fn from_name(name: &str) -> Option<DataType> {
+        match name {
+            "i8" => Some(DataType::I8),
+            "i16" => Some(DataType::I16),
+            "i32" => Some(DataType::I32),
+            "i64" => Some(DataType::I64),
+            "u8" => Some(DataType::U8),
+            "u16" => Some(DataType::U16),
+            "u32" => Some(DataType::U32),
+            "u64" => Some(DataType::U64),
+            "f32" => Some(DataType::F32),
+            "f64" => Some(DataType::F64),
+            "bool" => Some(DataType::Bool),
+            "str" => Some(DataType::Str),
+            "ptr" => Some(DataType::ptr(DataType::Void)),
+            "void" => Some(DataType::Void),
+            _ => None,
+        }
+    }
+
+    pub fn ptr(pointee: DataType) -> DataType {
+        DataType::Ptr(Box::new(pointee))
+    }
+
+    pub fn array(elem: DataType, len: u32) -> DataType {
+        DataType::Array(Box::new(elem), len)
+    }
+
+    pub fn is_array(&self) -> bool {
+        matches!(self, DataType::Array(..))
+    }
+
+    pub fn is_struct(&self) -> bool {
+        matches!(self, DataType::Struct(_))
+    }
+
+    pub fn is_ptr(&self) -> bool {
+        matches!(self, DataType::Ptr(_))
+    }
+
+    /// Type a pointer points to, `None` for non-pointers.
+    pub fn pointee(&self) -> Option<&DataType> {
+        match self {
+            DataType::Ptr(inner) => Some(inner),
+            _ => None,
+        }
+    }
+
+    pub fn is_int(&self) -> bool {
+        self.is_signed() || self.is_unsigned()
+    }
+
+    pub fn is_signed(&self) -> bool {
+        matches!(self, DataType::I8 | DataType::I16 | DataType::I32 | DataType::I64)
+    }
+
+    pub fn is_unsigned(&self) -> bool {
+        matches!(self, DataType::U8 | DataType::U16 | DataType::U32 | DataType::U64)
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, DataType::F32 | DataType::F64)
+    }
+
+    /// Whether `cast` converts this type to `to`: numbers and bools to numbers, and pointers
+    /// and strings to each other and to and from integers.
+    pub fn can_cast_to(&self, to: &DataType) -> bool {
+        let numeric = |t: &DataType| t.is_int() || t.is_float();
+        let address = |t: &DataType| t.is_ptr() || *t == DataType::Str;
+        self == to
+            || (numeric(self) && numeric(to))
+            || (*self == DataType::Bool && to.is_int())
+            || (address(self) && (address(to) || to.is_int()))
+            || (self.is_int() && address(to))
+    }
+
+    /// Whether `bitcast` can reinterpret the bits of this type as `to`, which takes numbers of the same width.
+    pub fn can_bitcast_to(&self, to: &DataType) -> bool {
+        let numeric = |t: &DataType| t.is_int() || t.is_float();
+        numeric(self) && numeric(to) && self.bit_width() == to.bit_width()
+    }
+
+    /// Whether the integer `value` is representable in this integer type.
+    pub fn fits(&self, value: i64) -> bool {
+        let bits = self.bit_width();
+        if self.is_signed() {
+            bits >= 64 || (value >= -(1 << (bits - 1)) && value < (1 << (bits - 1)))
+        } else if self.is_unsigned() {
+            value >= 0 && (bits >= 64 || value < (1 << bits))
+        } else {
+            false
+        }
+    }
+
+    /// Whether an integer literal of `radix` fits. Decimal literals have to be in range of the type,
+    /// other radixes may spell any bit pattern of its width. Negative literals need a signed type.
+    pub fn fits_literal(&self, value: i128, radix: u32) -> bool {
+        let bits = self.bit_width();
+        let min = if self.is_signed() { -(1i128 << (bits - 1)) } else { 0 };
+        let max = if self.is_signed() && radix == 10 {
+            (1i128 << (bits - 1)) - 1
+        } else {
+            (1i128 << bits) - 1
+        };
+
+        min <= value && value <= max
+    }
+
+    pub fn bit_width(&self) -> u32 {
+        match self {
+            DataType::I8 | DataType::U8 => 8,
+            DataType::I16 | DataType::U16 => 16,
+            DataType::I32 | DataType::U32 | DataType::F32 => 32,
+            DataType::I64 | DataType::U64 | DataType::F64 | DataType::Str | DataType::Ptr(_) => 64,
+            DataType::Bool => 1,
+            DataType::Array(elem, len) => elem.bit_width() * len,
+            // struct sizes depend on the declaration and are computed by codegen
+            DataType::Struct(_) | DataType::Void => 0,
+        }
+    }
+}
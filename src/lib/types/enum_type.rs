@@ -0,0 +1,36 @@
+use super::data_type::DataType;
+
+#[derive(Clone, Debug)]
+pub struct EnumVariant {
+    pub name: String,
+    pub value: i64,
+}
+
+impl EnumVariant {
+    pub fn new(name: String, value: i64) -> Self {
+        Self { name, value }
+    }
+}
+
+/// C-like enum. Variants are constants of `data_type`, written `Name.Variant`;
+/// a variant without `= value` is one more than the previous one.
+#[derive(Clone, Debug, Default)]
+pub struct EnumType {
+    pub name: String,
+    pub data_type: DataType,
+    pub variants: Vec<EnumVariant>,
+}
+
+impl EnumType {
+    pub fn new(name: String, data_type: DataType, variants: Vec<EnumVariant>) -> Self {
+        Self {
+            name,
+            data_type,
+            variants,
+        }
+    }
+
+    pub fn variant(&self, name: &str) -> Option<&EnumVariant> {
+        self.variants.iter().find(|v| v.name == name)
+    }
+}
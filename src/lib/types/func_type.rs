@@ -1,11 +1,17 @@
 use crate::types::lang_type::LangType;
+use super::data_type::DataType;
 use super::var_type::VarType;
 
 #[derive(Clone, Debug)]
 pub struct FuncType {
     pub name: String,
     pub param: Vec<VarType>,
+    pub ret: DataType,
     pub body: Vec<LangType>,
+    pub is_extern: bool,
+    pub is_export: bool,
+    pub variadic: bool,
+    pub line: u32, // 0 unless parsed with spans
 }
 
 impl Default for FuncType {
@@ -13,13 +19,35 @@ impl Default for FuncType {
         Self {
             name: "".to_string(),
             param: vec![],
+            ret: DataType::I32,
             body: vec![],
+            is_extern: false,
+            is_export: false,
+            variadic: false,
+            line: 0,
         }
     }
 }
 
 impl FuncType {
-    pub fn new(name: String, param: Vec<VarType>,body: Vec<LangType>) -> Self {
-        Self { name, param, body }
+    pub fn new(name: String, param: Vec<VarType>, ret: DataType, body: Vec<LangType>) -> Self {
+        Self {
+            name,
+            param,
+            ret,
+            body,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_extern(name: String, param: Vec<VarType>, ret: DataType, variadic: bool) -> Self {
+        Self {
+            name,
+            param,
+            ret,
+            is_extern: true,
+            variadic,
+            ..Default::default()
+        }
     }
 }
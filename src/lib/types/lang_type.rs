@@ -5,6 +5,13 @@ use super::op_type::OpType;
 use super::primitive_type::PrimitiveType;
 use super::var_type::VarType;
 use super::call_type::CallType;
+use super::return_type::ReturnType;
+use super::asm_type::AsmType;
+use super::struct_type::StructType;
+use super::enum_type::EnumType;
+use super::match_type::MatchType;
+use super::data_type::DataType;
+use crate::lexer::Span;
 
 #[derive(Clone, Debug)]
 pub enum LangType {
@@ -15,12 +22,19 @@ pub enum LangType {
     Call(CallType),
     Func(FuncType),
     If(IfType),
+    Return(ReturnType),
+    Asm(AsmType),
+    Struct(StructType),
+    Enum(EnumType),
+    Match(MatchType),
+    Type(DataType), // target of a cast
 
     // Parser helper
     Else,
     End,
     Eof,
     Comment(String),
+    Location(Span), // where the next statement starts, from Parser::with_spans
 
     Undefined,
 }
@@ -0,0 +1,41 @@
+use crate::types::lang_type::LangType;
+
+/// One arm of a match, taken when the value equals any of `patterns`.
+#[derive(Clone, Debug)]
+pub struct MatchArm {
+    pub patterns: Vec<LangType>,
+    pub body: Vec<LangType>,
+}
+
+impl MatchArm {
+    pub fn new(patterns: Vec<LangType>, body: Vec<LangType>) -> Self {
+        Self { patterns, body }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MatchType {
+    pub value: Box<LangType>,
+    pub arms: Vec<MatchArm>,
+    pub default: Option<Vec<LangType>>,
+}
+
+impl Default for MatchType {
+    fn default() -> Self {
+        Self {
+            value: Box::new(LangType::Undefined),
+            arms: vec![],
+            default: None,
+        }
+    }
+}
+
+impl MatchType {
+    pub fn new(value: LangType, arms: Vec<MatchArm>, default: Option<Vec<LangType>>) -> Self {
+        Self {
+            value: Box::new(value),
+            arms,
+            default,
+        }
+    }
+}
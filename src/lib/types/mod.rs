@@ -6,3 +6,9 @@ pub mod primitive_type;
 pub mod var_type;
 pub mod const_type;
 pub mod call_type;
+pub mod data_type;
+pub mod return_type;
+pub mod asm_type;
+pub mod struct_type;
+pub mod enum_type;
+pub mod match_type;
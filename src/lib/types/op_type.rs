@@ -1,5 +1,11 @@
 use super::lang_type::LangType;
 
+/// Mnemonics that start an instruction line, and `addr` which takes the address of its operand.
+pub const INSTRUCTIONS: &[&str] = &[
+    "add", "sub", "mul", "div", "mod", "offset", "neg", "itof", "ftoi", "load", "store", "move", "cast", "bitcast",
+    "addr",
+];
+
 #[derive(Clone, Debug)]
 pub enum Operation {
     //Basic Assignment Operator
@@ -11,6 +17,21 @@ pub enum Operation {
     Mul, // *
     Div, // /
     Mod, // %
+    Neg, // neg x or neg dest x or -x
+
+    //Conversion Operators
+    IntToFloat, // itof dest x
+    FloatToInt, // ftoi dest x, rounds toward zero
+    Cast,       // cast dest x as type
+    Bitcast,    // bitcast dest x as type, same bits as a type of the same width
+
+    //Pointer Operators
+    Addr,   // addr x -> address of variable x
+    Load,   // load dest p
+    Store,  // store p value
+    Offset, // offset dest p n -> p + n elements
+    Index,  // load dest arr i / store arr i value -> address of arr[i]
+    Field,  // load dest s.field / store s.field value -> address of s.field
 
     //Relational Operators
     Equal,       // ==
@@ -55,10 +76,21 @@ impl OpType {
             "mul" => Operation::Mul,
             "div" => Operation::Div,
             "mod" => Operation::Mod,
+            "offset" => Operation::Offset,
             _ => Operation::Error,
         }
     }
 
+    /// Instructions taking a single operand, `op x` in place or `op dest x`.
+    pub fn get_unary_op(op_name: &str) -> Option<Operation> {
+        match op_name {
+            "neg" => Some(Operation::Neg),
+            "itof" => Some(Operation::IntToFloat),
+            "ftoi" => Some(Operation::FloatToInt),
+            _ => None,
+        }
+    }
+
     pub fn is_op(op_name: &str) -> bool {
         let op = OpType::get_op_by_string(op_name);
         !matches!(op, Operation::Error)
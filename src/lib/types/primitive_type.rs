@@ -3,6 +3,7 @@
 #[derive(Clone, Debug)]
 pub enum Primitives {
     Int,
+    Float,
     String,
     Bool,
 }
@@ -11,6 +12,7 @@ pub enum Primitives {
 pub struct PrimitiveType {
     pub value: String,
     pub primitive: Primitives,
+    pub radix: u32, // digits of integers are in this base, after the sign and without prefix and `_`
 }
 
 impl Default for PrimitiveType {
@@ -18,13 +20,48 @@ impl Default for PrimitiveType {
         Self {
             value: "".to_string(),
             primitive: Primitives::Int,
+            radix: 10,
         }
     }
 }
 
 impl PrimitiveType {
     pub fn new(value: String, primitive: Primitives) -> Self {
-        Self { value, primitive }
+        Self {
+            value,
+            primitive,
+            radix: 10,
+        }
+    }
+
+    /// Integer from a literal like `255`, `-0xFF`, `0b1111_1111` or `0o377`.
+    pub fn int(literal: &str) -> Self {
+        let (sign, literal) = match literal.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", literal),
+        };
+        let (radix, digits) = match literal.get(..2) {
+            Some("0x") | Some("0X") => (16, &literal[2..]),
+            Some("0b") | Some("0B") => (2, &literal[2..]),
+            Some("0o") | Some("0O") => (8, &literal[2..]),
+            _ => (10, literal),
+        };
+
+        Self {
+            value: format!("{}{}", sign, digits.replace('_', "")),
+            primitive: Primitives::Int,
+            radix,
+        }
+    }
+
+    /// Float from a literal like `3.14`, `-0.5` or `1e-9`.
+    pub fn float(literal: &str) -> Self {
+        Self::new(literal.replace('_', ""), Primitives::Float)
+    }
+
+    /// Value of an integer; wide enough for every `i64` and `u64`.
+    pub fn to_i128(&self) -> Option<i128> {
+        i128::from_str_radix(&self.value, self.radix).ok()
     }
 
     pub fn is_int(&self) -> bool {
@@ -39,3 +76,37 @@ impl PrimitiveType {
         matches!(self.primitive, Primitives::Bool)
     }
 }
+
+/// `printf("%g")`: 6 significant digits without trailing zeros, in exponent form
+/// below 1e-4 and from 1e6 up.
+pub fn format_g(value: f64) -> String {
+    if value.is_nan() {
+        return "nan".to_string();
+    }
+    if value.is_infinite() {
+        return if value < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+    if value == 0.0 {
+        return if value.is_sign_negative() { "-0" } else { "0" }.to_string();
+    }
+
+    // the exponent after rounding to 6 digits decides the form
+    let scientific = format!("{:.5e}", value);
+    let (mantissa, exp) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exp: i32 = exp.parse().unwrap_or_default();
+
+    if !(-4..6).contains(&exp) {
+        let sign = if exp < 0 { '-' } else { '+' };
+        return format!("{}e{}{:02}", trim_fraction(mantissa), sign, exp.abs());
+    }
+
+    trim_fraction(&format!("{:.*}", (5 - exp) as usize, value)).to_string()
+}
+
+fn trim_fraction(s: &str) -> &str {
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        s
+    }
+}
@@ -0,0 +1,22 @@
+use crate::types::lang_type::LangType;
+
+#[derive(Clone, Debug)]
+pub struct ReturnType {
+    pub value: Box<LangType>,
+}
+
+impl Default for ReturnType {
+    fn default() -> Self {
+        Self {
+            value: Box::new(LangType::Undefined),
+        }
+    }
+}
+
+impl ReturnType {
+    pub fn new(value: LangType) -> Self {
+        Self {
+            value: Box::new(value),
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use super::var_type::VarType;
+
+/// `struct Name [packed] [align(N)]:` followed by one `field: type` per line.
+#[derive(Clone, Debug, Default)]
+pub struct StructType {
+    pub name: String,
+    pub fields: Vec<VarType>,
+    pub packed: bool,
+    pub align: Option<u32>,
+}
+
+impl StructType {
+    pub fn new(name: String, fields: Vec<VarType>, packed: bool, align: Option<u32>) -> Self {
+        Self {
+            name,
+            fields,
+            packed,
+            align,
+        }
+    }
+
+    /// Position of `field` in the declaration.
+    pub fn field_index(&self, field: &str) -> Option<usize> {
+        self.fields.iter().position(|f| f.name == field)
+    }
+}
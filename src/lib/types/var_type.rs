@@ -1,18 +1,32 @@
+use super::data_type::DataType;
+
 #[derive(Clone, Debug)]
 pub struct VarType {
     pub name: String,
+    pub data_type: Option<DataType>,
 }
 
 impl Default for VarType {
     fn default() -> Self {
         Self {
             name: "".to_string(),
+            data_type: None,
         }
     }
 }
 
 impl VarType {
     pub fn new(name: String) -> Self {
-        Self { name }
+        Self {
+            name,
+            data_type: None,
+        }
+    }
+
+    pub fn typed(name: String, data_type: DataType) -> Self {
+        Self {
+            name,
+            data_type: Some(data_type),
+        }
     }
 }
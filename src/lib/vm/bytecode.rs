@@ -0,0 +1,712 @@
+use std::fmt::Display;
+
+use anyhow::{anyhow, Result};
+
+use crate::types::data_type::DataType;
+
+pub const MAGIC: &[u8; 4] = b"MABC";
+const VERSION: u16 = 1;
+
+/// Index into the register window of the running function.
+pub type Reg = u16;
+
+/// Types a register value can have, one byte in the file format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ty {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+    Bool,
+    Str,
+}
+
+const TYS: [Ty; 12] = [
+    Ty::I8,
+    Ty::I16,
+    Ty::I32,
+    Ty::I64,
+    Ty::U8,
+    Ty::U16,
+    Ty::U32,
+    Ty::U64,
+    Ty::F32,
+    Ty::F64,
+    Ty::Bool,
+    Ty::Str,
+];
+
+impl Ty {
+    /// `None` for types the VM has no registers for, like pointers and structs.
+    pub fn from_data_type(data_type: &DataType) -> Option<Ty> {
+        TYS.iter().copied().find(|ty| ty.data_type() == *data_type)
+    }
+
+    pub fn data_type(self) -> DataType {
+        match self {
+            Ty::I8 => DataType::I8,
+            Ty::I16 => DataType::I16,
+            Ty::I32 => DataType::I32,
+            Ty::I64 => DataType::I64,
+            Ty::U8 => DataType::U8,
+            Ty::U16 => DataType::U16,
+            Ty::U32 => DataType::U32,
+            Ty::U64 => DataType::U64,
+            Ty::F32 => DataType::F32,
+            Ty::F64 => DataType::F64,
+            Ty::Bool => DataType::Bool,
+            Ty::Str => DataType::Str,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Ty> {
+        TYS.get(byte as usize).copied().ok_or_else(|| anyhow!("Unknown type tag {}", byte))
+    }
+
+    fn byte(self) -> u8 {
+        TYS.iter().position(|ty| *ty == self).unwrap_or_default() as u8
+    }
+}
+
+impl Display for Ty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.data_type())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Void,
+    Bool(bool),
+    Int(u64, Ty), // bits of the value, truncated to the width of the type
+    Float(f64, Ty),
+    Str(Vec<u8>),
+}
+
+impl Value {
+    /// Integer of `ty`, wrapping `value` to its width.
+    pub fn int(value: i128, ty: Ty) -> Self {
+        let bits = ty.data_type().bit_width();
+        let mask = if bits >= 64 { u64::MAX } else { (1 << bits) - 1 };
+        Value::Int(value as u64 & mask, ty)
+    }
+
+    /// Float of `ty`, `f32` values are rounded to single precision.
+    pub fn float(value: f64, ty: Ty) -> Self {
+        let value = if ty == Ty::F32 { value as f32 as f64 } else { value };
+        Value::Float(value, ty)
+    }
+
+    /// Numeric value of an integer or bool, sign extended for signed types.
+    pub fn to_i128(&self) -> Option<i128> {
+        match self {
+            Value::Int(bits, ty) if ty.data_type().is_signed() => {
+                let shift = 64 - ty.data_type().bit_width();
+                Some(((*bits << shift) as i64 >> shift) as i128)
+            }
+            Value::Int(bits, _) => Some(*bits as i128),
+            Value::Bool(value) => Some(*value as i128),
+            _ => None,
+        }
+    }
+
+    pub fn ty(&self) -> Option<Ty> {
+        match self {
+            Value::Void => None,
+            Value::Bool(_) => Some(Ty::Bool),
+            Value::Int(_, ty) | Value::Float(_, ty) => Some(*ty),
+            Value::Str(_) => Some(Ty::Str),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Void => write!(f, "void"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Int(_, ty) => write!(f, "{}:{}", self.to_i128().unwrap_or_default(), ty),
+            Value::Float(value, ty) => write!(f, "{:?}:{}", value, ty),
+            Value::Str(s) => write!(f, "{:?}", String::from_utf8_lossy(s)),
+        }
+    }
+}
+
+/// One register machine instruction. Arithmetic and comparisons take the operation from the
+/// types of their operands, which the compiler already checked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instr {
+    Const { dst: Reg, index: u32 },
+    Move { dst: Reg, src: Reg },
+    Add { dst: Reg, a: Reg, b: Reg }, // concatenates strings
+    Sub { dst: Reg, a: Reg, b: Reg },
+    Mul { dst: Reg, a: Reg, b: Reg },
+    Div { dst: Reg, a: Reg, b: Reg },
+    Mod { dst: Reg, a: Reg, b: Reg },
+    Neg { dst: Reg, src: Reg },
+    Eq { dst: Reg, a: Reg, b: Reg },
+    Ne { dst: Reg, a: Reg, b: Reg },
+    Lt { dst: Reg, a: Reg, b: Reg },
+    Gt { dst: Reg, a: Reg, b: Reg },
+    Cast { dst: Reg, src: Reg, ty: Ty },
+    Bitcast { dst: Reg, src: Reg, ty: Ty },
+    Jump { target: u32 },
+    JumpIf { cond: Reg, target: u32 },
+    JumpIfNot { cond: Reg, target: u32 },
+    Call { dst: Reg, func: u32, args: Reg, argc: u16 }, // arguments in args..args + argc
+    Ret { src: Reg },
+    RetVoid,
+    Print { args: Reg, argc: u16 },
+    Len { dst: Reg, src: Reg },
+    Substr { dst: Reg, src: Reg, start: Reg, count: Reg },
+    Trap, // a match without else found no arm
+}
+
+impl Instr {
+    /// Whether execution never continues with the next instruction.
+    pub fn is_terminator(&self) -> bool {
+        matches!(self, Instr::Jump { .. } | Instr::Ret { .. } | Instr::RetVoid | Instr::Trap)
+    }
+
+    fn opcode(&self) -> u8 {
+        match self {
+            Instr::Const { .. } => 0,
+            Instr::Move { .. } => 1,
+            Instr::Add { .. } => 2,
+            Instr::Sub { .. } => 3,
+            Instr::Mul { .. } => 4,
+            Instr::Div { .. } => 5,
+            Instr::Mod { .. } => 6,
+            Instr::Neg { .. } => 7,
+            Instr::Eq { .. } => 8,
+            Instr::Ne { .. } => 9,
+            Instr::Lt { .. } => 10,
+            Instr::Gt { .. } => 11,
+            Instr::Cast { .. } => 12,
+            Instr::Jump { .. } => 13,
+            Instr::JumpIf { .. } => 14,
+            Instr::JumpIfNot { .. } => 15,
+            Instr::Call { .. } => 16,
+            Instr::Ret { .. } => 17,
+            Instr::RetVoid => 18,
+            Instr::Print { .. } => 19,
+            Instr::Len { .. } => 20,
+            Instr::Substr { .. } => 21,
+            Instr::Trap => 22,
+            Instr::Bitcast { .. } => 23,
+        }
+    }
+
+    /// Every register the instruction reads or writes, ranges included.
+    fn registers(&self) -> Vec<Reg> {
+        match *self {
+            Instr::Const { dst, .. } => vec![dst],
+            Instr::Move { dst, src }
+            | Instr::Neg { dst, src }
+            | Instr::Cast { dst, src, .. }
+            | Instr::Bitcast { dst, src, .. }
+            | Instr::Len { dst, src } => vec![dst, src],
+            Instr::Add { dst, a, b }
+            | Instr::Sub { dst, a, b }
+            | Instr::Mul { dst, a, b }
+            | Instr::Div { dst, a, b }
+            | Instr::Mod { dst, a, b }
+            | Instr::Eq { dst, a, b }
+            | Instr::Ne { dst, a, b }
+            | Instr::Lt { dst, a, b }
+            | Instr::Gt { dst, a, b } => vec![dst, a, b],
+            Instr::JumpIf { cond, .. } | Instr::JumpIfNot { cond, .. } => vec![cond],
+            Instr::Call { dst, args, argc, .. } => {
+                let mut regs = vec![dst];
+                regs.extend((0..argc).map(|i| args.saturating_add(i)));
+                regs
+            }
+            Instr::Print { args, argc } => (0..argc).map(|i| args.saturating_add(i)).collect(),
+            Instr::Ret { src } => vec![src],
+            Instr::Substr { dst, src, start, count } => vec![dst, src, start, count],
+            Instr::Jump { .. } | Instr::RetVoid | Instr::Trap => vec![],
+        }
+    }
+}
+
+impl Display for Instr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Instr::Const { dst, index } => write!(f, "const r{}, #{}", dst, index),
+            Instr::Move { dst, src } => write!(f, "move r{}, r{}", dst, src),
+            Instr::Add { dst, a, b } => write!(f, "add r{}, r{}, r{}", dst, a, b),
+            Instr::Sub { dst, a, b } => write!(f, "sub r{}, r{}, r{}", dst, a, b),
+            Instr::Mul { dst, a, b } => write!(f, "mul r{}, r{}, r{}", dst, a, b),
+            Instr::Div { dst, a, b } => write!(f, "div r{}, r{}, r{}", dst, a, b),
+            Instr::Mod { dst, a, b } => write!(f, "mod r{}, r{}, r{}", dst, a, b),
+            Instr::Neg { dst, src } => write!(f, "neg r{}, r{}", dst, src),
+            Instr::Eq { dst, a, b } => write!(f, "eq r{}, r{}, r{}", dst, a, b),
+            Instr::Ne { dst, a, b } => write!(f, "ne r{}, r{}, r{}", dst, a, b),
+            Instr::Lt { dst, a, b } => write!(f, "lt r{}, r{}, r{}", dst, a, b),
+            Instr::Gt { dst, a, b } => write!(f, "gt r{}, r{}, r{}", dst, a, b),
+            Instr::Cast { dst, src, ty } => write!(f, "cast r{}, r{} as {}", dst, src, ty),
+            Instr::Bitcast { dst, src, ty } => write!(f, "bitcast r{}, r{} as {}", dst, src, ty),
+            Instr::Jump { target } => write!(f, "jump {:04}", target),
+            Instr::JumpIf { cond, target } => write!(f, "jumpif r{}, {:04}", cond, target),
+            Instr::JumpIfNot { cond, target } => write!(f, "jumpifnot r{}, {:04}", cond, target),
+            Instr::Call { dst, func, args, argc } => write!(f, "call r{}, fn{}, r{}..+{}", dst, func, args, argc),
+            Instr::Ret { src } => write!(f, "ret r{}", src),
+            Instr::RetVoid => write!(f, "ret"),
+            Instr::Print { args, argc } => write!(f, "print r{}..+{}", args, argc),
+            Instr::Len { dst, src } => write!(f, "len r{}, r{}", dst, src),
+            Instr::Substr { dst, src, start, count } => write!(f, "substr r{}, r{}, r{}, r{}", dst, src, start, count),
+            Instr::Trap => write!(f, "trap"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<Ty>, // arguments arrive in r0..params.len()
+    pub ret: Option<Ty>,
+    pub registers: u16,
+    pub code: Vec<Instr>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program {
+    pub constants: Vec<Value>,
+    pub functions: Vec<Function>,
+}
+
+impl Program {
+    pub fn function(&self, name: &str) -> Option<usize> {
+        self.functions.iter().position(|f| f.name == name)
+    }
+
+    /// Checks that every register, constant, jump and call is in bounds and that no function
+    /// runs past its end, so the VM never has to trust a loaded file.
+    pub fn verify(&self) -> Result<()> {
+        if self.constants.contains(&Value::Void) {
+            return Err(anyhow!("Constants cannot be void"));
+        }
+
+        for func in &self.functions {
+            let error = |pc: usize, msg: String| anyhow!("Invalid bytecode in {} at {:04}: {}", func.name, pc, msg);
+
+            if func.params.len() > func.registers as usize {
+                return Err(anyhow!("Function {} has more parameters than registers", func.name));
+            }
+            if !func.code.last().is_some_and(|i| i.is_terminator()) {
+                return Err(anyhow!("Function {} does not end with a terminator", func.name));
+            }
+
+            for (pc, instr) in func.code.iter().enumerate() {
+                if let Some(reg) = instr.registers().into_iter().find(|r| *r >= func.registers) {
+                    return Err(error(pc, format!("register r{} out of range", reg)));
+                }
+                match *instr {
+                    Instr::Const { index, .. } if index as usize >= self.constants.len() => {
+                        return Err(error(pc, format!("constant #{} out of range", index)));
+                    }
+                    Instr::Jump { target } | Instr::JumpIf { target, .. } | Instr::JumpIfNot { target, .. }
+                        if target as usize >= func.code.len() =>
+                    {
+                        return Err(error(pc, format!("jump to {:04} out of range", target)));
+                    }
+                    Instr::Call { func: index, argc, .. } => {
+                        let callee = self
+                            .functions
+                            .get(index as usize)
+                            .ok_or_else(|| error(pc, format!("function {} out of range", index)))?;
+                        if callee.params.len() != argc as usize {
+                            let msg = format!("{} expects {} arguments, got {}", callee.name, callee.params.len(), argc);
+                            return Err(error(pc, msg));
+                        }
+                    }
+                    Instr::Ret { .. } if func.ret.is_none() => return Err(error(pc, "ret with a value in a void function".into())),
+                    Instr::RetVoid if func.ret.is_some() => return Err(error(pc, "ret without a value".into())),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer(vec![]);
+        w.0.extend_from_slice(MAGIC);
+        w.u16(VERSION);
+
+        w.u32(self.constants.len() as u32);
+        for constant in &self.constants {
+            match constant {
+                Value::Int(bits, ty) => {
+                    w.u8(0);
+                    w.u8(ty.byte());
+                    w.u64(*bits);
+                }
+                Value::Float(value, ty) => {
+                    w.u8(1);
+                    w.u8(ty.byte());
+                    w.u64(value.to_bits());
+                }
+                Value::Bool(value) => {
+                    w.u8(2);
+                    w.u8(*value as u8);
+                }
+                Value::Str(s) => {
+                    w.u8(3);
+                    w.bytes(s);
+                }
+                Value::Void => w.u8(4),
+            }
+        }
+
+        w.u32(self.functions.len() as u32);
+        for func in &self.functions {
+            w.bytes(func.name.as_bytes());
+            w.u16(func.params.len() as u16);
+            for ty in &func.params {
+                w.u8(ty.byte());
+            }
+            // 0xff for void
+            w.u8(func.ret.map(|ty| ty.byte()).unwrap_or(u8::MAX));
+            w.u16(func.registers);
+            w.u32(func.code.len() as u32);
+            for instr in &func.code {
+                w.instr(instr);
+            }
+        }
+
+        w.0
+    }
+
+    /// Reads and verifies a program written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Program> {
+        let mut r = Reader { bytes, pos: 0 };
+        if r.take(4)? != MAGIC {
+            return Err(anyhow!("Not a modern_asm bytecode file"));
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(anyhow!("Unsupported bytecode version {}", version));
+        }
+
+        let mut program = Program::default();
+        for _ in 0..r.u32()? {
+            let constant = match r.u8()? {
+                0 => Value::Int(0, Ty::from_byte(r.u8()?)?),
+                1 => Value::Float(0.0, Ty::from_byte(r.u8()?)?),
+                2 => Value::Bool(r.u8()? != 0),
+                3 => Value::Str(r.bytes()?.to_vec()),
+                tag => return Err(anyhow!("Unknown constant tag {}", tag)),
+            };
+            // the payload is read once the type is known, and re-wrapped to catch stray bits
+            let constant = match constant {
+                Value::Int(_, ty) if ty.data_type().is_int() => Value::int(r.u64()? as i128, ty),
+                Value::Float(_, ty) if ty.data_type().is_float() => Value::float(f64::from_bits(r.u64()?), ty),
+                Value::Int(_, ty) | Value::Float(_, ty) => return Err(anyhow!("Invalid constant type {}", ty)),
+                other => other,
+            };
+            program.constants.push(constant);
+        }
+
+        for _ in 0..r.u32()? {
+            let name = String::from_utf8(r.bytes()?.to_vec()).map_err(|_| anyhow!("Function name is not UTF-8"))?;
+            let mut params = vec![];
+            for _ in 0..r.u16()? {
+                params.push(Ty::from_byte(r.u8()?)?);
+            }
+            let ret = match r.u8()? {
+                u8::MAX => None,
+                byte => Some(Ty::from_byte(byte)?),
+            };
+            let mut func = Function {
+                name,
+                params,
+                ret,
+                registers: r.u16()?,
+                code: vec![],
+            };
+            for _ in 0..r.u32()? {
+                func.code.push(r.instr()?);
+            }
+            program.functions.push(func);
+        }
+
+        if r.pos != bytes.len() {
+            return Err(anyhow!("Trailing bytes after bytecode"));
+        }
+        program.verify()?;
+
+        Ok(program)
+    }
+}
+
+/// The disassembly: constants, then every function with its instructions.
+impl Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, constant) in self.constants.iter().enumerate() {
+            writeln!(f, "#{} = {}", i, constant)?;
+        }
+
+        for (i, func) in self.functions.iter().enumerate() {
+            let params: Vec<String> = func.params.iter().map(|ty| ty.to_string()).collect();
+            let ret = func.ret.map(|ty| format!(" -> {}", ty)).unwrap_or_default();
+            writeln!(f, "\nfn{} {}({}){}, {} registers", i, func.name, params.join(", "), ret, func.registers)?;
+            for (pc, instr) in func.code.iter().enumerate() {
+                write!(f, "  {:04}  {}", pc, instr)?;
+                match instr {
+                    Instr::Const { index, .. } => match self.constants.get(*index as usize) {
+                        Some(constant) => write!(f, "  ; {}", constant)?,
+                        None => write!(f, "  ; ???")?,
+                    },
+                    Instr::Call { func, .. } => match self.functions.get(*func as usize) {
+                        Some(callee) => write!(f, "  ; {}", callee.name)?,
+                        None => write!(f, "  ; ???")?,
+                    },
+                    _ => {}
+                }
+                writeln!(f)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn instr(&mut self, instr: &Instr) {
+        self.u8(instr.opcode());
+        match *instr {
+            Instr::Const { dst, index } => {
+                self.u16(dst);
+                self.u32(index);
+            }
+            Instr::Move { dst, src } | Instr::Neg { dst, src } | Instr::Len { dst, src } => {
+                self.u16(dst);
+                self.u16(src);
+            }
+            Instr::Add { dst, a, b }
+            | Instr::Sub { dst, a, b }
+            | Instr::Mul { dst, a, b }
+            | Instr::Div { dst, a, b }
+            | Instr::Mod { dst, a, b }
+            | Instr::Eq { dst, a, b }
+            | Instr::Ne { dst, a, b }
+            | Instr::Lt { dst, a, b }
+            | Instr::Gt { dst, a, b } => {
+                self.u16(dst);
+                self.u16(a);
+                self.u16(b);
+            }
+            Instr::Cast { dst, src, ty } | Instr::Bitcast { dst, src, ty } => {
+                self.u16(dst);
+                self.u16(src);
+                self.u8(ty.byte());
+            }
+            Instr::Jump { target } => self.u32(target),
+            Instr::JumpIf { cond, target } | Instr::JumpIfNot { cond, target } => {
+                self.u16(cond);
+                self.u32(target);
+            }
+            Instr::Call { dst, func, args, argc } => {
+                self.u16(dst);
+                self.u32(func);
+                self.u16(args);
+                self.u16(argc);
+            }
+            Instr::Ret { src } => self.u16(src),
+            Instr::Print { args, argc } => {
+                self.u16(args);
+                self.u16(argc);
+            }
+            Instr::Substr { dst, src, start, count } => {
+                self.u16(dst);
+                self.u16(src);
+                self.u16(start);
+                self.u16(count);
+            }
+            Instr::RetVoid | Instr::Trap => {}
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(n).filter(|end| *end <= self.bytes.len());
+        let end = end.ok_or_else(|| anyhow!("Truncated bytecode at byte {}", self.pos))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn instr(&mut self) -> Result<Instr> {
+        let opcode = self.u8()?;
+        Ok(match opcode {
+            0 => Instr::Const { dst: self.u16()?, index: self.u32()? },
+            1 => Instr::Move { dst: self.u16()?, src: self.u16()? },
+            2 => Instr::Add { dst: self.u16()?, a: self.u16()?, b: self.u16()? },
+            3 => Instr::Sub { dst: self.u16()?, a: self.u16()?, b: self.u16()? },
+            4 => Instr::Mul { dst: self.u16()?, a: self.u16()?, b: self.u16()? },
+            5 => Instr::Div { dst: self.u16()?, a: self.u16()?, b: self.u16()? },
+            6 => Instr::Mod { dst: self.u16()?, a: self.u16()?, b: self.u16()? },
+            7 => Instr::Neg { dst: self.u16()?, src: self.u16()? },
+            8 => Instr::Eq { dst: self.u16()?, a: self.u16()?, b: self.u16()? },
+            9 => Instr::Ne { dst: self.u16()?, a: self.u16()?, b: self.u16()? },
+            10 => Instr::Lt { dst: self.u16()?, a: self.u16()?, b: self.u16()? },
+            11 => Instr::Gt { dst: self.u16()?, a: self.u16()?, b: self.u16()? },
+            12 => Instr::Cast { dst: self.u16()?, src: self.u16()?, ty: Ty::from_byte(self.u8()?)? },
+            13 => Instr::Jump { target: self.u32()? },
+            14 => Instr::JumpIf { cond: self.u16()?, target: self.u32()? },
+            15 => Instr::JumpIfNot { cond: self.u16()?, target: self.u32()? },
+            16 => Instr::Call { dst: self.u16()?, func: self.u32()?, args: self.u16()?, argc: self.u16()? },
+            17 => Instr::Ret { src: self.u16()? },
+            18 => Instr::RetVoid,
+            19 => Instr::Print { args: self.u16()?, argc: self.u16()? },
+            20 => Instr::Len { dst: self.u16()?, src: self.u16()? },
+            21 => Instr::Substr { dst: self.u16()?, src: self.u16()?, start: self.u16()?, count: self.u16()? },
+            22 => Instr::Trap,
+            23 => Instr::Bitcast { dst: self.u16()?, src: self.u16()?, ty: Ty::from_byte(self.u8()?)? },
+            _ => return Err(anyhow!("Unknown opcode {} at byte {}", opcode, self.pos - 1)),
+        })
+    }
+}
+
+// ------------------------------------
+// Tests
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use super::{Function, Instr, Program, Ty, Value};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::vm::compiler::compile;
+
+    fn program(input: &str) -> Result<Program> {
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        return compile(ast);
+    }
+
+    #[test]
+    fn bytecode_roundtrip() -> Result<()> {
+        let input = r#"fn twice n:i64 -> i64:
+    mul r n 2
+    return r
+end
+fn main -> i32:
+    let x twice(21)
+    let f -0.5
+    cast g f as f32
+    bitcast h g as u32
+    print("x", x, f, g, h, true)
+    return 0
+end"#;
+        let program = program(input)?;
+        let bytes = program.to_bytes();
+        assert_eq!(Program::from_bytes(&bytes)?, program);
+
+        assert!(Program::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Program::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+        assert!(Program::from_bytes(b"MAB").is_err());
+        assert!(Program::from_bytes(input.as_bytes()).is_err());
+
+        return Ok(());
+    }
+
+    #[test]
+    fn bytecode_disasm() -> Result<()> {
+        let program = program("fn twice n:i64 -> i64:\n    mul r n 2\n    return r\nend")?;
+        let expected = r#"#0 = 2:i64
+
+fn0 twice(i64) -> i64, 3 registers
+  0000  const r2, #0  ; 2:i64
+  0001  mul r1, r0, r2
+  0002  ret r1
+"#;
+        assert_eq!(program.to_string(), expected);
+
+        return Ok(());
+    }
+
+    #[test]
+    fn bytecode_verify() -> Result<()> {
+        let function = |code: Vec<Instr>| Program {
+            constants: vec![Value::int(1, Ty::I32)],
+            functions: vec![Function {
+                name: "main".to_string(),
+                params: vec![],
+                ret: Some(Ty::I32),
+                registers: 2,
+                code,
+            }],
+        };
+
+        assert!(function(vec![Instr::Const { dst: 0, index: 0 }, Instr::Ret { src: 0 }]).verify().is_ok());
+        assert!(function(vec![Instr::Const { dst: 2, index: 0 }, Instr::Ret { src: 0 }]).verify().is_err());
+        assert!(function(vec![Instr::Const { dst: 0, index: 1 }, Instr::Ret { src: 0 }]).verify().is_err());
+        assert!(function(vec![Instr::Const { dst: 0, index: 0 }]).verify().is_err());
+        assert!(function(vec![Instr::Jump { target: 1 }]).verify().is_err());
+        assert!(function(vec![Instr::RetVoid]).verify().is_err());
+        assert!(function(vec![Instr::Call { dst: 0, func: 0, args: 0, argc: 1 }, Instr::Trap]).verify().is_err());
+
+        return Ok(());
+    }
+}
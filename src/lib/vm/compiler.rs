@@ -0,0 +1,752 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::checker::{literal_type, Scope};
+use crate::types::call_type::CallType;
+use crate::types::data_type::DataType;
+use crate::types::enum_type::EnumType;
+use crate::types::func_type::FuncType;
+use crate::types::if_type::IfType;
+use crate::types::lang_type::LangType;
+use crate::types::match_type::MatchType;
+use crate::types::op_type::OpType;
+use crate::types::op_type::Operation;
+use crate::types::primitive_type::PrimitiveType;
+use crate::types::primitive_type::Primitives;
+use crate::types::return_type::ReturnType;
+use crate::types::struct_type::StructType;
+use crate::vm::bytecode::{Function, Instr, Program, Reg, Ty, Value};
+
+/// Compiles the AST to bytecode with the typing rules of codegen. Every variable and
+/// temporary gets its own register, there is no allocator.
+pub struct Compiler {
+    program: Program,
+    functions: HashMap<String, (u32, FuncType)>,
+    constants: HashMap<String, PrimitiveType>, // globals plus the ones of the current function
+    globals: HashMap<String, PrimitiveType>,
+    enums: HashMap<String, EnumType>,
+    variables: HashMap<String, (Reg, DataType)>,
+    return_type: DataType,
+    registers: u16,
+    code: Vec<Instr>,
+}
+
+/// Only enums, the VM has no structs.
+impl Scope for Compiler {
+    fn variable(&self, name: &str) -> Option<DataType> {
+        self.variables.get(name).map(|(_, t)| t.clone())
+    }
+
+    fn constant(&self, name: &str) -> Option<&PrimitiveType> {
+        self.constants.get(name)
+    }
+
+    fn function(&self, name: &str) -> Option<&FuncType> {
+        self.functions.get(name).map(|(_, func)| func)
+    }
+
+    fn struct_type(&self, _: &str) -> Option<&StructType> {
+        None
+    }
+
+    fn enum_type(&self, name: &str) -> Option<&EnumType> {
+        self.enums.get(name)
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            program: Program::default(),
+            functions: HashMap::new(),
+            constants: HashMap::new(),
+            globals: HashMap::new(),
+            enums: HashMap::new(),
+            variables: HashMap::new(),
+            return_type: DataType::Void,
+            registers: 0,
+            code: vec![],
+        }
+    }
+
+    pub fn compile(mut self, ast: Vec<LangType>) -> Result<Program> {
+        // declare everything first so uses don't depend on declaration order
+        let mut bodies = vec![];
+        for lang_t in ast {
+            match lang_t {
+                LangType::Func(func) => {
+                    let index = bodies.len() as u32;
+                    if func.is_extern {
+                        // externs stay callable by name for the error message, but have no body
+                        self.functions.insert(func.name.clone(), (u32::MAX, func));
+                        continue;
+                    }
+                    self.functions.insert(func.name.clone(), (index, func.clone()));
+                    bodies.push(func);
+                }
+                LangType::Const(con) => {
+                    self.globals.insert(con.name, con.value);
+                }
+                LangType::Enum(enum_t) => {
+                    self.enums.insert(enum_t.name.clone(), enum_t);
+                }
+                LangType::Struct(_) | LangType::Comment(_) => {}
+                _ => return Err(anyhow!("Expression outside of function!")),
+            }
+        }
+
+        for func in bodies {
+            let function = self.compile_fn(func)?;
+            self.program.functions.push(function);
+        }
+        self.program.verify()?;
+
+        Ok(self.program)
+    }
+
+    fn compile_fn(&mut self, func: FuncType) -> Result<Function> {
+        // consts of other functions are out of scope
+        self.constants = self.globals.clone();
+        self.variables.clear();
+        self.registers = 0;
+        self.code = vec![];
+        self.return_type = func.ret.clone();
+
+        let mut params = vec![];
+        for param in &func.param {
+            let data_type = param.data_type.clone().unwrap_or_default();
+            params.push(ty(&data_type)?);
+            let reg = self.alloc()?;
+            self.variables.insert(param.name.clone(), (reg, data_type));
+        }
+        let ret = match &func.ret {
+            DataType::Void => None,
+            ret => Some(ty(ret)?),
+        };
+
+        self.compile_block(func.body)?;
+
+        // falling off the end returns zero, like the generated code
+        let jumps_to_end = self.code.iter().any(|i| jump_target(i) == Some(self.code.len()));
+        if jumps_to_end || !self.code.last().is_some_and(|i| i.is_terminator()) {
+            match &func.ret {
+                DataType::Void => {
+                    self.code.push(Instr::RetVoid);
+                }
+                ret => {
+                    let src = self.constant(zero(ret)?, None)?;
+                    self.code.push(Instr::Ret { src });
+                }
+            }
+        }
+
+        Ok(Function {
+            name: func.name,
+            params,
+            ret,
+            registers: self.registers,
+            code: std::mem::take(&mut self.code),
+        })
+    }
+
+    fn compile_block(&mut self, body: Vec<LangType>) -> Result<()> {
+        for stmt in body {
+            self.compile_stmt(stmt)?;
+        }
+
+        Ok(())
+    }
+
+    fn compile_stmt(&mut self, stmt: LangType) -> Result<()> {
+        match stmt {
+            LangType::Op(op) if matches!(op.op, Operation::Assign) => self.compile_assign(op),
+            LangType::Op(op) if matches!(op.op, Operation::Store) => Err(unsupported("store")),
+            LangType::Call(call) => self.compile_call(call, None).map(|_| ()),
+            LangType::If(if_t) => self.compile_if(if_t),
+            LangType::Match(match_t) => self.compile_match(match_t),
+            LangType::Return(ret) => self.compile_return(ret),
+            LangType::Asm(_) => Err(unsupported("inline asm")),
+            LangType::Const(con) => {
+                self.constants.insert(con.name, con.value);
+                Ok(())
+            }
+            LangType::Comment(_) | LangType::Location(_) => Ok(()),
+            other => Err(anyhow!("Unexpected statement {:?}", other)),
+        }
+    }
+
+    fn compile_assign(&mut self, op: OpType) -> Result<()> {
+        let (name, declared_type) = match *op.lhs {
+            LangType::Var(var) => (var.name, var.data_type),
+            other => return Err(anyhow!("Cannot assign to {:?}", other)),
+        };
+
+        if self.constants.contains_key(&name) {
+            return Err(anyhow!("Cannot assign to constant {}", name));
+        }
+
+        if let Some(data_type) = declared_type {
+            if self.variables.contains_key(&name) {
+                return Err(anyhow!("Variable {} is already declared", name));
+            }
+            let reg = match *op.rhs {
+                LangType::Undefined => self.constant(zero(&data_type)?, None)?,
+                rhs => {
+                    let reg = self.alloc()?;
+                    let (_, value_type) = self.compile_expr(rhs, Some(&data_type), Some(reg))?;
+                    if value_type != data_type {
+                        return Err(anyhow!("Cannot assign {} to {} of type {}", value_type, name, data_type));
+                    }
+                    reg
+                }
+            };
+            self.variables.insert(name, (reg, data_type));
+
+            return Ok(());
+        }
+
+        match self.variables.get(&name).cloned() {
+            Some((reg, data_type)) => {
+                let (_, value_type) = self.compile_expr(*op.rhs, Some(&data_type), Some(reg))?;
+                if value_type != data_type {
+                    return Err(anyhow!("Cannot assign {} to {} of type {}", value_type, name, data_type));
+                }
+            }
+            None => {
+                let reg = self.alloc()?;
+                let (_, data_type) = self.compile_expr(*op.rhs, None, Some(reg))?;
+                self.variables.insert(name, (reg, data_type));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn compile_if(&mut self, if_t: IfType) -> Result<()> {
+        let (cond, cond_type) = self.compile_expr(*if_t.condition, None, None)?;
+        if cond_type != DataType::Bool {
+            return Err(anyhow!("If condition must be bool, got {}", cond_type));
+        }
+
+        let to_else = self.emit(Instr::JumpIfNot { cond, target: 0 });
+        self.compile_block(if_t.body)?;
+        let to_end = self.emit_jump_unless_terminated();
+
+        self.patch(to_else);
+        self.compile_block(if_t.else_body)?;
+        if let Some(to_end) = to_end {
+            self.patch(to_end);
+        }
+
+        Ok(())
+    }
+
+    /// Compares against every pattern in order, then runs the arm's body.
+    fn compile_match(&mut self, match_t: MatchType) -> Result<()> {
+        let (value, value_type) = self.compile_expr(*match_t.value.clone(), None, None)?;
+        if !value_type.is_int() {
+            return Err(anyhow!("Cannot match on {}", value_type));
+        }
+        self.check_match(&match_t, &value_type)?;
+
+        let mut to_arms = vec![];
+        for arm in &match_t.arms {
+            let mut jumps = vec![];
+            for pattern in &arm.patterns {
+                let (case, _) = self.compile_expr(pattern.clone(), Some(&value_type), None)?;
+                let equal = self.alloc()?;
+                self.code.push(Instr::Eq { dst: equal, a: value, b: case });
+                jumps.push(self.emit(Instr::JumpIf { cond: equal, target: 0 }));
+            }
+            to_arms.push(jumps);
+        }
+        // without an else arm the arms cover the enum, other values come from casts and trap
+        let to_default = match match_t.default {
+            Some(_) => Some(self.emit(Instr::Jump { target: 0 })),
+            None => {
+                self.code.push(Instr::Trap);
+                None
+            }
+        };
+
+        let mut to_end = vec![];
+        for (arm, jumps) in match_t.arms.into_iter().zip(to_arms) {
+            for jump in jumps {
+                self.patch(jump);
+            }
+            self.compile_block(arm.body)?;
+            to_end.extend(self.emit_jump_unless_terminated());
+        }
+        if let (Some(body), Some(jump)) = (match_t.default, to_default) {
+            self.patch(jump);
+            self.compile_block(body)?;
+        }
+        for jump in to_end {
+            self.patch(jump);
+        }
+
+        Ok(())
+    }
+
+    fn compile_return(&mut self, ret: ReturnType) -> Result<()> {
+        let return_type = self.return_type.clone();
+
+        match (*ret.value, &return_type) {
+            (LangType::Undefined, DataType::Void) => {
+                self.code.push(Instr::RetVoid);
+            }
+            (LangType::Undefined, _) => return Err(anyhow!("Missing return value of type {}", return_type)),
+            (_, DataType::Void) => return Err(anyhow!("Cannot return a value from a void function")),
+            (value, _) => {
+                let (src, value_type) = self.compile_expr(value, Some(&return_type), None)?;
+                if value_type != return_type {
+                    return Err(anyhow!("Cannot return {} from a function returning {}", value_type, return_type));
+                }
+                self.code.push(Instr::Ret { src });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compiles a call into `dst`, returning `None` for void functions.
+    fn compile_call(&mut self, call: CallType, dst: Option<Reg>) -> Result<Option<(Reg, DataType)>> {
+        if !self.functions.contains_key(&call.name) {
+            match call.name.as_str() {
+                "print" => {
+                    let (args, argc) = self.compile_args(call.param, &[])?;
+                    self.code.push(Instr::Print { args, argc });
+                    return Ok(None);
+                }
+                "len" | "substr" => return self.compile_str_call(call, dst).map(Some),
+                "syscall" => return Err(unsupported("syscall")),
+                _ => {}
+            }
+        }
+
+        let (index, func) = self
+            .functions
+            .get(&call.name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown function {}", call.name))?;
+        if func.is_extern {
+            return Err(anyhow!("The VM cannot call extern function {}", func.name));
+        }
+        if call.param.len() != func.param.len() {
+            return Err(anyhow!(
+                "Function {} expects {} arguments, got {}",
+                call.name,
+                func.param.len(),
+                call.param.len()
+            ));
+        }
+
+        let expected: Vec<DataType> = func.param.iter().map(|p| p.data_type.clone().unwrap_or_default()).collect();
+        let (args, argc) = self.compile_args(call.param, &expected)?;
+        let dst = self.target(dst)?;
+        self.code.push(Instr::Call { dst, func: index, args, argc });
+
+        Ok(match func.ret {
+            DataType::Void => None,
+            ret => Some((dst, ret)),
+        })
+    }
+
+    /// Evaluates arguments into consecutive registers, checking them against `expected` types.
+    fn compile_args(&mut self, params: Vec<LangType>, expected: &[DataType]) -> Result<(Reg, u16)> {
+        let argc = u16::try_from(params.len()).map_err(|_| anyhow!("Too many arguments"))?;
+        let args = self.registers;
+        for _ in 0..argc {
+            self.alloc()?;
+        }
+
+        for (i, param) in params.into_iter().enumerate() {
+            let (_, data_type) = self.compile_expr(param, expected.get(i), Some(args + i as u16))?;
+            if let Some(expected) = expected.get(i) {
+                if data_type != *expected {
+                    return Err(anyhow!("Argument {} must be {}, got {}", i + 1, expected, data_type));
+                }
+            }
+        }
+
+        Ok((args, argc))
+    }
+
+    /// `len(s) -> u64` and `substr(s, start, count) -> str`.
+    fn compile_str_call(&mut self, call: CallType, dst: Option<Reg>) -> Result<(Reg, DataType)> {
+        let expected = if call.name == "len" { 1 } else { 3 };
+        if call.param.len() != expected {
+            return Err(anyhow!("{} expects {} arguments, got {}", call.name, expected, call.param.len()));
+        }
+
+        let mut params = call.param.into_iter();
+        let (src, s_type) = self.compile_expr(params.next().unwrap_or(LangType::Undefined), None, None)?;
+        if s_type != DataType::Str {
+            return Err(anyhow!("{} expects a str, got {}", call.name, s_type));
+        }
+
+        let mut bounds = vec![];
+        for param in params {
+            let (reg, data_type) = self.compile_expr(param, Some(&DataType::U64), None)?;
+            if !data_type.is_int() {
+                return Err(anyhow!("{} expects integer bounds, got {}", call.name, data_type));
+            }
+            bounds.push(reg);
+        }
+
+        let dst = self.target(dst)?;
+        if let [start, count] = bounds[..] {
+            self.code.push(Instr::Substr { dst, src, start, count });
+            return Ok((dst, DataType::Str));
+        }
+        self.code.push(Instr::Len { dst, src });
+
+        Ok((dst, DataType::U64))
+    }
+
+    /// Compiles `expr` into `dst`, or into any register when `dst` is `None`. Variables are
+    /// read in place then, so the result must not be written to.
+    fn compile_expr(&mut self, expr: LangType, hint: Option<&DataType>, dst: Option<Reg>) -> Result<(Reg, DataType)> {
+        match expr {
+            LangType::Primitive(p) => {
+                let value = literal(p, hint)?;
+                let data_type = value.ty().map(|t| t.data_type()).unwrap_or(DataType::Void);
+                Ok((self.constant(value, dst)?, data_type))
+            }
+            LangType::Var(var) => {
+                if let Some(con) = self.constants.get(&var.name).cloned() {
+                    return self.compile_expr(LangType::Primitive(con), hint, dst);
+                }
+
+                let (reg, data_type) = self
+                    .variables
+                    .get(&var.name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Unknown variable {}", var.name))?;
+                match dst {
+                    Some(dst) if dst != reg => {
+                        self.code.push(Instr::Move { dst, src: reg });
+                        Ok((dst, data_type))
+                    }
+                    _ => Ok((reg, data_type)),
+                }
+            }
+            LangType::Op(op) => self.compile_op(op, hint, dst),
+            LangType::Call(call) => {
+                let name = call.name.clone();
+                self.compile_call(call, dst)?
+                    .ok_or_else(|| anyhow!("Function {} does not return a value", name))
+            }
+            other => Err(anyhow!("Unexpected expression {:?}", other)),
+        }
+    }
+
+    fn compile_op(&mut self, op: OpType, hint: Option<&DataType>, dst: Option<Reg>) -> Result<(Reg, DataType)> {
+        match op.op {
+            Operation::Field => {
+                let value = self.variant(&op.lhs, &op.rhs)?;
+                let data_type = value.ty().map(|t| t.data_type()).unwrap_or(DataType::Void);
+                return Ok((self.constant(value, dst)?, data_type));
+            }
+            Operation::Addr | Operation::Load | Operation::Offset | Operation::Index => {
+                return Err(unsupported("pointers"))
+            }
+            Operation::Store => return Err(anyhow!("store does not produce a value")),
+            Operation::Neg => {
+                let value_hint = self.type_of(&op.lhs).or_else(|| hint.cloned());
+                let (src, data_type) = self.compile_expr(*op.lhs, value_hint.as_ref(), None)?;
+                if !data_type.is_signed() && !data_type.is_float() {
+                    return Err(anyhow!(
+                        "Cannot negate {}, only signed integers and floats can be negated",
+                        data_type
+                    ));
+                }
+                let dst = self.target(dst)?;
+                self.code.push(Instr::Neg { dst, src });
+                return Ok((dst, data_type));
+            }
+            Operation::IntToFloat | Operation::FloatToInt | Operation::Cast => return self.compile_cast(op, hint, dst),
+            Operation::Bitcast => {
+                let (src, from) = self.compile_expr(*op.lhs, None, None)?;
+                let to = match *op.rhs {
+                    LangType::Type(data_type) => data_type,
+                    other => return Err(anyhow!("Expected a type to cast to, got {:?}", other)),
+                };
+                if !from.can_bitcast_to(&to) {
+                    return Err(anyhow!("Cannot bitcast {} to {}", from, to));
+                }
+                let dst = self.target(dst)?;
+                self.code.push(Instr::Bitcast { dst, src, ty: ty(&to)? });
+                return Ok((dst, to));
+            }
+            _ => {}
+        }
+
+        let is_compare = matches!(
+            op.op,
+            Operation::Equal | Operation::NotEqual | Operation::LessThan | Operation::GreaterThan
+        );
+
+        // literals take the width of the other operand
+        let operand_hint = self
+            .type_of(&op.lhs)
+            .or_else(|| self.type_of(&op.rhs))
+            .or_else(|| if is_compare { None } else { hint.cloned() });
+
+        let (a, lhs_type) = self.compile_expr(*op.lhs, operand_hint.as_ref(), None)?;
+        let (b, rhs_type) = self.compile_expr(*op.rhs, Some(&lhs_type), None)?;
+        if lhs_type != rhs_type {
+            return Err(anyhow!("Mismatched operand types {} and {}", lhs_type, rhs_type));
+        }
+
+        let supported = match lhs_type {
+            DataType::Str => is_compare || matches!(op.op, Operation::Add),
+            DataType::Bool => matches!(op.op, Operation::Equal | Operation::NotEqual),
+            ref t => t.is_int() || t.is_float(),
+        };
+        if !supported {
+            return Err(anyhow!("Operation {:?} is not supported for {}", op.op, lhs_type));
+        }
+
+        let dst = self.target(dst)?;
+        self.code.push(match op.op {
+            Operation::Add => Instr::Add { dst, a, b },
+            Operation::Sub => Instr::Sub { dst, a, b },
+            Operation::Mul => Instr::Mul { dst, a, b },
+            Operation::Div => Instr::Div { dst, a, b },
+            Operation::Mod => Instr::Mod { dst, a, b },
+            Operation::Equal => Instr::Eq { dst, a, b },
+            Operation::NotEqual => Instr::Ne { dst, a, b },
+            Operation::LessThan => Instr::Lt { dst, a, b },
+            Operation::GreaterThan => Instr::Gt { dst, a, b },
+            other => return Err(anyhow!("Unexpected operation {:?}", other)),
+        });
+
+        Ok((dst, if is_compare { DataType::Bool } else { lhs_type }))
+    }
+
+    /// `cast`, `itof` and `ftoi`, the conversions of the interpreter.
+    fn compile_cast(&mut self, op: OpType, hint: Option<&DataType>, dst: Option<Reg>) -> Result<(Reg, DataType)> {
+        let (src, from) = self.compile_expr(*op.lhs, None, None)?;
+        let to = match op.op {
+            Operation::IntToFloat if !from.is_int() => return Err(anyhow!("itof expects an integer, got {}", from)),
+            Operation::IntToFloat => hint.filter(|t| t.is_float()).cloned().unwrap_or(DataType::F64),
+            Operation::FloatToInt if !from.is_float() => return Err(anyhow!("ftoi expects a float, got {}", from)),
+            Operation::FloatToInt => hint.filter(|t| t.is_int()).cloned().unwrap_or(DataType::I64),
+            _ => match *op.rhs {
+                LangType::Type(data_type) => data_type,
+                other => return Err(anyhow!("Expected a type to cast to, got {:?}", other)),
+            },
+        };
+
+        if !from.can_cast_to(&to) {
+            return Err(anyhow!("Cannot cast {} to {}", from, to));
+        }
+        if from != to && (from.is_ptr() || to.is_ptr() || from == DataType::Str || to == DataType::Str) {
+            return Err(unsupported("pointers"));
+        }
+
+        let dst = self.target(dst)?;
+        self.code.push(Instr::Cast { dst, src, ty: ty(&to)? });
+
+        Ok((dst, to))
+    }
+
+    /// `Enum.Variant`, the only kind of field access without pointers.
+    fn variant(&self, base: &LangType, field: &LangType) -> Result<Value> {
+        let (enum_t, field) = match (base, field) {
+            (LangType::Var(var), LangType::Var(field)) if !self.variables.contains_key(&var.name) => {
+                match self.enums.get(&var.name) {
+                    Some(enum_t) => (enum_t, field),
+                    None => return Err(unsupported("struct fields")),
+                }
+            }
+            _ => return Err(unsupported("struct fields")),
+        };
+
+        let variant = enum_t
+            .variant(&field.name)
+            .ok_or_else(|| anyhow!("Enum {} has no variant {}", enum_t.name, field.name))?;
+        Ok(Value::int(variant.value as i128, ty(&enum_t.data_type)?))
+    }
+
+    /// Loads `value` from the constant pool into `dst` or a new register.
+    fn constant(&mut self, value: Value, dst: Option<Reg>) -> Result<Reg> {
+        let index = match self.program.constants.iter().position(|c| *c == value) {
+            Some(index) => index,
+            None => {
+                self.program.constants.push(value);
+                self.program.constants.len() - 1
+            }
+        };
+        let index = u32::try_from(index).map_err(|_| anyhow!("Too many constants"))?;
+
+        let dst = self.target(dst)?;
+        self.code.push(Instr::Const { dst, index });
+        Ok(dst)
+    }
+
+    fn target(&mut self, dst: Option<Reg>) -> Result<Reg> {
+        match dst {
+            Some(dst) => Ok(dst),
+            None => self.alloc(),
+        }
+    }
+
+    fn alloc(&mut self) -> Result<Reg> {
+        let reg = self.registers;
+        self.registers = reg
+            .checked_add(1)
+            .filter(|n| *n < u16::MAX)
+            .ok_or_else(|| anyhow!("Function needs more than {} registers", u16::MAX - 1))?;
+        Ok(reg)
+    }
+
+    fn emit(&mut self, instr: Instr) -> usize {
+        self.code.push(instr);
+        self.code.len() - 1
+    }
+
+    /// Jump over what follows unless the block just compiled already left.
+    fn emit_jump_unless_terminated(&mut self) -> Option<usize> {
+        match self.code.last() {
+            Some(instr) if instr.is_terminator() => None,
+            _ => Some(self.emit(Instr::Jump { target: 0 })),
+        }
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let next = self.code.len() as u32;
+        match &mut self.code[at] {
+            Instr::Jump { target } | Instr::JumpIf { target, .. } | Instr::JumpIfNot { target, .. } => *target = next,
+            _ => {}
+        }
+    }
+}
+
+/// Compiles a parsed program.
+pub fn compile(ast: Vec<LangType>) -> Result<Program> {
+    Compiler::new().compile(ast)
+}
+
+fn ty(data_type: &DataType) -> Result<Ty> {
+    Ty::from_data_type(data_type).ok_or_else(|| anyhow!("The VM does not support values of type {}", data_type))
+}
+
+fn jump_target(instr: &Instr) -> Option<usize> {
+    match instr {
+        Instr::Jump { target } | Instr::JumpIf { target, .. } | Instr::JumpIfNot { target, .. } => Some(*target as usize),
+        _ => None,
+    }
+}
+
+fn zero(data_type: &DataType) -> Result<Value> {
+    Ok(match ty(data_type)? {
+        Ty::Bool => Value::Bool(false),
+        Ty::Str => Value::Str(vec![]),
+        t if data_type.is_float() => Value::float(0.0, t),
+        t => Value::int(0, t),
+    })
+}
+
+fn literal(p: PrimitiveType, hint: Option<&DataType>) -> Result<Value> {
+    Ok(match p.primitive {
+        Primitives::Int => {
+            let value = p
+                .to_i128()
+                .ok_or_else(|| anyhow!("Integer literal {} does not fit in 64 bits", p.value))?;
+            let data_type = literal_type(&p, hint).unwrap_or(DataType::I64);
+            if !data_type.fits_literal(value, p.radix) {
+                return Err(anyhow!("Integer literal {} does not fit in {}", value, data_type));
+            }
+
+            Value::int(value, ty(&data_type)?)
+        }
+        Primitives::Float => {
+            let data_type = literal_type(&p, hint).unwrap_or(DataType::F64);
+            let value: f64 = p.value.parse().map_err(|_| anyhow!("Invalid float literal {}", p.value))?;
+            let finite = if data_type == DataType::F32 { (value as f32).is_finite() } else { value.is_finite() };
+            if !finite {
+                return Err(anyhow!("Float literal {} does not fit in {}", p.value, data_type));
+            }
+
+            Value::float(value, ty(&data_type)?)
+        }
+        Primitives::Bool => Value::Bool(p.value == "true"),
+        Primitives::String => Value::Str(p.value.into_bytes()),
+    })
+}
+
+fn unsupported(what: &str) -> anyhow::Error {
+    anyhow!("The VM does not support {}", what)
+}
+
+// ------------------------------------
+// Tests
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use super::compile;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::vm::bytecode::{Instr, Program};
+
+    fn program(input: &str) -> Result<Program> {
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        return compile(ast);
+    }
+
+    #[test]
+    fn compile_branches() -> Result<()> {
+        let input = r#"fn sign n:i32 -> i32:
+    if n < 0:
+        return -1
+    else:
+        if n > 0:
+            return 1
+        end
+    end
+end"#;
+        let program = program(input)?;
+        let code = &program.functions[0].code;
+
+        // the inner if falls through to the implicit return 0
+        assert!(matches!(code[1], Instr::Lt { .. }));
+        assert!(matches!(code[2], Instr::JumpIfNot { target: 5, .. }));
+        assert!(matches!(code.last(), Some(Instr::Ret { .. })));
+        assert_eq!(program.constants.len(), 3);
+
+        return Ok(());
+    }
+
+    #[test]
+    fn compile_errors() -> Result<()> {
+        assert!(program("fn main:\n    let a 1\n    add a a true\nend").is_err());
+        assert!(program("fn main:\n    let a 1\n    let p addr a\nend").is_err());
+        assert!(program("fn main:\n    let a 1\n    let b:u8\n    add b b a\nend").is_err());
+        assert!(program("extern fn puts str -> i32\nfn main:\n    puts(\"hi\")\nend").is_err());
+        assert!(program("fn main:\n    nothing()\nend").is_err());
+        assert!(program("fn main:\n    let a 1\n    match a:\n    1:\n        print(a)\n    end\nend").is_err());
+
+        // consts of a function are not visible in the ones compiled after it
+        let main = "fn main -> i32:\n    const N 7\n    return N\nend";
+        let helper = "fn helper -> i32:\n    return N\nend";
+        for input in [format!("{}\n{}", main, helper), format!("{}\n{}", helper, main)] {
+            assert_eq!(program(&input).unwrap_err().to_string(), "Unknown variable N");
+        }
+
+        return Ok(());
+    }
+}
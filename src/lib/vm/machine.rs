@@ -0,0 +1,560 @@
+use std::cmp::Ordering;
+use std::io::Write;
+use std::mem::size_of;
+
+use anyhow::{anyhow, Result};
+
+use crate::types::primitive_type::format_g;
+use crate::vm::bytecode::{Instr, Program, Reg, Ty, Value};
+
+/// Bounds for running untrusted bytecode. Exceeding one stops the program with an error.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+    pub instructions: Option<u64>,
+    pub call_depth: usize,
+    pub memory: usize, // bytes of registers and strings held by all frames at once
+    pub string_bytes: Option<usize>, // longest string an instruction may produce
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            instructions: None,
+            call_depth: 10_000,
+            memory: 256 << 20,
+            string_bytes: Some(16 << 20),
+        }
+    }
+}
+
+struct Frame {
+    func: usize,
+    pc: usize,
+    base: usize, // first register of the frame in `regs`
+    dst: Reg, // caller register receiving the return value
+}
+
+/// Register machine for a verified `Program`. Calls get a window of `regs` instead of host
+/// stack, so deep recursion is bounded by `Limits::call_depth` and `Limits::memory`.
+pub struct Vm<'p, W: Write> {
+    program: &'p Program,
+    limits: Limits,
+    regs: Vec<Value>,
+    frames: Vec<Frame>,
+    memory: usize, // bytes of `regs` and the strings in them
+    executed: u64,
+    out: W,
+}
+
+impl<'p, W: Write> Vm<'p, W> {
+    pub fn new(program: &'p Program, out: W) -> Result<Self> {
+        program.verify()?;
+
+        Ok(Self {
+            program,
+            limits: Limits::default(),
+            regs: vec![],
+            frames: vec![],
+            memory: 0,
+            executed: 0,
+            out,
+        })
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Instructions executed so far, across all calls.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    pub fn into_output(self) -> W {
+        self.out
+    }
+
+    /// Runs `main` and returns its exit code, 0 for a void `main`.
+    pub fn run_main(&mut self) -> Result<i64> {
+        match self.call("main", vec![])? {
+            Value::Void => Ok(0),
+            Value::Int(bits, ty) => Ok(Value::Int(bits, ty).to_i128().unwrap_or_default() as i64),
+            value => Err(anyhow!(
+                "main must return an integer or void, not {}",
+                value.ty().map(|t| t.to_string()).unwrap_or_default()
+            )),
+        }
+    }
+
+    /// Calls the function `name` with `args`, returning `Value::Void` for void functions.
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value> {
+        let program = self.program;
+        let index = program.function(name).ok_or_else(|| anyhow!("Unknown function {}", name))?;
+        let func = &program.functions[index];
+        if args.len() != func.params.len() {
+            return Err(anyhow!("Function {} expects {} arguments, got {}", name, func.params.len(), args.len()));
+        }
+        for (i, (arg, ty)) in args.iter().zip(&func.params).enumerate() {
+            if arg.ty() != Some(*ty) {
+                return Err(anyhow!("Argument {} of {} must be {}", i + 1, name, ty));
+            }
+        }
+
+        self.reset();
+        self.enter(index, 0)?;
+        for (i, arg) in args.into_iter().enumerate() {
+            self.set(i as Reg, arg)?;
+        }
+
+        let result = self.execute();
+        self.reset();
+        result
+    }
+
+    fn execute(&mut self) -> Result<Value> {
+        let program = self.program;
+
+        loop {
+            let frame = self.frames.last_mut().ok_or_else(|| anyhow!("No function is running"))?;
+            let func = &program.functions[frame.func];
+            // verified code ends in a terminator and only jumps in range, pc never runs off
+            let instr = func.code[frame.pc];
+            frame.pc += 1;
+
+            if let Some(max) = self.limits.instructions {
+                if self.executed >= max {
+                    return Err(anyhow!("Instruction limit of {} exceeded in {}", max, func.name));
+                }
+            }
+            self.executed += 1;
+
+            match instr {
+                Instr::Const { dst, index } => {
+                    let value = program.constants[index as usize].clone();
+                    self.set(dst, value)?;
+                }
+                Instr::Move { dst, src } => {
+                    let value = self.get(src)?.clone();
+                    self.set(dst, value)?;
+                }
+                Instr::Add { dst, a, b }
+                | Instr::Sub { dst, a, b }
+                | Instr::Mul { dst, a, b }
+                | Instr::Div { dst, a, b }
+                | Instr::Mod { dst, a, b } => {
+                    let value = arith(&instr, self.get(a)?, self.get(b)?)?;
+                    self.check_string(&value)?;
+                    self.set(dst, value)?;
+                }
+                Instr::Neg { dst, src } => {
+                    let value = match self.get(src)? {
+                        Value::Float(value, ty) => Value::float(-value, *ty),
+                        value @ Value::Int(_, ty) if ty.data_type().is_signed() => {
+                            Value::int(value.to_i128().unwrap_or_default().wrapping_neg(), *ty)
+                        }
+                        _ => return Err(mismatch(&instr)),
+                    };
+                    self.set(dst, value)?;
+                }
+                Instr::Eq { dst, a, b } | Instr::Ne { dst, a, b } | Instr::Lt { dst, a, b } | Instr::Gt { dst, a, b } => {
+                    // NaN is unordered, only ne holds
+                    let order = compare(&instr, self.get(a)?, self.get(b)?)?;
+                    let value = match instr {
+                        Instr::Eq { .. } => order == Some(Ordering::Equal),
+                        Instr::Ne { .. } => order != Some(Ordering::Equal),
+                        Instr::Lt { .. } => order == Some(Ordering::Less),
+                        _ => order == Some(Ordering::Greater),
+                    };
+                    self.set(dst, Value::Bool(value))?;
+                }
+                Instr::Cast { dst, src, ty } => {
+                    let value = cast(self.get(src)?, ty).ok_or_else(|| mismatch(&instr))?;
+                    self.set(dst, value)?;
+                }
+                Instr::Bitcast { dst, src, ty } => {
+                    let value = bitcast(self.get(src)?, ty).ok_or_else(|| mismatch(&instr))?;
+                    self.set(dst, value)?;
+                }
+                Instr::Jump { target } => self.jump(target),
+                Instr::JumpIf { cond, target } | Instr::JumpIfNot { cond, target } => {
+                    let jump_on = matches!(instr, Instr::JumpIf { .. });
+                    match self.get(cond)? {
+                        Value::Bool(value) if *value == jump_on => self.jump(target),
+                        Value::Bool(_) => {}
+                        _ => return Err(mismatch(&instr)),
+                    }
+                }
+                Instr::Call { dst, func: index, args, argc } => {
+                    let callee = &program.functions[index as usize];
+                    let mut values = vec![];
+                    for (i, ty) in (0..argc).zip(&callee.params) {
+                        let value = self.get(args + i)?;
+                        if value.ty() != Some(*ty) {
+                            return Err(anyhow!("Argument {} of {} must be {}", i + 1, callee.name, ty));
+                        }
+                        values.push(value.clone());
+                    }
+
+                    self.enter(index as usize, dst)?;
+                    for (i, value) in values.into_iter().enumerate() {
+                        self.set(i as Reg, value)?;
+                    }
+                }
+                Instr::Ret { src } => {
+                    let value = self.get(src)?.clone();
+                    if value.ty() != func.ret {
+                        return Err(anyhow!("{} returned a value of the wrong type", func.name));
+                    }
+                    if let Some(value) = self.leave(Some(value))? {
+                        return Ok(value);
+                    }
+                }
+                Instr::RetVoid => {
+                    if let Some(value) = self.leave(None)? {
+                        return Ok(value);
+                    }
+                }
+                Instr::Print { args, argc } => {
+                    let mut line: Vec<u8> = vec![];
+                    for i in 0..argc {
+                        if !line.is_empty() {
+                            line.push(b' ');
+                        }
+                        match self.get(args + i)? {
+                            Value::Str(s) => line.extend_from_slice(s),
+                            Value::Float(value, _) => line.extend_from_slice(format_g(*value).as_bytes()),
+                            value => {
+                                let value = value.to_i128().unwrap_or_default();
+                                line.extend_from_slice(value.to_string().as_bytes());
+                            }
+                        }
+                    }
+                    line.push(b'\n');
+
+                    self.out.write_all(&line)?;
+                }
+                Instr::Len { dst, src } => {
+                    let len = match self.get(src)? {
+                        Value::Str(s) => s.len(),
+                        _ => return Err(mismatch(&instr)),
+                    };
+                    self.set(dst, Value::int(len as i128, Ty::U64))?;
+                }
+                Instr::Substr { dst, src, start, count } => {
+                    // the runtime takes the bounds as u64, negative ones become huge
+                    let start = self.get(start)?.to_i128().ok_or_else(|| mismatch(&instr))? as u64;
+                    let count = self.get(count)?.to_i128().ok_or_else(|| mismatch(&instr))? as u64;
+                    let value = match self.get(src)? {
+                        Value::Str(s) => {
+                            let start = (start as usize).min(s.len());
+                            let count = (count as usize).min(s.len() - start);
+                            Value::Str(s[start..start + count].to_vec())
+                        }
+                        _ => return Err(mismatch(&instr)),
+                    };
+                    self.set(dst, value)?;
+                }
+                Instr::Trap => return Err(anyhow!("No match arm in {}", func.name)),
+            }
+        }
+    }
+
+    /// Pushes a frame for `func` with fresh registers.
+    fn enter(&mut self, func: usize, dst: Reg) -> Result<()> {
+        if self.frames.len() >= self.limits.call_depth {
+            let name = &self.program.functions[func].name;
+            return Err(anyhow!("Call depth exceeded {} calling {}", self.limits.call_depth, name));
+        }
+
+        let base = self.regs.len();
+        let registers = self.program.functions[func].registers as usize;
+        self.reserve(registers * size_of::<Value>(), 0)?;
+        self.regs.resize(base + registers, Value::Void);
+        self.frames.push(Frame { func, pc: 0, base, dst });
+
+        Ok(())
+    }
+
+    /// Pops the running frame, returning the result once the outermost call returns.
+    fn leave(&mut self, value: Option<Value>) -> Result<Option<Value>> {
+        let Some(frame) = self.frames.pop() else {
+            return Ok(None);
+        };
+        let freed: usize = self.regs[frame.base..].iter().map(|v| string_bytes(v) + size_of::<Value>()).sum();
+        self.regs.truncate(frame.base);
+        self.memory -= freed;
+
+        if self.frames.is_empty() {
+            return Ok(Some(value.unwrap_or(Value::Void)));
+        }
+        if let Some(value) = value {
+            self.set(frame.dst, value)?;
+        }
+        Ok(None)
+    }
+
+    fn reset(&mut self) {
+        self.regs.clear();
+        self.frames.clear();
+        self.memory = 0;
+    }
+
+    /// Accounts for `add` more bytes in use and `free` fewer, failing past `Limits::memory`.
+    fn reserve(&mut self, add: usize, free: usize) -> Result<()> {
+        let memory = self.memory - free + add;
+        if memory > self.limits.memory {
+            return Err(anyhow!("Memory limit of {} bytes exceeded", self.limits.memory));
+        }
+        self.memory = memory;
+        Ok(())
+    }
+
+    fn jump(&mut self, target: u32) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.pc = target as usize;
+        }
+    }
+
+    fn base(&self) -> usize {
+        self.frames.last().map(|f| f.base).unwrap_or_default()
+    }
+
+    fn get(&self, reg: Reg) -> Result<&Value> {
+        match &self.regs[self.base() + reg as usize] {
+            Value::Void => Err(anyhow!("Read of uninitialised register r{}", reg)),
+            value => Ok(value),
+        }
+    }
+
+    fn set(&mut self, reg: Reg, value: Value) -> Result<()> {
+        let index = self.base() + reg as usize;
+        self.reserve(string_bytes(&value), string_bytes(&self.regs[index]))?;
+        self.regs[index] = value;
+        Ok(())
+    }
+
+    fn check_string(&self, value: &Value) -> Result<()> {
+        match (value, self.limits.string_bytes) {
+            (Value::Str(s), Some(max)) if s.len() > max => Err(anyhow!("String limit of {} bytes exceeded", max)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Runs `main` of a verified program with `limits`, writing printed lines to `out`.
+pub fn run<W: Write>(program: &Program, out: W, limits: Limits) -> Result<i64> {
+    Vm::new(program, out)?.with_limits(limits).run_main()
+}
+
+fn string_bytes(value: &Value) -> usize {
+    match value {
+        Value::Str(s) => s.len(),
+        _ => 0,
+    }
+}
+
+fn arith(instr: &Instr, a: &Value, b: &Value) -> Result<Value> {
+    Ok(match (a, b) {
+        (Value::Str(a), Value::Str(b)) if matches!(instr, Instr::Add { .. }) => Value::Str([a.as_slice(), b.as_slice()].concat()),
+        (Value::Float(x, ty), Value::Float(y, other)) if ty == other => {
+            let value = match instr {
+                Instr::Add { .. } => x + y,
+                Instr::Sub { .. } => x - y,
+                Instr::Mul { .. } => x * y,
+                Instr::Div { .. } => x / y,
+                _ => x % y,
+            };
+            Value::float(value, *ty)
+        }
+        (Value::Int(_, ty), Value::Int(_, other)) if ty == other => {
+            let x = a.to_i128().unwrap_or_default();
+            let y = b.to_i128().unwrap_or_default();
+            if matches!(instr, Instr::Div { .. } | Instr::Mod { .. }) && y == 0 {
+                return Err(anyhow!("Division by zero"));
+            }
+            let value = match instr {
+                Instr::Add { .. } => x.wrapping_add(y),
+                Instr::Sub { .. } => x.wrapping_sub(y),
+                Instr::Mul { .. } => x.wrapping_mul(y),
+                Instr::Div { .. } => x / y,
+                _ => x % y,
+            };
+            Value::int(value, *ty)
+        }
+        _ => return Err(mismatch(instr)),
+    })
+}
+
+/// `None` when either operand is NaN.
+fn compare(instr: &Instr, a: &Value, b: &Value) -> Result<Option<Ordering>> {
+    Ok(match (a, b) {
+        (Value::Str(x), Value::Str(y)) => Some(x.cmp(y)),
+        (Value::Float(x, ty), Value::Float(y, other)) if ty == other => x.partial_cmp(y),
+        (Value::Bool(x), Value::Bool(y)) if matches!(instr, Instr::Eq { .. } | Instr::Ne { .. }) => Some(x.cmp(y)),
+        (Value::Int(_, ty), Value::Int(_, other)) if ty == other => a.to_i128().cmp(&b.to_i128()).into(),
+        _ => return Err(mismatch(instr)),
+    })
+}
+
+/// The conversions of the interpreter's `cast`, `None` for the invalid ones.
+fn cast(value: &Value, to: Ty) -> Option<Value> {
+    let to_type = to.data_type();
+
+    Some(match value {
+        _ if value.ty() == Some(to) => value.clone(),
+        Value::Int(..) | Value::Bool(_) if to_type.is_int() => Value::int(value.to_i128()?, to),
+        Value::Int(..) if to_type.is_float() => Value::float(value.to_i128()? as f64, to),
+        // out of range is poison in LLVM, here it saturates
+        Value::Float(v, _) if to_type.is_int() => Value::int(*v as i128, to),
+        Value::Float(v, _) if to_type.is_float() => Value::float(*v, to),
+        _ => return None,
+    })
+}
+
+/// `value` with its bits reinterpreted as `to`, like LLVM's bitcast.
+fn bitcast(value: &Value, to: Ty) -> Option<Value> {
+    let from = value.ty()?;
+    if !from.data_type().can_bitcast_to(&to.data_type()) {
+        return None;
+    }
+    let bits = match value {
+        Value::Int(bits, _) => *bits,
+        Value::Float(v, Ty::F32) => (*v as f32).to_bits() as u64,
+        Value::Float(v, _) => v.to_bits(),
+        _ => return None,
+    };
+
+    Some(match to {
+        Ty::F32 => Value::float(f32::from_bits(bits as u32) as f64, to),
+        Ty::F64 => Value::float(f64::from_bits(bits), to),
+        _ => Value::int(bits as i128, to),
+    })
+}
+
+fn mismatch(instr: &Instr) -> anyhow::Error {
+    anyhow!("Operand types do not match {}", instr)
+}
+
+// ------------------------------------
+// Tests
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use super::{Limits, Vm};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::vm::bytecode::{Program, Ty, Value};
+    use crate::vm::compiler::compile;
+
+    fn program(input: &str) -> Result<Program> {
+        let lex = Lexer::new(input.into()).collect()?;
+        let ast = Parser::new(lex).parse_file()?;
+
+        return compile(ast);
+    }
+
+    fn execute(input: &str, limits: Limits) -> Result<(i64, String)> {
+        let program = program(input)?;
+        let mut out = vec![];
+        let code = Vm::new(&program, &mut out)?.with_limits(limits).run_main()?;
+
+        return Ok((code, String::from_utf8(out)?));
+    }
+
+    // the programs both backends run are compared in tests/backends.rs, these test the VM itself
+    const SUM: &str = r#"fn sum n:i64 -> i64:
+    if n < 1:
+        return 0
+    end
+    sub m n 1
+    let s sum(m)
+    add s s n
+    return s
+end
+fn main -> i32:
+    let s sum(100)
+    print("sum", s)
+    return 3
+end"#;
+
+    #[test]
+    fn vm_calls() -> Result<()> {
+        assert_eq!(execute(SUM, Limits::default())?, (3, "sum 5050\n".to_string()));
+
+        let program = program(SUM)?;
+        let mut vm = Vm::new(&program, vec![])?;
+        assert_eq!(vm.call("sum", vec![Value::int(10, Ty::I64)])?, Value::int(55, Ty::I64));
+        assert!(vm.call("sum", vec![Value::int(10, Ty::I32)]).is_err());
+        assert!(vm.call("sum", vec![]).is_err());
+        assert!(vm.call("missing", vec![]).is_err());
+
+        // loaded bytecode runs like the compiled program
+        let loaded = Program::from_bytes(&program.to_bytes())?;
+        let mut out = vec![];
+        assert_eq!(Vm::new(&loaded, &mut out)?.run_main()?, 3);
+        assert_eq!(String::from_utf8(out)?, "sum 5050\n");
+
+        return Ok(());
+    }
+
+    #[test]
+    fn vm_limits() -> Result<()> {
+        let program = program(SUM)?;
+        let mut vm = Vm::new(&program, vec![])?;
+        vm.run_main()?;
+        let executed = vm.executed();
+
+        let limits = |n| Limits {
+            instructions: Some(n),
+            ..Default::default()
+        };
+        assert_eq!(execute(SUM, limits(executed))?.0, 3);
+        let err = execute(SUM, limits(executed - 1)).unwrap_err();
+        assert!(err.to_string().contains(&format!("Instruction limit of {}", executed - 1)));
+
+        let deep = "fn deep n:i32:\n    deep(n)\nend\nfn main:\n    deep(1)\nend";
+        let limits = Limits {
+            call_depth: 100_000,
+            ..Default::default()
+        };
+        assert!(execute(deep, limits).unwrap_err().to_string().contains("Call depth exceeded 100000"));
+
+        let doubling = "fn grow s:str -> str:\n    add s s s\n    return grow(s)\nend\nfn main:\n    grow(\"ab\")\nend";
+        let limits = Limits {
+            string_bytes: Some(1024),
+            ..Default::default()
+        };
+        assert!(execute(doubling, limits).unwrap_err().to_string().contains("String limit"));
+
+        // the default limits stop both without any flags
+        assert!(execute(doubling, Limits::default()).unwrap_err().to_string().contains("String limit"));
+        let limits = Limits {
+            call_depth: usize::MAX,
+            memory: 64 << 10,
+            ..Default::default()
+        };
+        assert!(execute(deep, limits).unwrap_err().to_string().contains("Memory limit of 65536 bytes"));
+        let limits = Limits {
+            memory: 4096,
+            string_bytes: None,
+            ..Default::default()
+        };
+        assert!(execute(doubling, limits).unwrap_err().to_string().contains("Memory limit"));
+        assert_eq!(execute(SUM, Limits { memory: 32 << 10, ..Default::default() })?.0, 3);
+
+        return Ok(());
+    }
+
+    #[test]
+    fn vm_errors() -> Result<()> {
+        assert!(execute("fn main:\n    let a 1\n    div a a 0\nend", Limits::default()).is_err());
+        // a value outside the enum, here a plain u8, traps instead of falling through
+        let outside = "enum Bit u8:\n    Off\n    On\nend\nfn main:\n    let b:u8 7\n    match b:\n    Bit.Off, Bit.On:\n        print(b)\n    end\nend";
+        assert!(execute(outside, Limits::default()).unwrap_err().to_string().contains("No match arm"));
+
+        return Ok(());
+    }
+}
@@ -0,0 +1,151 @@
+#![cfg(all(feature = "llvm", feature = "interp"))]
+
+use std::fs;
+use std::process::Command;
+
+use anyhow::Result;
+
+/// Runs `input` with `modern_asm run` and the extra `flags`, returning the exit code, stdout and stderr.
+fn run(name: &str, input: &str, flags: &[&str]) -> Result<(Option<i32>, String, String)> {
+    let path = std::env::temp_dir().join(format!("{}-{}.masm", name, std::process::id()));
+    fs::write(&path, input)?;
+    let output = Command::new(env!("CARGO_BIN_EXE_modern_asm")).arg("run").arg(&path).args(flags).output();
+    fs::remove_file(&path)?;
+    let output = output?;
+
+    return Ok((output.status.code(), String::from_utf8(output.stdout)?, String::from_utf8(output.stderr)?));
+}
+
+/// Runs `input` compiled, interpreted and on the VM, all of them have to exit and print the same.
+fn compare(name: &str, input: &str) -> Result<(Option<i32>, String, String)> {
+    let compiled = run(name, input, &[])?;
+    let interpreted = run(name, input, &["--interp"])?;
+    assert_eq!(compiled, interpreted, "{} differs between codegen and the interpreter", name);
+    #[cfg(feature = "vm")]
+    {
+        let vm = run(name, input, &["--vm"])?;
+        assert_eq!(compiled, vm, "{} differs between codegen and the VM", name);
+    }
+
+    return Ok(compiled);
+}
+
+#[test]
+fn backends_calls() -> Result<()> {
+    let input = r#"fn fib n:i32 -> i32:
+    if n < 2:
+        return n
+    end
+    sub a n 1
+    sub b n 2
+    let fa fib(a)
+    let fb fib(b)
+    add r fa fb
+    return r
+end
+fn main -> i32:
+    let x fib(10)
+    print("fib", x)
+    return 3
+end"#;
+
+    assert_eq!(compare("backends_calls", input)?, (Some(3), "fib 55\n".to_string(), String::new()));
+
+    return Ok(());
+}
+
+#[test]
+fn backends_types() -> Result<()> {
+    let input = r#"enum Color u8:
+    Red
+    Green = 200
+end
+fn main:
+    let small:u8
+    let green Color.Green
+    add small green 100
+    let big -1
+    cast wide big as u64
+    div q -7 2
+    let f 1.5
+    mul f f 3.0
+    ftoi n f
+    let s "ab"
+    add s s "c"
+    let l len(s)
+    let t substr(s, 1, 5)
+    let k:i64 5
+    let one 1.0
+    bitcast bits one as u64
+    match small:
+    44:
+        print(small, wide, q, f, n, s, l, t, k, bits)
+    else:
+        print("wrapping broke")
+    end
+end"#;
+
+    let expected = "44 18446744073709551615 -3 4.5 4 abc 3 bc 5 4607182418800017408\n";
+    assert_eq!(compare("backends_types", input)?, (Some(0), expected.to_string(), String::new()));
+
+    return Ok(());
+}
+
+#[test]
+fn backends_exit_codes() -> Result<()> {
+    // both truncate the result of main to the exit status of the platform
+    assert_eq!(compare("backends_exit_wide", "fn main -> i32:\n    return 300\nend")?.0, Some(44));
+    assert_eq!(compare("backends_exit_negative", "fn main -> i64:\n    return -1\nend")?.0, Some(255));
+
+    return Ok(());
+}
+
+#[test]
+fn backends_local_consts() -> Result<()> {
+    // a const of main is not visible in the functions main calls
+    let input = r#"fn main -> i32:
+    const N 7
+    let n helper()
+    return n
+end
+fn helper -> i32:
+    return N
+end"#;
+
+    let error = (Some(1), String::new(), "error: Unknown variable N\n".to_string());
+    assert_eq!(compare("backends_local_consts", input)?, error);
+
+    return Ok(());
+}
+
+#[test]
+fn backends_match_errors() -> Result<()> {
+    // rejected before anything runs, even when no value would miss the arms
+    let no_else = r#"fn main:
+    print("start")
+    let a 1
+    match a:
+    1:
+        print(a)
+    end
+end"#;
+    let error = (Some(1), String::new(), "error: Match on i32 needs an else arm\n".to_string());
+    assert_eq!(compare("backends_match_no_else", no_else)?, error);
+
+    let duplicate = r#"fn main:
+    print("start")
+    let a 1
+    match a:
+    1, 2:
+        print(a)
+    2:
+        print(a)
+    else:
+    end
+end"#;
+    let (code, stdout, stderr) = compare("backends_match_duplicate", duplicate)?;
+    assert_eq!((code, stdout.as_str()), (Some(1), ""));
+    assert!(stderr.starts_with("error: Unreachable match arm"), "{}", stderr);
+
+    return Ok(());
+}
@@ -0,0 +1,71 @@
+#![cfg(feature = "llvm")]
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Result};
+
+/// Feeds `script` to `modern_asm repl` and returns what it printed, without the prompts.
+fn repl(script: &str) -> Result<String> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_modern_asm"))
+        .arg("repl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().ok_or_else(|| anyhow!("No stdin"))?.write_all(script.as_bytes())?;
+    let output = child.wait_with_output()?;
+
+    return Ok(String::from_utf8(output.stdout)?.replace("... ", "").replace("> ", ""));
+}
+
+#[test]
+fn repl_definitions() -> Result<()> {
+    let script = r#"fn square n:i32 -> i32:
+    mul r n n
+    return r
+end
+let a square(7)
+a
+add a a 1
+let b square(3)
+print(a, b)
+"#;
+    assert_eq!(repl(script)?, "49\n50 9\n");
+
+    return Ok(());
+}
+
+#[test]
+fn repl_redefinition() -> Result<()> {
+    let script = r#"const LIMIT 3
+fn next n:i32 -> i32:
+    add r n 1
+    return r
+end
+let a next(LIMIT)
+fn next n:i32 -> i32:
+    add r n 10
+    return r
+end
+let b next(LIMIT)
+print(a, b)
+LIMIT
+"#;
+    assert_eq!(repl(script)?, "4 13\n3\n");
+
+    return Ok(());
+}
+
+#[test]
+fn repl_errors() -> Result<()> {
+    // a failing entry reports its error and leaves the earlier ones usable
+    let output = repl("let a 1\nprint(missing)\nadd a a true\nadd a a 2\na\n:quit\na\n")?;
+    let lines: Vec<&str> = output.lines().collect();
+
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("error: "));
+    assert!(lines[1].starts_with("error: "));
+    assert_eq!(lines[2], "3");
+
+    return Ok(());
+}